@@ -0,0 +1,26 @@
+use crate::error::Result;
+use crate::napm::Napm;
+use crate::util::human_size;
+
+pub fn run(napm: &Napm) -> Result<()> {
+    let usage = napm.pkg_cache_usage()?;
+
+    println!(
+        "Package files  : {} ({})",
+        usage.files,
+        human_size(usage.size as i64)
+    );
+    println!(
+        "  installed    : {} ({})",
+        usage.installed_files,
+        human_size(usage.installed_size as i64)
+    );
+    println!(
+        "  obsolete     : {} ({})",
+        usage.obsolete_files,
+        human_size(usage.obsolete_size as i64)
+    );
+    println!("Search cache   : {}", human_size(usage.sqlite_size as i64));
+
+    Ok(())
+}
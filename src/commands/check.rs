@@ -0,0 +1,46 @@
+use crate::ansi::*;
+use crate::error::{Error, Result};
+use crate::napm::Napm;
+
+pub fn run(napm: &Napm, pkg_names: &[&str], deep: bool) -> Result<()> {
+    let results = napm.check(pkg_names, deep)?;
+
+    let mut broken = 0;
+
+    for result in &results {
+        for file in &result.missing {
+            println!(
+                "{}: {ANSI_RED}missing{ANSI_RESET} {ANSI_BLUE}{file}{ANSI_RESET}",
+                result.pkg.formatted_name(false)
+            );
+        }
+
+        for (file, reason) in &result.altered {
+            println!(
+                "{}: {ANSI_YELLOW}{reason}{ANSI_RESET} {ANSI_BLUE}{file}{ANSI_RESET}",
+                result.pkg.formatted_name(false)
+            );
+        }
+
+        if !result.is_ok() {
+            broken += 1;
+        }
+
+        // a full system check only reports the broken packages
+        if !result.is_ok() || !pkg_names.is_empty() {
+            println!(
+                "{}: {} total files, {} missing, {} altered",
+                result.pkg.formatted_name(false),
+                result.total,
+                result.missing.len(),
+                result.altered.len()
+            );
+        }
+    }
+
+    if broken > 0 {
+        return Err(Error::CheckFailed(broken));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,21 @@
+use clap::Command;
+use clap_complete::{CompletionCandidate, Shell, generate};
+use std::ffi::OsStr;
+
+use crate::napm::Napm;
+
+pub fn run(shell: Shell, cmd: &mut Command) {
+    let name = cmd.get_name().to_string();
+    generate(shell, cmd, name, &mut std::io::stdout());
+}
+
+pub fn complete_pkg_names(current: &OsStr) -> Vec<CompletionCandidate> {
+    let Some(prefix) = current.to_str() else {
+        return Vec::new();
+    };
+
+    Napm::cached_pkg_names(prefix)
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
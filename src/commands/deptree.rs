@@ -0,0 +1,46 @@
+use crate::ansi::*;
+use crate::error::Result;
+use crate::napm::{Napm, deps::DepTreeNode};
+use crate::pkg::Pkg;
+
+fn node_label(node: &DepTreeNode) -> String {
+    let mut label = Pkg::format_name(&node.name, None);
+
+    if let Some(provides) = &node.provides {
+        label.push_str(&format!(" provides {provides}"));
+    }
+
+    if !node.resolved {
+        label.push_str(&format!(" {ANSI_RED}(not found){ANSI_RESET}"));
+    } else if node.seen {
+        label.push_str(&format!(" {ANSI_YELLOW}(already shown){ANSI_RESET}"));
+    }
+
+    label
+}
+
+fn print_children(node: &DepTreeNode, prefix: &str) {
+    for (i, child) in node.children.iter().enumerate() {
+        let last = i + 1 == node.children.len();
+
+        println!(
+            "{prefix}{}{}",
+            if last { "└─ " } else { "├─ " },
+            node_label(child)
+        );
+
+        print_children(
+            child,
+            &format!("{prefix}{}", if last { "   " } else { "│  " }),
+        );
+    }
+}
+
+pub fn run(napm: &Napm, pkg_name: &str, depth: Option<usize>, reverse: bool) -> Result<()> {
+    let tree = napm.deptree(pkg_name, depth, reverse)?;
+
+    println!("{}", node_label(&tree));
+    print_children(&tree, "");
+
+    Ok(())
+}
@@ -1,10 +1,31 @@
 use crate::error::Result;
 use crate::napm::Napm;
+use crate::pager::Pager;
+use crate::util::glob_match;
 
-pub fn run(napm: &mut Napm, pkg_name: &str, with_dirs: bool) -> Result<()> {
-    for f in napm.files(pkg_name, with_dirs)? {
-        println!("{}", f);
+pub fn run(
+    napm: &mut Napm,
+    pkg_name: &str,
+    with_dirs: bool,
+    repos: &[String],
+    sort: bool,
+    filter: Option<&str>,
+) -> Result<()> {
+    let mut files = napm.files(pkg_name, with_dirs, repos)?;
+
+    if let Some(pattern) = filter {
+        files.retain(|f| glob_match(pattern, f));
+    }
+
+    if sort {
+        files.sort();
+    }
+
+    let mut pager = Pager::new();
+
+    for f in files {
+        pager.line(f);
     }
 
-    Ok(())
+    pager.show()
 }
@@ -1,21 +1,44 @@
 use crate::ansi::*;
 use crate::error::{Error, Result};
 use crate::napm::Napm;
+use crate::pager::Pager;
+use crate::pkg::Pkg;
 
-pub fn run(napm: &mut Napm, path: String, exact: bool) -> Result<()> {
-    let results = napm.find(path, exact)?;
+pub fn run(napm: &mut Napm, path: String, exact: bool, installed: bool) -> Result<()> {
+    // a soname like libssl.so.3 is rarely the exact file name, the provides know it
+    let mut sonames = napm.find_packages_by_soname(&path)?;
+    let mut results = napm.find(path, exact)?;
 
-    if results.is_empty() {
+    if installed {
+        sonames = napm.installed_matches(sonames);
+        results = napm.installed_matches(results);
+    }
+
+    let name = |pkg: &Pkg| {
+        if installed {
+            format!("{}/{}", pkg.repo, pkg.formatted_name(true))
+        } else {
+            pkg.formatted_name(false)
+        }
+    };
+
+    if results.is_empty() && sonames.is_empty() {
         return Err(Error::NoResults);
     }
 
+    let mut pager = Pager::new();
+
+    for (pkg, provided) in sonames {
+        pager.line(format!(
+            "{}: provides {ANSI_GREEN}{}{ANSI_RESET}",
+            name(&pkg),
+            provided
+        ));
+    }
+
     for (pkg, path) in results {
-        println!(
-            "{}: {ANSI_BLUE}{}{ANSI_RESET}",
-            pkg.formatted_name(false),
-            path
-        );
+        pager.line(format!("{}: {ANSI_BLUE}{}{ANSI_RESET}", name(&pkg), path));
     }
 
-    Ok(())
+    pager.show()
 }
@@ -0,0 +1,39 @@
+use crate::ansi::*;
+use crate::error::{Error, Result};
+use crate::napm::Napm;
+use crate::pkg::Pkg;
+
+pub fn run(napm: &Napm, package: Option<&str>, limit: usize) -> Result<()> {
+    let transactions = napm.transactions(package, limit)?;
+
+    if transactions.is_empty() {
+        return Err(Error::NoResults);
+    }
+
+    for transaction in transactions {
+        println!(
+            "{ANSI_BOLD}{}{ANSI_RESET} {ANSI_YELLOW}{}{ANSI_RESET}",
+            transaction.time, transaction.operation
+        );
+
+        for item in transaction.items {
+            let versions = match (&item.old_version, &item.new_version) {
+                (Some(old), Some(new)) if old != new => {
+                    format!("{ANSI_MAGENTA}{old}{ANSI_RESET} => {ANSI_MAGENTA}{new}{ANSI_RESET}")
+                }
+                (Some(version), _) | (None, Some(version)) => {
+                    format!("{ANSI_MAGENTA}{version}{ANSI_RESET}")
+                }
+                (None, None) => String::new(),
+            };
+
+            println!(
+                " {:<9} {} {versions}",
+                item.action,
+                Pkg::format_name(&item.name, None)
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -1,12 +1,40 @@
 use crate::error::Result;
-use crate::napm::Napm;
+use crate::format::format_pkg;
+use crate::napm::{Napm, info::InfoSource};
+use crate::util::human_size;
 
-pub fn run(napm: &Napm, pkg: &str) -> Result<()> {
-    let p = napm.info(pkg)?;
+pub fn run(napm: &Napm, pkg: &str, repos: &[String], print_format: Option<&str>) -> Result<()> {
+    let info = napm.info(pkg, repos)?;
+    let p = &info.pkg;
+
+    if let Some(template) = print_format {
+        println!("{}", format_pkg(template, p));
+        return Ok(());
+    }
 
     println!("Name          : {}", p.name);
     println!("Version       : {}", p.version);
     println!("Description   : {}", p.desc);
+    if info.source == InfoSource::Local {
+        println!(
+            "Repository    : {}",
+            napm.origin_repo(&p.name)
+                .unwrap_or_else(|| "none (foreign)".to_string())
+        );
+    } else {
+        println!("Repository    : {}", p.repo);
+    }
+    println!(
+        "Depends On    : {}",
+        if info.depends.is_empty() {
+            "None".to_string()
+        } else {
+            info.depends.join("  ")
+        }
+    );
+    println!("Download Size : {}", human_size(info.csize as i64));
+    println!("Installed Size: {}", human_size(info.isize as i64));
+    println!("Source        : {}", info.source.as_str());
 
     // TODO: more info + link to `packages.neoarchlinux.org/package/{pkg}` once the website is created
 
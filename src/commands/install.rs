@@ -1,17 +1,80 @@
+use std::path::PathBuf;
+
 use crate::error::{Error, Result};
-use crate::log_error;
-use crate::napm::Napm;
+use crate::napm::{Napm, actions::InstallOptions};
+use crate::pkg::Pkg;
 use crate::util::{confirm, require_root};
+use crate::{log_error, log_info};
+
+// .pkg.tar.zst, .pkg.tar.xz or any other compression, a file in the cwd named like a package
+// (e.g. ./vim) is still a package name
+fn is_pkg_file(target: &str) -> bool {
+    target.rsplit_once(".pkg.tar.").is_some_and(|(stem, ext)| {
+        !stem.is_empty() && !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+fn is_pkg_url(target: &str) -> bool {
+    target.starts_with("http://") || target.starts_with("https://")
+}
+
+pub fn run(napm: &mut Napm, targets: &[&str], options: InstallOptions) -> Result<()> {
+    if !napm.dry_run() && !options.print_uris {
+        require_root()?;
+    }
+
+    let (pkg_urls, targets): (Vec<&str>, Vec<&str>) =
+        targets.iter().partition(|target| is_pkg_url(target));
+
+    let (pkg_files, pkg_names): (Vec<&str>, Vec<&str>) =
+        targets.into_iter().partition(|target| is_pkg_file(target));
+
+    let pkg_urls = pkg_urls.into_iter().map(String::from).collect::<Vec<_>>();
+
+    let pkg_files = pkg_files.into_iter().map(PathBuf::from).collect::<Vec<_>>();
+
+    // removing is not optional like a missing install target, the swap has to be complete
+    let to_remove = options
+        .remove
+        .iter()
+        .map(|name| napm.local_pkg(name))
+        .collect::<Result<Vec<_>>>()?;
+
+    // --asdeps and --asexplicit on installed packages only change their reason
+    let (pkg_names, reason_only) = match options.reason {
+        Some(reason) => {
+            let (installed, pkg_names): (Vec<&str>, Vec<&str>) = pkg_names
+                .into_iter()
+                .partition(|name| napm.local_pkg(name).is_ok());
+
+            if !installed.is_empty() {
+                napm.set_install_reason(
+                    &installed.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+                    reason,
+                )?;
+            }
+
+            (pkg_names, !installed.is_empty())
+        }
+        None => (pkg_names, false),
+    };
 
-pub fn run(napm: &mut Napm, pkg_names: &[&str]) -> Result<()> {
-    require_root()?;
+    if reason_only
+        && pkg_names.is_empty()
+        && pkg_files.is_empty()
+        && pkg_urls.is_empty()
+        && to_remove.is_empty()
+    {
+        return Ok(());
+    }
 
     let pkgs = {
         let pkgs_res = napm
-            .pkgs(pkg_names)
+            .pkgs(&pkg_names)
             .into_iter()
             .map(|pkg| {
                 if let Ok(ref p) = pkg
+                    && !options.needed
                     && let Ok(_) = napm.local_pkg(&p.name)
                 {
                     Err(Error::PackageAlreadyInstalled(p.name.clone()))
@@ -25,6 +88,8 @@ pub fn run(napm: &mut Napm, pkg_names: &[&str]) -> Result<()> {
             .iter()
             .filter_map(|pkg| pkg.as_ref().ok())
             .map(|pkg| pkg.formatted_name(false))
+            .chain(pkg_files.iter().map(|path| path.display().to_string()))
+            .chain(pkg_urls.iter().cloned())
             .collect();
 
         let invalid_errs = pkgs_res
@@ -35,6 +100,21 @@ pub fn run(napm: &mut Napm, pkg_names: &[&str]) -> Result<()> {
         if !invalid_errs.is_empty() {
             for invalid_err in invalid_errs {
                 log_error!("{invalid_err}");
+
+                if let Error::PackageNotFound(name) = invalid_err {
+                    let suggestions = napm.suggest_names(name);
+
+                    if !suggestions.is_empty() {
+                        log_info!(
+                            "Did you mean {}?",
+                            suggestions
+                                .iter()
+                                .map(|s| Pkg::format_name(s, None))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        );
+                    }
+                }
             }
 
             let confirm_message = format!(
@@ -47,7 +127,7 @@ pub fn run(napm: &mut Napm, pkg_names: &[&str]) -> Result<()> {
             }
         }
 
-        if display_names.is_empty() {
+        if display_names.is_empty() && to_remove.is_empty() {
             return Err(Error::NoValidPackage);
         }
 
@@ -57,5 +137,33 @@ pub fn run(napm: &mut Napm, pkg_names: &[&str]) -> Result<()> {
             .collect::<Vec<_>>()
     };
 
-    napm.install_pkgs(&pkgs)
+    napm.install_pkgs(&pkgs, &pkg_files, &pkg_urls, &to_remove, &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pkg_file_needs_the_package_suffix() {
+        for target in [
+            "foo-1.0-1-x86_64.pkg.tar.zst",
+            "./foo-1.0-1-any.pkg.tar.xz",
+            "/tmp/foo-1.0-1-x86_64.pkg.tar.gz",
+        ] {
+            assert!(is_pkg_file(target), "{target}");
+        }
+
+        for target in [
+            "foo",
+            "Cargo.toml",
+            "src",
+            "foo.pkg.tar",
+            "foo.pkg.tar.",
+            ".pkg.tar.zst",
+            "foo.pkg.tar.zst.sig",
+        ] {
+            assert!(!is_pkg_file(target), "{target}");
+        }
+    }
 }
@@ -1,10 +1,171 @@
+use std::collections::HashMap;
+
+use unicode_width::UnicodeWidthStr;
+
+use crate::ansi::*;
 use crate::error::Result;
-use crate::napm::Napm;
+use crate::format::format_pkg;
+use crate::napm::{Napm, actions::ListFilter};
+use crate::pager::Pager;
+use crate::pkg::Pkg;
+use crate::util::{offline, terminal_width, truncate_to_width};
+
+// names never get narrower than this, even on a tiny terminal
+const MIN_NAME_WIDTH: usize = 12;
+
+struct Row {
+    pkg: Pkg,
+    // the sync package, only with --upgradable
+    new: Option<Pkg>,
+}
+
+impl Row {
+    fn version(&self) -> String {
+        match &self.new {
+            Some(new) => format!("{} -> {}", self.pkg.version, new.version),
+            None => self.pkg.version.clone(),
+        }
+    }
+
+    fn colored_version(&self) -> String {
+        match &self.new {
+            Some(new) => format!(
+                "{ANSI_MAGENTA}{}{ANSI_RESET} -> {ANSI_GREEN}{}{ANSI_RESET}",
+                self.pkg.version, new.version
+            ),
+            None => format!("{ANSI_MAGENTA}{}{ANSI_RESET}", self.pkg.version),
+        }
+    }
+}
+
+fn aligned_lines(rows: &[Row], term_width: usize) -> Vec<String> {
+    let version_width = rows.iter().map(|r| r.version().width()).max().unwrap_or(0);
+    let name_width = rows
+        .iter()
+        .map(|r| r.pkg.name.width())
+        .max()
+        .unwrap_or(0)
+        .min(
+            term_width
+                .saturating_sub(version_width + 1)
+                .max(MIN_NAME_WIDTH),
+        );
+
+    rows.iter()
+        .map(|row| {
+            let name = truncate_to_width(&row.pkg.name, name_width);
+            let padding = " ".repeat(name_width - name.width());
+
+            format!(
+                "{ANSI_CYAN}{name}{ANSI_RESET}{padding} {}",
+                row.colored_version()
+            )
+        })
+        .collect()
+}
+
+fn lines(
+    rows: &[Row],
+    quiet: bool,
+    print_format: Option<&str>,
+    term_width: Option<usize>,
+) -> Vec<String> {
+    if let Some(template) = print_format {
+        rows.iter()
+            .map(|row| format_pkg(template, row.new.as_ref().unwrap_or(&row.pkg)))
+            .collect()
+    } else if quiet {
+        rows.iter().map(|row| row.pkg.name.clone()).collect()
+    } else if let Some(term_width) = term_width {
+        aligned_lines(rows, term_width)
+    } else {
+        // scripts get one plain `name version` per line
+        rows.iter()
+            .map(|row| format!("{} {}", row.pkg.name, row.version()))
+            .collect()
+    }
+}
 
-pub fn run(napm: &Napm) -> Result<()> {
-    for pkg in napm.list() {
-        println!("{}", pkg.formatted_name(true));
+// like checkupdates, the refresh goes to a private copy so the system databases
+// never get ahead of the installed packages
+fn upgradable_rows(napm: &mut Napm, pkgs: Vec<Pkg>, no_sync: bool) -> Result<Vec<Row>> {
+    if !no_sync && !offline() {
+        napm.use_private_db_path()?;
+        napm.sync_dbs(false)?;
     }
 
-    Ok(())
+    let mut upgrades: HashMap<String, Pkg> = napm
+        .upgradable()
+        .into_iter()
+        .map(|(old, new)| (old.name, new))
+        .collect();
+
+    Ok(pkgs
+        .into_iter()
+        .filter_map(|pkg| {
+            let new = upgrades.remove(&pkg.name)?;
+            Some(Row {
+                pkg,
+                new: Some(new),
+            })
+        })
+        .collect())
+}
+
+pub fn run(
+    napm: &mut Napm,
+    filter: ListFilter,
+    upgradable: Option<bool>,
+    quiet: bool,
+    print_format: Option<&str>,
+) -> Result<()> {
+    let pkgs = napm.list(filter);
+
+    // Some(no_sync) with --upgradable
+    let rows = match upgradable {
+        Some(no_sync) => upgradable_rows(napm, pkgs, no_sync)?,
+        None => pkgs.into_iter().map(|pkg| Row { pkg, new: None }).collect(),
+    };
+
+    let mut pager = Pager::new();
+
+    for line in lines(&rows, quiet, print_format, terminal_width()) {
+        pager.line(line);
+    }
+
+    pager.show()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napm::fixtures::Fixture;
+
+    fn quiet_lines(napm: &Napm, filter: ListFilter) -> Vec<String> {
+        let rows = napm
+            .list(filter)
+            .into_iter()
+            .map(|pkg| Row { pkg, new: None })
+            .collect::<Vec<_>>();
+
+        // quiet wins over the terminal layout
+        let mut lines = lines(&rows, true, None, Some(80));
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn quiet_prints_only_names() {
+        let mut fixture = Fixture::new("list-quiet");
+        fixture
+            .local("foo", "1.0-1")
+            .local("bar", "2.0-1")
+            .sync("core", &[("foo", "1.0-1", &[])]);
+        let napm = fixture.napm();
+
+        assert_eq!(quiet_lines(&napm, ListFilter::All), ["bar", "foo"]);
+        assert_eq!(quiet_lines(&napm, ListFilter::Explicit), ["bar", "foo"]);
+        assert_eq!(quiet_lines(&napm, ListFilter::Foreign), ["bar"]);
+        assert!(quiet_lines(&napm, ListFilter::Deps).is_empty());
+    }
 }
@@ -0,0 +1,18 @@
+use alpm::PackageReason;
+
+use crate::error::Result;
+use crate::napm::Napm;
+use crate::util::require_root;
+
+pub fn run(napm: &mut Napm, pkg_names: &[String], reason: PackageReason) -> Result<()> {
+    if !napm.dry_run() {
+        require_root()?;
+    }
+
+    // all or nothing, a half applied mark is easy to miss
+    for name in pkg_names {
+        napm.local_pkg(name)?;
+    }
+
+    napm.set_install_reason(pkg_names, reason)
+}
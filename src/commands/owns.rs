@@ -0,0 +1,21 @@
+use crate::ansi::*;
+use crate::error::{Error, Result};
+use crate::napm::Napm;
+
+pub fn run(napm: &Napm, path: &str) -> Result<()> {
+    let results = napm.owns(path)?;
+
+    if results.is_empty() {
+        return Err(Error::NoResults);
+    }
+
+    for (pkg, file) in results {
+        println!(
+            "{ANSI_BLUE}{}{ANSI_RESET} is owned by {}",
+            file,
+            pkg.formatted_name(true)
+        );
+    }
+
+    Ok(())
+}
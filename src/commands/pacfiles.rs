@@ -0,0 +1,58 @@
+use std::process::Command;
+
+use crate::ansi::*;
+use crate::error::{Error, Result};
+use crate::log_warn;
+use crate::napm::Napm;
+
+pub fn run(napm: &Napm, diff: bool) -> Result<()> {
+    let pacfiles = napm.pacfiles()?;
+
+    if pacfiles.is_empty() {
+        return Err(Error::NoResults);
+    }
+
+    let mut current_owner = None;
+
+    for pacfile in &pacfiles {
+        let owner = pacfile.owner.as_ref().map(|pkg| pkg.name.as_str());
+
+        if current_owner != Some(owner) {
+            match &pacfile.owner {
+                Some(pkg) => println!("{}:", pkg.formatted_name(true)),
+                None => println!("{ANSI_YELLOW}not owned by any package{ANSI_RESET}:"),
+            }
+            current_owner = Some(owner);
+        }
+
+        println!(" {ANSI_BLUE}{}{ANSI_RESET}", pacfile.path.display());
+
+        if diff {
+            let original = pacfile.on_disk(&pacfile.original);
+
+            if !original.exists() {
+                log_warn!(
+                    "{} does not exist, nothing to compare",
+                    pacfile.original.display()
+                );
+                continue;
+            }
+
+            // DIFFPROG is what pacdiff uses as well
+            let diff_prog = std::env::var("DIFFPROG").unwrap_or_else(|_| "diff -u".to_string());
+            let mut diff_args = diff_prog.split_whitespace();
+
+            let Some(program) = diff_args.next() else {
+                continue;
+            };
+
+            Command::new(program)
+                .args(diff_args)
+                .arg(&original)
+                .arg(pacfile.on_disk(&pacfile.path))
+                .status()?;
+        }
+    }
+
+    Ok(())
+}
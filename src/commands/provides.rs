@@ -0,0 +1,21 @@
+use crate::ansi::*;
+use crate::error::{Error, Result};
+use crate::napm::Napm;
+
+pub fn run(napm: &mut Napm, target: &str) -> Result<()> {
+    let results = napm.provides(target)?;
+
+    if results.is_empty() {
+        return Err(Error::NoResults);
+    }
+
+    for (pkg, provided) in results {
+        println!(
+            "{}: {ANSI_BLUE}{}{ANSI_RESET}",
+            pkg.formatted_name(true),
+            provided
+        );
+    }
+
+    Ok(())
+}
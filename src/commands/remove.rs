@@ -1,10 +1,25 @@
+use clap::ValueEnum;
+
 use crate::error::{Error, Result};
 use crate::log_error;
-use crate::napm::Napm;
+use crate::napm::{Napm, actions::RemoveOptions};
 use crate::util::{confirm, require_root};
 
-pub fn run(napm: &mut Napm, pkg_names: &[&str], deep: bool) -> Result<()> {
-    require_root()?;
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum RemovedFormat {
+    Json,
+    Null,
+}
+
+pub fn run(
+    napm: &mut Napm,
+    pkg_names: &[&str],
+    options: RemoveOptions,
+    output_removed: Option<RemovedFormat>,
+) -> Result<()> {
+    if !napm.dry_run() {
+        require_root()?;
+    }
 
     let pkgs = {
         let pkgs_res = napm.local_pkgs(pkg_names);
@@ -45,5 +60,61 @@ pub fn run(napm: &mut Napm, pkg_names: &[&str], deep: bool) -> Result<()> {
             .collect::<Vec<_>>()
     };
 
-    napm.remove_pkgs(&pkgs, deep)
+    let removed = napm
+        .remove_pkgs(&pkgs, &options)?
+        .into_iter()
+        .map(|pkg| pkg.name)
+        .collect::<Vec<_>>();
+
+    if let Some(format) = output_removed {
+        print!("{}", format_removed(&removed, format));
+    }
+
+    Ok(())
+}
+
+fn format_removed(removed: &[String], format: RemovedFormat) -> String {
+    match format {
+        RemovedFormat::Json => format!(
+            "{}\n",
+            serde_json::to_string(removed).expect("a list of strings is always valid json")
+        ),
+        RemovedFormat::Null => removed.iter().map(|name| format!("{name}\0")).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napm::fixtures::Fixture;
+
+    #[test]
+    fn output_lists_the_packages_cascade_adds() {
+        let mut fixture = Fixture::new("output-removed");
+        fixture
+            .local("bar", "1.0-1")
+            .local_with("foo", "1.0-1", &["bar"], &[]);
+        let mut napm = fixture.dry_run_napm();
+
+        let bar = napm.local_pkg("bar").unwrap();
+        let options = RemoveOptions {
+            deep: true,
+            unneeded: false,
+            no_save: false,
+        };
+
+        let mut removed = napm
+            .remove_pkgs(&[bar], &options)
+            .unwrap()
+            .into_iter()
+            .map(|pkg| pkg.name)
+            .collect::<Vec<_>>();
+        removed.sort();
+
+        assert_eq!(
+            format_removed(&removed, RemovedFormat::Json),
+            "[\"bar\",\"foo\"]\n"
+        );
+        assert_eq!(format_removed(&removed, RemovedFormat::Null), "bar\0foo\0");
+    }
 }
@@ -1,9 +1,93 @@
+use std::cmp::Ordering;
+
 use crate::ansi::*;
-use crate::error::Result;
-use crate::napm::Napm;
+use crate::commands::install;
+use crate::error::{Error, Result};
+use crate::format::format_pkg;
+use crate::history;
+use crate::napm::{
+    Napm,
+    actions::InstallOptions,
+    cache::{CacheKind, SearchOptions, suppress_stale_cache_warning},
+};
+use crate::pager::Pager;
+use crate::pkg::Pkg;
+use crate::util::{offline, require_existing_cache, require_root, run_cache_update, select};
+use crate::{log_info, log_warn};
+
+pub struct SearchOutput<'a> {
+    pub num_results: Option<u32>,
+    pub select_to_install: bool,
+    pub print_format: Option<&'a str>,
+}
+
+pub struct SearchHistory {
+    pub repeat_last: bool,
+    pub list: bool,
+    pub record: bool,
+}
+
+pub fn run(
+    napm: &mut Napm,
+    mut search_terms: Vec<String>,
+    no_sync: bool,
+    output: SearchOutput,
+    search_history: SearchHistory,
+    options: SearchOptions,
+) -> Result<()> {
+    let SearchOutput {
+        num_results,
+        select_to_install,
+        print_format,
+    } = output;
+
+    if search_history.list {
+        for (i, query) in history::entries().iter().enumerate() {
+            println!(
+                " {ANSI_YELLOW}[{ANSI_BOLD}{}{ANSI_RESET}{ANSI_YELLOW}]{ANSI_RESET} {query}",
+                i + 1
+            );
+        }
+
+        return Ok(());
+    }
+
+    if search_history.repeat_last {
+        let last = history::last().ok_or(Error::NoSearchHistory)?;
+        log_info!("Repeating the search for {ANSI_YELLOW}{last}{ANSI_RESET}");
+        search_terms = last.split_whitespace().map(str::to_string).collect();
+    }
+
+    if search_history.record
+        && !search_terms.is_empty()
+        && napm.search_history_enabled()
+        && let Err(e) = history::record(&search_terms.join(" "))
+    {
+        log_warn!("Could not record the search history: {e}");
+    }
+
+    // before searching, escalating later would rerun the whole search
+    if select_to_install && !napm.dry_run() {
+        require_root()?;
+    }
 
-pub fn run(napm: &Napm, search_terms: Vec<String>, num_results: Option<u32>) -> Result<()> {
-    let results = napm.search(search_terms)?;
+    if no_sync || offline() {
+        require_existing_cache()?;
+        suppress_stale_cache_warning();
+    } else if !Napm::cache_is_fresh() {
+        run_cache_update(
+            "The package cache is missing or outdated and will be refreshed before searching",
+            CacheKind::DescOnly,
+        )?;
+    }
+
+    let query_words = search_terms
+        .iter()
+        .flat_map(|term| term.split_whitespace())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    let results = napm.search(search_terms, &options)?;
 
     let results = if let Some(n) = num_results {
         results.iter().take(n as usize).collect::<Vec<_>>()
@@ -11,14 +95,158 @@ pub fn run(napm: &Napm, search_terms: Vec<String>, num_results: Option<u32>) ->
         results.iter().collect::<Vec<_>>()
     };
 
-    for (i, pkg) in results.iter().enumerate().rev() {
-        println!(
-            " {ANSI_RED}-{ANSI_RESET} {ANSI_YELLOW}[{ANSI_BOLD}{}{ANSI_RESET}{ANSI_YELLOW}]{ANSI_RESET} {} {}",
+    // best match first, unlike the interactive listing which ends with it
+    let mut pager = Pager::new();
+
+    if let Some(template) = print_format {
+        for (_, pkg) in &results {
+            pager.line(format_pkg(template, pkg));
+        }
+
+        return pager.show();
+    }
+
+    for (i, (score, pkg)) in results.iter().enumerate().rev() {
+        let (name, installed) = match napm.local_pkg(&pkg.name) {
+            Ok(local) => match alpm::vercmp(local.version.as_str(), pkg.version.as_str()) {
+                Ordering::Less => (
+                    format!(
+                        "{}-{ANSI_YELLOW}{}{ANSI_RESET}",
+                        Pkg::format_name(&pkg.name, None),
+                        pkg.version
+                    ),
+                    format!(" {ANSI_YELLOW}[installed: {}]{ANSI_RESET}", local.version),
+                ),
+                Ordering::Equal => (
+                    pkg.formatted_name(true),
+                    format!(" {ANSI_GREEN}[installed]{ANSI_RESET}"),
+                ),
+                Ordering::Greater => (
+                    pkg.formatted_name(true),
+                    format!(" {ANSI_GREEN}[installed: {}]{ANSI_RESET}", local.version),
+                ),
+            },
+            Err(_) => (pkg.formatted_name(true), String::new()),
+        };
+
+        pager.line(format!(
+            " {ANSI_RED}-{ANSI_RESET} {ANSI_YELLOW}[{ANSI_BOLD}{}{ANSI_RESET}{ANSI_YELLOW}]{ANSI_RESET} {name}{installed} {ANSI_DIM}({score:.2}){ANSI_RESET} {}",
             i + 1,
-            pkg.formatted_name(true),
-            pkg.desc,
+            highlight(&pkg.desc, &query_words),
+        ));
+    }
+
+    pager.show()?;
+
+    if !select_to_install || results.is_empty() {
+        return Ok(());
+    }
+
+    let selection = select("Enter the numbers of packages to install", results.len())?;
+
+    if selection.is_empty() {
+        return Err(Error::NothingToDo);
+    }
+
+    let names = selection
+        .iter()
+        .map(|n| results[n - 1].1.name.as_str())
+        .collect::<Vec<_>>();
+
+    install::run(napm, &names, InstallOptions::default())
+}
+
+// works on chars, byte offsets of the lowercased text do not match the original
+fn highlight(text: &str, words: &[String]) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let lower = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect::<Vec<_>>();
+
+    let mut marked = vec![false; chars.len()];
+
+    for word in words {
+        let word = word
+            .chars()
+            .map(|c| c.to_lowercase().next().unwrap_or(c))
+            .collect::<Vec<_>>();
+        if word.is_empty() || word.len() > lower.len() {
+            continue;
+        }
+
+        for start in 0..=lower.len() - word.len() {
+            if lower[start..start + word.len()] == word[..] {
+                marked[start..start + word.len()].fill(true);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    let mut in_match = false;
+
+    for (c, m) in chars.iter().zip(&marked) {
+        if *m != in_match {
+            out.push_str(if *m { ANSI_UNDERLINE } else { ANSI_RESET });
+            in_match = *m;
+        }
+        out.push(*c);
+    }
+
+    if in_match {
+        out.push_str(ANSI_RESET);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    fn marked(s: &str) -> String {
+        format!("{ANSI_UNDERLINE}{s}{ANSI_RESET}")
+    }
+
+    #[test]
+    fn highlight_marks_every_occurrence_case_insensitively() {
+        assert_eq!(
+            highlight("Vim and vim", &words(&["vim"])),
+            format!("{} and {}", marked("Vim"), marked("vim"))
         );
     }
 
-    Ok(())
+    #[test]
+    fn highlight_merges_overlapping_words() {
+        assert_eq!(
+            highlight("neovim editor", &words(&["neo", "ovim"])),
+            format!("{} editor", marked("neovim"))
+        );
+        assert_eq!(
+            highlight("text editor", &words(&["text", "editor"])),
+            format!("{} {}", marked("text"), marked("editor"))
+        );
+    }
+
+    #[test]
+    fn highlight_keeps_non_ascii_text_intact() {
+        // the lowercase form of İ is longer in bytes, offsets must not shift
+        assert_eq!(
+            highlight("İstanbul Straße", &words(&["straße"])),
+            format!("İstanbul {}", marked("Straße"))
+        );
+    }
+
+    #[test]
+    fn highlight_without_matches_is_unchanged() {
+        assert_eq!(
+            highlight("plain text", &words(&["", "missing"])),
+            "plain text"
+        );
+        assert_eq!(highlight("", &words(&["a"])), "");
+    }
 }
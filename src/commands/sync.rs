@@ -0,0 +1,44 @@
+use crate::error::{Error, Result};
+use crate::log_info;
+use crate::napm::{Napm, cache::CacheKind};
+use crate::util::{is_root, offline, require_root};
+
+pub fn run(napm: &mut Napm, files: bool, cache: bool, force: bool) -> Result<()> {
+    if offline() {
+        return Err(Error::Offline("Refreshing the databases".to_string()));
+    }
+
+    if !is_root() && !files && !cache {
+        let db_path = napm.use_private_db_path()?;
+
+        log_info!(
+            "Not running as root, refreshing a private copy of the databases in {}",
+            db_path.display()
+        );
+
+        napm.sync_dbs(force)?;
+
+        match napm.upgradable().len() {
+            0 => log_info!("The system is up to date"),
+            n => log_info!(
+                "{ANSI_YELLOW}{n}{ANSI_RESET} package(s) can be upgraded, run {ANSI_YELLOW}napm upgrade{ANSI_RESET}"
+            ),
+        }
+
+        return Ok(());
+    }
+
+    require_root()?;
+
+    if files {
+        napm.sync_files(force)?;
+        napm.update_cache(force, CacheKind::Full)?;
+    } else if cache {
+        napm.sync_dbs(force)?;
+        napm.update_cache(force, CacheKind::DescOnly)?;
+    } else {
+        napm.sync_dbs(force)?;
+    }
+
+    Ok(())
+}
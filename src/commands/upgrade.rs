@@ -1,9 +1,21 @@
-use crate::error::Result;
-use crate::napm::Napm;
-use crate::util::require_root;
+use crate::error::{Error, Result};
+use crate::napm::{Napm, actions::UpgradeOptions};
+use crate::util::{offline, require_root};
 
-pub fn run(napm: &mut Napm) -> Result<()> {
-    require_root()?;
+pub fn run(napm: &mut Napm, options: UpgradeOptions) -> Result<()> {
+    if offline() {
+        return Err(Error::Offline("Upgrading the system".to_string()));
+    }
 
-    napm.upgrade()
+    // printing the URIs resolves the same transaction as a dry run, refreshing the system sync
+    // databases for it would write and leave a partial upgrade behind, even as root
+    if !napm.dry_run() && !options.print_uris {
+        require_root()?;
+    } else {
+        napm.use_private_db_path()?;
+    }
+
+    napm.sync_dbs(false)?;
+
+    napm.upgrade(&options)
 }
@@ -0,0 +1,25 @@
+use crate::ansi::*;
+use crate::error::Result;
+use crate::napm::{Napm, deps::InstallReason};
+use crate::pkg::Pkg;
+
+pub fn run(napm: &Napm, pkg_name: &str) -> Result<()> {
+    let name = Pkg::format_name(pkg_name, None);
+
+    match napm.why(pkg_name)? {
+        InstallReason::Explicit => println!("{name} was explicitly installed"),
+        InstallReason::Dependency(chain) => println!(
+            "{name} is required by {}",
+            chain
+                .iter()
+                .map(|n| Pkg::format_name(n, None))
+                .collect::<Vec<_>>()
+                .join(&format!(" {ANSI_YELLOW}->{ANSI_RESET} "))
+        ),
+        InstallReason::Orphan => println!(
+            "{name} was installed as a dependency, but {ANSI_YELLOW}no explicitly installed package requires it{ANSI_RESET}"
+        ),
+    }
+
+    Ok(())
+}
@@ -29,9 +29,6 @@ pub enum Error {
     #[error("No supported privilege escalation tool found ({}).", crate::util::PE_TOOLS.join(", "))]
     NoPETool,
 
-    #[error("No supported shell found ({}).", crate::util::SHELLS.join(", "))]
-    NoShell,
-
     #[error("User denied required privilege escalation, please run {ANSI_YELLOW}{0}{ANSI_RESET}")]
     DeniedPE(String),
 
@@ -104,9 +101,55 @@ pub enum Error {
     #[error("Package {ANSI_YELLOW}{0}{ANSI_RESET} not found")]
     PackageNotFound(String),
 
+    #[error("No version of {ANSI_YELLOW}{0}{ANSI_RESET} satisfies {ANSI_YELLOW}{1}{ANSI_RESET}")]
+    NoSatisfyingVersion(String, String),
+
     #[error("Package {ANSI_YELLOW}{0}{ANSI_RESET} is not installed or does not exist")]
     PackageNotInLocalDb(String),
 
+    #[error(
+        "Package {ANSI_YELLOW}{0}{ANSI_RESET} not found in the databases, run {ANSI_YELLOW}napm sync{ANSI_RESET} to refresh them"
+    )]
+    PackageNotInSyncDb(String),
+
+    #[error(
+        "Package {ANSI_YELLOW}{0}{ANSI_RESET} is ignored (IgnorePkg or IgnoreGroup in pacman.conf)"
+    )]
+    PackageIgnored(String),
+
+    #[error("Repository {ANSI_YELLOW}{0}{ANSI_RESET} is not configured")]
+    UnknownRepo(String),
+
+    #[error(
+        "Hook {ANSI_YELLOW}{0}{ANSI_RESET} failed and aborted the transaction, nothing was changed{1}"
+    )]
+    HookFailed(String, String),
+
+    #[error("Root {ANSI_YELLOW}{0}{ANSI_RESET} cannot be used: {1}")]
+    InvalidRoot(String, String),
+
+    #[error("User {ANSI_YELLOW}{0}{ANSI_RESET} does not exist")]
+    UnknownUser(String),
+
+    #[error("Failed to load package file {ANSI_YELLOW}{0}{ANSI_RESET}: {1}")]
+    PackageFileLoad(String, alpm::Error),
+
+    #[error("Failed to download package files: {0}")]
+    PackageDownload(alpm::Error),
+
+    #[error("Network error: {0}, check the connection or the mirrors in the mirrorlist")]
+    Network(alpm::Error),
+
+    #[error(
+        "Signature verification failed: {0}, the keyring may be outdated, upgrade the {ANSI_YELLOW}*-keyring{ANSI_RESET} packages first (see RefreshKeyringBeforeUpgrade in napm.conf)"
+    )]
+    Keyring(alpm::Error),
+
+    #[error(
+        "Failed to read an archive: {0}, the download may be corrupt, remove it from the package cache and retry"
+    )]
+    CorruptArchive(alpm::Error),
+
     #[error("Failed to parse `SigLevel = {0}` in the config")]
     SigLevelParse(String),
 
@@ -122,17 +165,83 @@ pub enum Error {
     #[error("Cache database error: {0}")]
     CacheDatabaseError(rusqlite::Error),
 
+    #[error(
+        "The package cache does not exist yet, run {ANSI_YELLOW}napm sync --files{ANSI_RESET} first"
+    )]
+    CacheMissing,
+
     #[error("System upgrade reqiuired")]
     UpgradeRequired,
 
     #[error("No init system detected")]
     NoInitSystem,
+
+    #[error("Failed to install the interrupt signal handler")]
+    SignalHandler,
+
+    #[error("{0} packages have missing or altered files")]
+    CheckFailed(usize),
+
+    #[error("{0} is not cached, cannot proceed offline")]
+    NotCachedOffline(String),
+
+    #[error("{0} needs the network, which is not possible with --offline")]
+    Offline(String),
+
+    #[error("No previous search to repeat")]
+    NoSearchHistory,
+
+    #[error("Invalid {file} line {0}: {1}", file = crate::napm::config::NAPM_CONFIG_FILE)]
+    NapmConfigInvalid(usize, String),
 }
 
+// exit codes scripts can rely on, clap exits with 2 on invalid arguments as well
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_GENERIC: i32 = 1;
+pub const EXIT_NOT_FOUND: i32 = 2;
+pub const EXIT_NETWORK: i32 = 3;
+pub const EXIT_PERMISSION: i32 = 4;
+
 impl Error {
+    // nothing to do is a success, `napm upgrade && ...` must not fail on an up to date system
+    pub fn exit_code(&self) -> i32 {
+        use Error as E;
+
+        match self {
+            E::NothingToDo => EXIT_SUCCESS,
+
+            E::ConfigParse
+            | E::NapmConfigInvalid(..)
+            | E::SigLevelParse(_)
+            | E::NoResults
+            | E::NoValidPackage
+            | E::PackageNotFound(_)
+            | E::NoSatisfyingVersion(..)
+            | E::PackageNotInLocalDb(_)
+            | E::PackageNotInSyncDb(_)
+            | E::PackageAlreadyInstalled(_)
+            | E::UnknownRepo(_)
+            | E::UnknownUser(_)
+            | E::InvalidRoot(..)
+            | E::CacheMissing
+            | E::NoSearchHistory => EXIT_NOT_FOUND,
+
+            E::PackageDownload(_)
+            | E::Network(_)
+            | E::DbRefresh
+            | E::Update
+            | E::NotCachedOffline(_)
+            | E::Offline(_) => EXIT_NETWORK,
+
+            E::BadPerms | E::NoPETool | E::DeniedPE(_) => EXIT_PERMISSION,
+
+            _ => EXIT_GENERIC,
+        }
+    }
+
     pub fn die(&self) {
         crate::log_fatal!("{}", self);
-        std::process::exit(1);
+        std::process::exit(self.exit_code());
     }
 }
 
@@ -155,3 +264,14 @@ impl From<rusqlite::Error> for Error {
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrade_required_is_not_a_network_failure() {
+        assert_eq!(Error::UpgradeRequired.exit_code(), EXIT_GENERIC);
+        assert_eq!(Error::DbRefresh.exit_code(), EXIT_NETWORK);
+    }
+}
@@ -0,0 +1,32 @@
+use crate::pkg::Pkg;
+
+pub const PRINT_FORMAT_HELP: &str = "Print each package using this template: %n name, %v version, %r repository, %d description, %% a literal %; anything else is printed as is";
+
+// unknown tokens are kept literally, a typo shows up in the output instead of failing a script
+pub fn format_pkg(template: &str, pkg: &Pkg) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.peek() {
+            Some('n') => out.push_str(&pkg.name),
+            Some('v') => out.push_str(&pkg.version),
+            Some('r') => out.push_str(&pkg.repo),
+            Some('d') => out.push_str(&pkg.desc),
+            Some('%') => out.push('%'),
+            _ => {
+                out.push('%');
+                continue;
+            }
+        }
+
+        chars.next();
+    }
+
+    out
+}
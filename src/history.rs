@@ -0,0 +1,120 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::error::Result;
+
+const MAX_HISTORY: usize = 100;
+
+fn history_file() -> Option<PathBuf> {
+    let data_home = env::var("XDG_DATA_HOME")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var("HOME")
+                .ok()
+                .map(|home| PathBuf::from(home).join(".local/share"))
+        })?;
+
+    Some(data_home.join("napm").join("search_history"))
+}
+
+fn entries_in(path: &Path) -> Vec<String> {
+    fs::read_to_string(path)
+        .map(|content| content.lines().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+fn last_in(path: &Path) -> Option<String> {
+    entries_in(path).pop()
+}
+
+fn record_in(path: &Path, query: &str) -> Result<()> {
+    let mut entries = entries_in(path);
+
+    // repeating the same search does not push older queries out
+    entries.retain(|entry| entry != query);
+    entries.push(query.to_string());
+
+    let skip = entries.len().saturating_sub(MAX_HISTORY);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    fs::write(path, entries[skip..].join("\n") + "\n")?;
+
+    Ok(())
+}
+
+pub fn entries() -> Vec<String> {
+    history_file()
+        .map(|path| entries_in(&path))
+        .unwrap_or_default()
+}
+
+pub fn last() -> Option<String> {
+    history_file().and_then(|path| last_in(&path))
+}
+
+pub fn record(query: &str) -> Result<()> {
+    match history_file() {
+        Some(path) => record_in(&path, query),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_history(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("napm-history-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("napm").join("search_history")
+    }
+
+    #[test]
+    fn record_moves_repeated_queries_to_the_end() {
+        let path = temp_history("dedup");
+
+        for query in ["vim", "firefox", "vim"] {
+            record_in(&path, query).unwrap();
+        }
+
+        assert_eq!(entries_in(&path), ["firefox", "vim"]);
+        // what --repeat-last runs
+        assert_eq!(last_in(&path).as_deref(), Some("vim"));
+
+        fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn record_keeps_at_most_max_history() {
+        let path = temp_history("bounded");
+
+        for i in 0..MAX_HISTORY + 5 {
+            record_in(&path, &format!("query {i}")).unwrap();
+        }
+
+        let entries = entries_in(&path);
+        assert_eq!(entries.len(), MAX_HISTORY);
+        assert_eq!(entries[0], "query 5");
+        assert_eq!(
+            entries.last().map(String::as_str),
+            Some(&*format!("query {}", MAX_HISTORY + 4))
+        );
+
+        fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn missing_history_is_empty() {
+        let path = temp_history("missing");
+
+        assert!(entries_in(&path).is_empty());
+        assert_eq!(last_in(&path), None);
+    }
+}
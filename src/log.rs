@@ -1,8 +1,120 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const NAPM_LOG_FILE: &str = "/var/log/napm.log";
+const MAX_LOG_SIZE: u64 = 8 * 1024 * 1024;
+
+static LOG_FILE: OnceLock<Option<Mutex<File>>> = OnceLock::new();
+
+pub const VERBOSITY_QUIET: u8 = 0;
+pub const VERBOSITY_NORMAL: u8 = 1;
+pub const VERBOSITY_DEBUG: u8 = 2;
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(VERBOSITY_NORMAL);
+
+pub fn set_verbosity(verbosity: u8) {
+    VERBOSITY.store(verbosity, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> u8 {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+// only root writes the log, the previous one is kept as napm.log.old once it gets too big
+fn log_file() -> Option<&'static Mutex<File>> {
+    LOG_FILE
+        .get_or_init(|| {
+            if !nix::unistd::Uid::effective().is_root() {
+                return None;
+            }
+
+            if fs::metadata(NAPM_LOG_FILE).is_ok_and(|m| m.len() > MAX_LOG_SIZE) {
+                let _ = fs::rename(NAPM_LOG_FILE, format!("{NAPM_LOG_FILE}.old"));
+            }
+
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(NAPM_LOG_FILE)
+                .ok()
+                .map(Mutex::new)
+        })
+        .as_ref()
+}
+
+pub fn write_log_file(level: &str, msg: &str) {
+    let Some(file) = log_file() else {
+        return;
+    };
+
+    let line = format!(
+        "[{}] [{level}] {}\n",
+        timestamp(),
+        strip_ansi(msg.trim_end())
+    );
+
+    if let Ok(mut file) = file.lock() {
+        let _ = file.write_all(line.as_bytes());
+    }
+}
+
+fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+// UTC, days to civil date from http://howardhinnant.github.io/date_algorithms.html
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
 #[macro_export]
 macro_rules! log_debug {
     ($($arg:tt)*) => {{
         use $crate::ansi::*;
-        eprintln!("{ANSI_BLUE}{ANSI_BOLD}D{ANSI_RESET}: {}", format!($($arg)*));
+        if $crate::log::verbosity() >= $crate::log::VERBOSITY_DEBUG {
+            let msg = format!($($arg)*);
+            eprintln!("{ANSI_BLUE}{ANSI_BOLD}D{ANSI_RESET}: {}", msg);
+            $crate::log::write_log_file("DEBUG", &msg);
+        }
     }};
 }
 
@@ -10,7 +122,11 @@ macro_rules! log_debug {
 macro_rules! log_info {
     ($($arg:tt)*) => {{
         use $crate::ansi::*;
-        eprintln!("{ANSI_GREEN}{ANSI_BOLD}I{ANSI_RESET}: {}", format!($($arg)*));
+        let msg = format!($($arg)*);
+        if $crate::log::verbosity() >= $crate::log::VERBOSITY_NORMAL {
+            eprintln!("{ANSI_GREEN}{ANSI_BOLD}I{ANSI_RESET}: {}", msg);
+        }
+        $crate::log::write_log_file("INFO", &msg);
     }};
 }
 
@@ -18,7 +134,9 @@ macro_rules! log_info {
 macro_rules! log_warn {
     ($($arg:tt)*) => {{
         use $crate::ansi::*;
-        eprintln!("{ANSI_YELLOW}{ANSI_BOLD}W{ANSI_RESET}: {}", format!($($arg)*));
+        let msg = format!($($arg)*);
+        eprintln!("{ANSI_YELLOW}{ANSI_BOLD}W{ANSI_RESET}: {}", msg);
+        $crate::log::write_log_file("WARN", &msg);
     }};
 }
 
@@ -26,7 +144,9 @@ macro_rules! log_warn {
 macro_rules! log_error {
     ($($arg:tt)*) => {{
         use $crate::ansi::*;
-        eprintln!("{ANSI_RED}{ANSI_BOLD}E{ANSI_RESET}: {}", format!($($arg)*));
+        let msg = format!($($arg)*);
+        eprintln!("{ANSI_RED}{ANSI_BOLD}E{ANSI_RESET}: {}", msg);
+        $crate::log::write_log_file("ERROR", &msg);
     }};
 }
 
@@ -34,7 +154,9 @@ macro_rules! log_error {
 macro_rules! log_fatal {
     ($($arg:tt)*) => {{
         use $crate::ansi::*;
-        eprintln!("{ANSI_MAGENTA}{ANSI_BOLD}F{ANSI_RESET}: {}", format!($($arg)*));
+        let msg = format!($($arg)*);
+        eprintln!("{ANSI_MAGENTA}{ANSI_BOLD}F{ANSI_RESET}: {}", msg);
+        $crate::log::write_log_file("FATAL", &msg);
     }};
 }
 
@@ -57,6 +179,8 @@ macro_rules! log_action_required {
 macro_rules! log_repair {
     ($($arg:tt)*) => {{
         use $crate::ansi::*;
-        eprintln!("[{ANSI_CYAN}AUTO REPAIR{ANSI_RESET}] {}", format!($($arg)*));
+        let msg = format!($($arg)*);
+        eprintln!("[{ANSI_CYAN}AUTO REPAIR{ANSI_RESET}] {}", msg);
+        $crate::log::write_log_file("REPAIR", &msg);
     }};
 }
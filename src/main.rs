@@ -1,47 +1,227 @@
-use clap::{Parser, Subcommand};
+use alpm::PackageReason;
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::{ArgValueCompleter, CompleteEnv, Shell};
 
 pub mod ansi;
 pub mod error;
+pub mod format;
+pub mod history;
 pub mod log;
 pub mod napm;
+pub mod pager;
 pub mod pkg;
 pub mod util;
 
 pub mod commands {
+    pub mod cache;
+    pub mod check;
+    pub mod completions;
+    pub mod deptree;
     pub mod files;
     pub mod find;
+    pub mod history;
     pub mod info;
     pub mod install;
     pub mod list;
+    pub mod mark;
+    pub mod owns;
+    pub mod pacfiles;
+    pub mod provides;
     pub mod remove;
     pub mod search;
-    pub mod update;
+    pub mod sync;
     pub mod upgrade;
+    pub mod why;
 }
 
+use commands::remove::RemovedFormat;
+use commands::search::{SearchHistory, SearchOutput};
 use error::{Error, Result};
-use napm::Napm;
+use napm::{
+    Napm, NapmOptions,
+    actions::{InstallOptions, ListFilter, RemoveOptions, UpgradeOptions},
+    cache::{SearchField, SearchOptions},
+};
 
 #[derive(Parser)]
 #[command(name = "napm")]
 #[command(about = "napm - NeoArch Package Manager")]
+#[command(
+    after_long_help = "Exit codes:\n  0  success, also when there was nothing to do\n  1  generic error\n  2  invalid arguments, package, repository or resource not found\n  3  download or database refresh failed\n  4  missing permissions or privilege escalation denied"
+)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        help = "Do not ask for confirmation, assume the default answer"
+    )]
+    noconfirm: bool,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        help = "Resolve the transaction and show what would be done, without changing anything"
+    )]
+    dry_run: bool,
+
+    #[arg(
+        long,
+        short,
+        global = true,
+        env = "NAPM_ROOT",
+        value_name = "PATH",
+        help = "Operate on an alternate installation root instead of /"
+    )]
+    root: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        conflicts_with = "root",
+        help = "Operate on a complete system at PATH, using its own pacman.conf"
+    )]
+    sysroot: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        help = "Never use the network, only work with what is already cached"
+    )]
+    offline: bool,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        help = "Never page long output through $PAGER"
+    )]
+    no_pager: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Download this many files at once, overrides ParallelDownloads"
+    )]
+    parallel_downloads: Option<u32>,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        help = "Keep waiting on stalled downloads instead of moving on to the next mirror"
+    )]
+    disable_download_timeout: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "USER",
+        help = "Download packages as this unprivileged user, overrides DownloadUser"
+    )]
+    download_user: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        default_value_t = false,
+        help = "Report errors as they happen instead of trying to repair them, see NoAutoRepair"
+    )]
+    no_repair: bool,
+
+    #[arg(
+        long,
+        short,
+        global = true,
+        action = clap::ArgAction::Count,
+        help = "Show more output, including debug messages"
+    )]
+    verbose: u8,
+
+    #[arg(
+        long,
+        short,
+        global = true,
+        default_value_t = false,
+        conflicts_with = "verbose",
+        help = "Only show warnings and errors (and only package names for list)"
+    )]
+    quiet: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    #[command(about = "Show how much space the package cache and the search cache take")]
+    Cache,
+
+    #[command(about = "Check the files of installed packages, all of them if none are given")]
+    Check {
+        packages: Vec<String>,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Also verify permissions, sizes, modification times and checksums"
+        )]
+        files: bool,
+    },
+
+    #[command(
+        about = "Print a completion script for bash, zsh, fish, elvish or powershell",
+        long_about = "Print a completion script for bash, zsh, fish, elvish or powershell.\n\nFor package name completion from the cache, use the dynamic completions instead, e.g. `source <(COMPLETE=bash napm)`"
+    )]
+    Completions { shell: Shell },
+
+    #[command(about = "Show the dependency tree of a package")]
+    Deptree {
+        #[arg(add = ArgValueCompleter::new(commands::completions::complete_pkg_names))]
+        package: String,
+
+        #[arg(long, short, help = "Only descend this many levels")]
+        depth: Option<usize>,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Show the packages requiring it instead of its dependencies"
+        )]
+        reverse: bool,
+    },
+
     #[command(about = "List the files of a package")]
     Files {
+        #[arg(add = ArgValueCompleter::new(commands::completions::complete_pkg_names))]
         package: String,
 
         #[arg(long, short, default_value_t = false, help = "Show directories too")]
         dirs: bool,
+
+        #[arg(long, value_name = "REPO", help = "Only consider this repository")]
+        repo: Vec<String>,
+
+        #[arg(long, short, default_value_t = false, help = "Sort the paths")]
+        sort: bool,
+
+        #[arg(
+            long,
+            short,
+            value_name = "GLOB",
+            help = "Only show paths matching this pattern, `*` also matches `/` (e.g. '*/bin/*')"
+        )]
+        filter: Option<String>,
     },
 
-    #[command(about = "Find packages that contain a specific file")]
+    #[command(about = "Find packages that contain a specific file or provide a library soname")]
     Find {
+        #[arg(help = "A file path, or a soname like libssl.so.3")]
         path: String,
 
         #[arg(
@@ -50,16 +230,197 @@ enum Commands {
             help = "Only match exact paths (e.g. /bin/sudo)"
         )]
         exact: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Only show installed packages, with the repository they were installed from"
+        )]
+        installed: bool,
+    },
+
+    #[command(about = "Show past transactions")]
+    History {
+        #[arg(long, help = "Only show the version timeline of this package")]
+        package: Option<String>,
+
+        #[arg(
+            long,
+            short = 'n',
+            default_value_t = 20,
+            help = "Number of transactions to show"
+        )]
+        limit: usize,
     },
 
     #[command(about = "Show package information")]
-    Info { package: String },
+    Info {
+        #[arg(add = ArgValueCompleter::new(commands::completions::complete_pkg_names))]
+        package: String,
+
+        #[arg(long, value_name = "REPO", help = "Only consider this repository")]
+        repo: Vec<String>,
 
-    #[command(about = "Install packages")]
-    Install { packages: Vec<String> },
+        #[arg(long, alias = "format", value_name = "FORMAT", help = format::PRINT_FORMAT_HELP)]
+        print_format: Option<String>,
+    },
+
+    #[command(about = "Install packages from the repositories, local package files or URLs")]
+    Install {
+        #[arg(add = ArgValueCompleter::new(commands::completions::complete_pkg_names))]
+        packages: Vec<String>,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            conflicts_with = "asexplicit",
+            help = "Mark the packages as installed as a dependency"
+        )]
+        asdeps: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Mark the packages as explicitly installed"
+        )]
+        asexplicit: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Skip packages that are already installed and up to date"
+        )]
+        needed: bool,
+
+        #[arg(
+            long,
+            short = 'w',
+            default_value_t = false,
+            help = "Only download the packages to the cache, do not install them"
+        )]
+        downloadonly: bool,
+
+        #[arg(
+            long,
+            value_name = "GLOB",
+            help = "Overwrite conflicting files matching this glob"
+        )]
+        overwrite: Vec<String>,
+
+        #[arg(
+            long,
+            short = 'R',
+            value_name = "PKG",
+            num_args = 1..,
+            add = ArgValueCompleter::new(commands::completions::complete_pkg_names),
+            help = "Remove these packages in the same transaction"
+        )]
+        remove: Vec<String>,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            conflicts_with = "downloadonly",
+            help = "Print the download URLs of the resolved packages, do not install them"
+        )]
+        print_uris: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Do not offer to upgrade the system first when it is out of date"
+        )]
+        allow_partial: bool,
+    },
 
     #[command(about = "List installed packages")]
-    List,
+    List {
+        #[arg(
+            long,
+            short,
+            default_value_t = false,
+            conflicts_with_all = ["deps", "foreign"],
+            help = "Only list explicitly installed packages"
+        )]
+        explicit: bool,
+
+        #[arg(
+            long,
+            short,
+            default_value_t = false,
+            conflicts_with = "foreign",
+            help = "Only list packages installed as dependencies"
+        )]
+        deps: bool,
+
+        #[arg(
+            long,
+            short,
+            default_value_t = false,
+            help = "Only list packages found in no sync database (AUR, manually built)"
+        )]
+        foreign: bool,
+
+        #[arg(
+            long,
+            short,
+            default_value_t = false,
+            help = "Only list packages with a newer version available"
+        )]
+        upgradable: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            requires = "upgradable",
+            help = "Use the already downloaded databases instead of refreshing a private copy"
+        )]
+        no_sync: bool,
+
+        #[arg(long, alias = "format", value_name = "FORMAT", help = format::PRINT_FORMAT_HELP)]
+        print_format: Option<String>,
+    },
+
+    #[command(
+        about = "Change whether installed packages count as explicitly installed or as dependencies",
+        group = clap::ArgGroup::new("reason").required(true).args(["asdeps", "asexplicit"])
+    )]
+    Mark {
+        #[arg(required = true, add = ArgValueCompleter::new(commands::completions::complete_pkg_names))]
+        packages: Vec<String>,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Mark the packages as installed as a dependency"
+        )]
+        asdeps: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Mark the packages as explicitly installed"
+        )]
+        asexplicit: bool,
+    },
+
+    #[command(about = "Find the installed package owning a file")]
+    Owns { path: String },
+
+    #[command(about = "List .pacnew and .pacsave files left in /etc")]
+    Pacfiles {
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Show the differences to the current files (uses $DIFFPROG if set)"
+        )]
+        diff: bool,
+    },
+
+    #[command(
+        about = "Find packages providing a name, virtual package, soname (e.g. libssl.so=3-64) or file"
+    )]
+    Provides { target: String },
 
     #[command(about = "Remove a package")]
     Remove {
@@ -71,6 +432,35 @@ enum Commands {
             help = "Do not remove dependencies (not recommended)"
         )]
         no_deep: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Skip packages still required by other installed packages, and never remove those"
+        )]
+        unneeded: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Delete modified config files instead of keeping them as .pacsave, also for removed dependencies"
+        )]
+        no_save: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            conflicts_with = "no_save",
+            help = "Keep modified config files as .pacsave (the default)"
+        )]
+        keep_config: bool,
+
+        #[arg(
+            long,
+            value_name = "FORMAT",
+            help = "Print the removed packages, including dependencies, as a json array or null-delimited"
+        )]
+        output_removed: Option<RemovedFormat>,
     },
 
     #[command(about = "Search for a package by name or description")]
@@ -79,16 +469,166 @@ enum Commands {
 
         #[arg(long, short)]
         num_results: Option<u32>,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Search the existing cache without refreshing the databases"
+        )]
+        no_sync: bool,
+
+        #[arg(long, value_name = "REPO", help = "Only consider this repository")]
+        repo: Vec<String>,
+
+        #[arg(
+            long,
+            value_name = "REPO",
+            conflicts_with = "repo",
+            help = "Do not show results from this repository"
+        )]
+        exclude_repo: Vec<String>,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Do not show packages that are already installed"
+        )]
+        exclude_installed: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Only show packages matching every search term"
+        )]
+        match_all: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            conflicts_with = "desc_only",
+            help = "Only match package names"
+        )]
+        name_only: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Only match package descriptions"
+        )]
+        desc_only: bool,
+
+        #[arg(
+            long,
+            default_value_t = 0.0,
+            value_name = "SCORE",
+            help = "Drop results scoring below this, relative to the best match (0 to 1)"
+        )]
+        min_score: f64,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Choose packages to install from the results"
+        )]
+        select: bool,
+
+        #[arg(
+            long,
+            alias = "format",
+            value_name = "FORMAT",
+            conflicts_with = "select",
+            help = format::PRINT_FORMAT_HELP
+        )]
+        print_format: Option<String>,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            conflicts_with = "history",
+            help = "Repeat the previous search"
+        )]
+        repeat_last: bool,
+
+        #[arg(long, default_value_t = false, help = "List recent searches")]
+        history: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Do not record this search in the history"
+        )]
+        no_history: bool,
     },
 
-    #[command(about = "Update the package metadata, NOTE: this is not a system upgrade !!!")]
-    Update {
+    #[command(
+        alias = "update",
+        about = "Refresh the package databases, NOTE: this is not a system upgrade !!!"
+    )]
+    Sync {
         #[arg(long, default_value_t = false, help = "Update the file cache")]
         files: bool,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            conflicts_with = "files",
+            help = "Update only the package descriptions in the cache, enough for search"
+        )]
+        cache: bool,
+
+        #[arg(
+            short = 'y',
+            action = clap::ArgAction::Count,
+            help = "Pass twice (-yy) to force a refresh of up to date databases"
+        )]
+        refresh: u8,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Download all databases and rebuild the file cache even if they are up to date"
+        )]
+        force: bool,
     },
 
-    #[command(about = "Upgrade all packages on the system")]
-    Upgrade,
+    #[command(about = "Refresh the package databases and upgrade all packages on the system")]
+    Upgrade {
+        #[arg(
+            long,
+            default_value_t = false,
+            help = "Allow downgrading packages newer than their repository version"
+        )]
+        allow_downgrade: bool,
+
+        #[arg(long, default_value_t = false, help = "Sort the verbose table by size")]
+        sort_size: bool,
+
+        #[arg(
+            long,
+            short = 'w',
+            default_value_t = false,
+            help = "Only download the packages to the cache, do not install them"
+        )]
+        downloadonly: bool,
+
+        #[arg(
+            long,
+            value_name = "GLOB",
+            help = "Overwrite conflicting files matching this glob"
+        )]
+        overwrite: Vec<String>,
+
+        #[arg(
+            long,
+            default_value_t = false,
+            conflicts_with = "downloadonly",
+            help = "Print the download URLs of the resolved packages, do not upgrade them"
+        )]
+        print_uris: bool,
+    },
+
+    #[command(about = "Explain why a package is installed")]
+    Why { package: String },
 }
 
 #[derive(Subcommand)]
@@ -99,48 +639,258 @@ enum CacheSubcommand {
 fn run() -> Result<()> {
     let cli = Cli::parse();
 
-    let mut napm = Napm::new()?;
+    // needs neither root nor an alpm handle
+    if let Commands::Completions { shell } = cli.command {
+        commands::completions::run(shell, &mut Cli::command());
+        return Ok(());
+    }
+
+    log::write_log_file("RUN", &format!("napm {}", util::current_args().join(" ")));
+
+    log::set_verbosity(if cli.quiet {
+        log::VERBOSITY_QUIET
+    } else {
+        log::VERBOSITY_NORMAL + cli.verbose
+    });
+    util::set_noconfirm(cli.noconfirm);
+    pager::set_no_pager(cli.no_pager);
+    util::set_offline(cli.offline);
+    util::set_no_repair(cli.no_repair);
+    let sysroot = cli.sysroot.is_some();
+    let root = cli
+        .root
+        .or(cli.sysroot)
+        .as_deref()
+        .map(Napm::canonical_root)
+        .transpose()?;
+    util::set_root(root.clone());
+    util::set_sysroot(sysroot);
+
+    napm::interrupt::install_handler()?;
+
+    let mut napm = Napm::new(NapmOptions {
+        dry_run: cli.dry_run,
+        root,
+        sysroot,
+        db_path: None,
+        parallel_downloads: cli.parallel_downloads,
+        disable_download_timeout: cli.disable_download_timeout,
+        download_user: cli.download_user,
+        no_repair: cli.no_repair,
+    })?;
 
     match cli.command {
-        Commands::Update { files } => commands::update::run(&mut napm, files),
-        Commands::Files { package, dirs } => commands::files::run(&mut napm, &package, dirs),
-        Commands::Info { package } => commands::info::run(&napm, &package),
-        Commands::Install { packages } => commands::install::run(
+        Commands::Completions { .. } => unreachable!("handled before the handle is created"),
+        Commands::Cache => commands::cache::run(&napm),
+        Commands::Check { packages, files } => commands::check::run(
+            &napm,
+            packages
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .as_slice(),
+            files,
+        ),
+        Commands::Deptree {
+            package,
+            depth,
+            reverse,
+        } => commands::deptree::run(&napm, &package, depth, reverse),
+        Commands::Files {
+            package,
+            dirs,
+            repo,
+            sort,
+            filter,
+        } => commands::files::run(&mut napm, &package, dirs, &repo, sort, filter.as_deref()),
+        Commands::History { package, limit } => {
+            commands::history::run(&napm, package.as_deref(), limit)
+        }
+        Commands::Info {
+            package,
+            repo,
+            print_format,
+        } => commands::info::run(&napm, &package, &repo, print_format.as_deref()),
+        Commands::Install {
+            packages,
+            asdeps,
+            asexplicit,
+            needed,
+            downloadonly,
+            overwrite,
+            remove,
+            print_uris,
+            allow_partial,
+        } => commands::install::run(
             &mut napm,
             packages
                 .iter()
                 .map(|s| s.as_str())
                 .collect::<Vec<_>>()
                 .as_slice(),
+            InstallOptions {
+                reason: if asdeps {
+                    Some(PackageReason::Depend)
+                } else if asexplicit {
+                    Some(PackageReason::Explicit)
+                } else {
+                    None
+                },
+                needed,
+                download_only: downloadonly,
+                overwrite,
+                remove,
+                print_uris,
+                allow_partial,
+            },
+        ),
+        Commands::List {
+            explicit,
+            deps,
+            foreign,
+            upgradable,
+            no_sync,
+            print_format,
+        } => commands::list::run(
+            &mut napm,
+            if explicit {
+                ListFilter::Explicit
+            } else if deps {
+                ListFilter::Deps
+            } else if foreign {
+                ListFilter::Foreign
+            } else {
+                ListFilter::All
+            },
+            upgradable.then_some(no_sync),
+            cli.quiet,
+            print_format.as_deref(),
         ),
-        Commands::List => commands::list::run(&napm),
-        Commands::Find { path, exact } => commands::find::run(&mut napm, path, exact),
-        Commands::Remove { packages, no_deep } => commands::remove::run(
+        Commands::Find {
+            path,
+            exact,
+            installed,
+        } => commands::find::run(&mut napm, path, exact, installed),
+        Commands::Mark {
+            packages,
+            asdeps,
+            asexplicit: _,
+        } => commands::mark::run(
+            &mut napm,
+            &packages,
+            if asdeps {
+                PackageReason::Depend
+            } else {
+                PackageReason::Explicit
+            },
+        ),
+        Commands::Owns { path } => commands::owns::run(&napm, &path),
+        Commands::Pacfiles { diff } => commands::pacfiles::run(&napm, diff),
+        Commands::Provides { target } => commands::provides::run(&mut napm, &target),
+        Commands::Remove {
+            packages,
+            no_deep,
+            unneeded,
+            no_save,
+            keep_config: _,
+            output_removed,
+        } => commands::remove::run(
             &mut napm,
             packages
                 .iter()
                 .map(|s| s.as_str())
                 .collect::<Vec<_>>()
                 .as_slice(),
-            !no_deep,
+            RemoveOptions {
+                deep: !no_deep,
+                unneeded,
+                no_save,
+            },
+            output_removed,
         ),
         Commands::Search {
             search_terms,
             num_results,
-        } => commands::search::run(&napm, search_terms, num_results),
-        Commands::Upgrade => commands::upgrade::run(&mut napm),
+            no_sync,
+            repo,
+            exclude_repo,
+            exclude_installed,
+            match_all,
+            name_only,
+            desc_only,
+            min_score,
+            select,
+            print_format,
+            repeat_last,
+            history,
+            no_history,
+        } => commands::search::run(
+            &mut napm,
+            search_terms,
+            no_sync,
+            SearchOutput {
+                num_results,
+                select_to_install: select,
+                print_format: print_format.as_deref(),
+            },
+            SearchHistory {
+                repeat_last,
+                list: history,
+                record: !no_history,
+            },
+            SearchOptions {
+                repos: repo,
+                exclude_repos: exclude_repo,
+                exclude_installed,
+                match_all,
+                field: if name_only {
+                    SearchField::Name
+                } else if desc_only {
+                    SearchField::Desc
+                } else {
+                    SearchField::All
+                },
+                min_score,
+            },
+        ),
+        Commands::Sync {
+            files,
+            cache,
+            refresh,
+            force,
+        } => commands::sync::run(&mut napm, files, cache, force || refresh > 1),
+        Commands::Upgrade {
+            allow_downgrade,
+            sort_size,
+            downloadonly,
+            overwrite,
+            print_uris,
+        } => commands::upgrade::run(
+            &mut napm,
+            UpgradeOptions {
+                allow_downgrade,
+                verbose: cli.verbose > 0,
+                sort_by_size: sort_size,
+                download_only: downloadonly,
+                overwrite,
+                print_uris,
+            },
+        ),
+        Commands::Why { package } => commands::why::run(&napm, &package),
     }?;
 
     Ok(())
 }
 
 fn main() {
+    CompleteEnv::with_factory(Cli::command).complete();
+
     if let Err(err) = run() {
         if let Error::NothingToDo = err {
             log_info!("Nothing to do");
         } else {
             log_fatal!("{}", err);
-            std::process::exit(1)
+            std::process::exit(err.exit_code())
         }
     }
 }
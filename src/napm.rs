@@ -1,27 +1,48 @@
 use alpm::{
     Alpm, AnyDownloadEvent, AnyEvent, AnyQuestion, DownloadEvent, DownloadEventCompleted,
-    DownloadEventProgress, DownloadResult, Usage,
+    DownloadEventProgress, DownloadResult, LogLevel, Usage,
 };
 use indicatif::{MultiProgress, ProgressBar};
 use pacmanconf::Config;
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex},
+    fs,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 
 use crate::ansi::*;
 use crate::error::{Error, Result};
+use crate::napm::config::NapmConfig;
 use crate::pkg::Pkg;
-use crate::util::{choose, confirm};
-use crate::{log_error, log_info, log_warn};
+use crate::util::{choose, confirm, multi_progress, progress_enabled};
+use crate::{log_debug, log_error, log_info, log_warn};
 
 pub mod actions;
+pub mod archive;
 pub mod auto_repair;
 pub mod cache;
+pub mod check;
+pub mod config;
+pub mod deps;
+#[cfg(test)]
+pub mod fixtures;
+pub mod info;
 pub mod init_system;
+pub mod interrupt;
+pub mod pacfiles;
+pub mod pkg_cache;
 pub mod style;
+pub mod sync;
+pub mod transactions;
 pub mod util;
 
+const ARCH: &str = "x86_64";
+
 // NAPM ERROR DATA
 
 // struct NapmDep {
@@ -34,6 +55,13 @@ struct NapmConflict {
     // TODO: reason: NapmDep,
 }
 
+struct NapmFileConflict {
+    pkg: Pkg,
+    file: String,
+    // None when the file exists on the filesystem without an owner
+    owner: Option<Pkg>,
+}
+
 struct NapmDepMissing {
     target: String,
     causing_pkg: Option<String>,
@@ -43,56 +71,155 @@ struct NapmDepMissing {
 #[allow(dead_code)]
 enum NapmErrorData {
     Empty,
-    FileConflict(Vec<NapmConflict>),
+    Pkg(String),
+    FileConflict(Vec<NapmFileConflict>),
     PkgInvalid(Vec<String>),
     PkgInvalidArch(Vec<Pkg>),
     UnsatisfiedDeps(Vec<NapmDepMissing>),
     ConflictingDeps(Vec<NapmConflict>),
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct NapmOptions {
+    pub dry_run: bool,
+    pub root: Option<String>,
+    // the root is a complete system, its own pacman.conf is used instead of the host's
+    pub sysroot: bool,
+    pub db_path: Option<String>,
+    pub parallel_downloads: Option<u32>,
+    pub disable_download_timeout: bool,
+    pub download_user: Option<String>,
+    pub no_repair: bool,
+}
+
 pub struct Napm {
     config: Config,
     handle: Option<Alpm>,
+    options: NapmOptions,
+    napm_config: NapmConfig,
+    // set between trans_init and trans_release, so an early return doesn't leave it behind
+    trans_active: bool,
 }
 
 impl Napm {
-    pub fn new() -> Result<Self> {
+    // the root is expected to have gone through canonical_root already
+    pub fn new(options: NapmOptions) -> Result<Self> {
         let mut me = Self {
-            config: Config::new().map_err(|_| Error::ConfigParse)?,
+            config: Self::load_config(options.root.as_deref(), options.sysroot)?,
             handle: None,
+            options,
+            napm_config: NapmConfig::load()?,
+            trans_active: false,
         };
+        Self::set_progress_config(&me.napm_config);
         me.reset()?;
         Ok(me)
     }
 
-    pub fn reset(&mut self) -> Result<()> {
-        let cfg = Config::new().map_err(|_| Error::ConfigParse)?;
+    // a relative root would resolve differently once re-executed as root from another directory
+    pub fn canonical_root(root: &str) -> Result<String> {
+        let invalid = |reason: String| Error::InvalidRoot(root.to_string(), reason);
 
-        if cfg.root_dir != "/" {
-            unimplemented!("Non / root");
+        let path = fs::canonicalize(root).map_err(|e| invalid(e.to_string()))?;
+
+        if !path.is_dir() {
+            return Err(invalid("not a directory".to_string()));
         }
 
-        let mut handle = Alpm::new("/", &cfg.db_path)?;
+        Ok(path.to_string_lossy().to_string())
+    }
 
-        let arch = "x86_64";
+    fn load_config(root: Option<&str>, sysroot: bool) -> Result<Config> {
+        let config = root
+            .filter(|_| sysroot)
+            .map(|root| format!("{}/etc/pacman.conf", root.trim_end_matches('/')));
 
-        for dir in &cfg.cache_dir {
-            let path: Vec<u8> = if dir.starts_with('/') {
-                dir.clone()
-            } else {
-                format!("/{}", &dir)
+        Config::with_opts(None, config.as_deref(), root).map_err(|_| Error::ConfigParse)
+    }
+
+    // paths of the host, like a DBPath or HookDir set in its pacman.conf, must not be used
+    // for a system being installed, like pacstrap does
+    fn in_root(&self, path: &str) -> String {
+        match self.options.root.as_deref() {
+            Some(root) if self.foreign_root() && !Path::new(path).starts_with(root) => {
+                Path::new(root)
+                    .join(path.trim_start_matches('/'))
+                    .to_string_lossy()
+                    .to_string()
+            }
+            _ => path.to_string(),
+        }
+    }
+
+    pub fn reset(&mut self) -> Result<()> {
+        let cfg = Self::load_config(self.options.root.as_deref(), self.options.sysroot)?;
+
+        // the lock lives next to the database, so it is the one of the target root too
+        let db_path = match &self.options.db_path {
+            Some(db_path) => db_path.clone(),
+            None => self.in_root(&cfg.db_path),
+        };
+        let db_path = &db_path;
+        log_debug!("Using root {} and database path {db_path}", cfg.root_dir);
+
+        let cache_dirs: Vec<PathBuf> = cfg
+            .cache_dir
+            .iter()
+            .map(|dir| Path::new("/").join(dir))
+            .collect();
+
+        // a fresh root, like /mnt before pacstrap, has none of these yet
+        if self.options.root.is_some() && !self.dry_run() {
+            let root = Path::new(&cfg.root_dir);
+
+            for dir in std::iter::once(&PathBuf::from(db_path)).chain(&cache_dirs) {
+                if dir.starts_with(root) && !dir.exists() {
+                    log_debug!("Creating {}", dir.display());
+                    fs::create_dir_all(dir)?;
+                }
             }
-            .into();
+        }
+
+        let mut handle = Alpm::new(cfg.root_dir.as_str(), db_path.as_str())?;
 
-            handle.add_cachedir(path)?;
+        for dir in &cache_dirs {
+            handle.add_cachedir(dir.as_os_str().as_bytes())?;
         }
 
         handle.set_check_space(cfg.check_space);
 
-        if cfg.parallel_downloads > 0 {
-            handle.set_parallel_downloads(cfg.parallel_downloads as u32);
+        // the command line wins over napm.conf, which wins over pacman.conf
+        let parallel_downloads = self
+            .options
+            .parallel_downloads
+            .or(self.napm_config.parallel_downloads)
+            .unwrap_or(cfg.parallel_downloads as u32);
+
+        if parallel_downloads > 0 {
+            handle.set_parallel_downloads(parallel_downloads);
         }
 
+        // the command line wins over DownloadUser from pacman.conf
+        if let Some(user) = self
+            .options
+            .download_user
+            .as_ref()
+            .or(cfg.download_user.as_ref())
+        {
+            if !matches!(nix::unistd::User::from_name(user), Ok(Some(_))) {
+                return Err(Error::UnknownUser(user.clone()));
+            }
+
+            if let Err(e) = handle.set_sandbox_user(Some(user.as_str())) {
+                log_warn!("Downloads will run as root, setting the download user failed: {e}");
+            }
+        }
+
+        // without it, libalpm gives up on a mirror stalled for 10 seconds and tries the next one
+        handle.set_disable_dl_timeout(
+            self.options.disable_download_timeout || cfg.disable_download_timeout,
+        );
+
         let local_siglevel = Self::parse_siglevel(&cfg.local_file_sig_level)?;
         let remote_siglevel = Self::parse_siglevel(&cfg.remote_file_sig_level)?;
 
@@ -109,18 +236,28 @@ impl Napm {
             let name: Vec<u8> = repo.clone().name.into();
             let db = handle.register_syncdb_mut(name, siglevel)?;
 
-            for server in &repo.servers {
-                let url = server.replace("$repo", &repo.name).replace("$arch", arch);
+            // cache servers are tried first, whatever the order of the repo's own servers
+            let servers = repo
+                .servers
+                .iter()
+                .map(|server| server.replace("$repo", &repo.name).replace("$arch", ARCH));
+
+            for url in self
+                .cache_server_urls(&repo.name)
+                .into_iter()
+                .chain(servers)
+            {
                 db.add_server(url)?;
             }
 
             db.set_usage(Usage::all())?; // TODO? take from config
         }
 
-        handle.add_hookdir("/usr/share/libalpm/hooks")?;
+        let syshook_dir = Path::new(&cfg.root_dir).join("usr/share/libalpm/hooks");
+        handle.add_hookdir(syshook_dir.to_string_lossy().as_bytes())?;
 
         for hook_dir in &cfg.hook_dir {
-            handle.add_hookdir(hook_dir.clone())?;
+            handle.add_hookdir(self.in_root(hook_dir))?;
         }
 
         let gpg_dir: Vec<u8> = cfg.gpg_dir.clone().into();
@@ -128,7 +265,7 @@ impl Napm {
 
         // callbacks
 
-        let download_progress = Arc::new(Mutex::new((MultiProgress::new(), HashMap::new())));
+        let download_progress = Arc::new(Mutex::new((multi_progress(), HashMap::new())));
         handle.set_dl_cb(download_progress, download_callback);
 
         handle.set_event_cb((), event_callback);
@@ -138,6 +275,8 @@ impl Napm {
 
         handle.set_question_cb((), question_callback);
 
+        handle.set_log_cb((), log_callback);
+
         // TODO: handle.set_fetch_cb
 
         self.config = cfg;
@@ -149,13 +288,40 @@ impl Napm {
 
 impl Drop for Napm {
     fn drop(&mut self) {
-        if let Some(h) = self.handle.take() {
+        self.mark_trans_active(false);
+
+        if let Some(mut h) = self.handle.take() {
+            if self.trans_active {
+                let _ = h.trans_release();
+            }
             let _ = h.unlock();
             let _ = h.release();
         }
+
+        self.mark_unlocked();
     }
 }
 
+// the hook running or run last, its output explains a failure reported afterwards
+struct HookRun {
+    name: String,
+    post_transaction: bool,
+    running: bool,
+    output: Vec<String>,
+}
+
+static HOOKS_POST_TRANSACTION: AtomicBool = AtomicBool::new(false);
+static HOOK_RUN: Mutex<Option<HookRun>> = Mutex::new(None);
+
+// name and output of the last hook run
+fn last_hook_run() -> Option<(String, Vec<String>)> {
+    HOOK_RUN
+        .lock()
+        .ok()?
+        .as_ref()
+        .map(|run| (run.name.clone(), run.output.clone()))
+}
+
 fn event_callback(ev: AnyEvent, _: &mut ()) {
     use alpm::{HookWhen, PackageOperation};
 
@@ -195,7 +361,16 @@ fn event_callback(ev: AnyEvent, _: &mut ()) {
         E::IntegrityDone => (),
         E::LoadStart => (),
         E::LoadDone => (),
-        E::ScriptletInfo(scriptlet_info) => log_info!("  {}", scriptlet_info.line().trim()),
+        E::ScriptletInfo(scriptlet_info) => {
+            log_info!("  {}", scriptlet_info.line().trim());
+
+            if let Ok(mut hook_run) = HOOK_RUN.lock()
+                && let Some(run) = hook_run.as_mut().filter(|run| run.running)
+            {
+                run.output
+                    .push(scriptlet_info.line().trim_end().to_string());
+            }
+        }
         E::RetrieveStart => log_info!("Retrieving files"),
         E::RetrieveDone => (),
         E::RetrieveFailed => log_info!("Failed to retrieve some"),
@@ -241,27 +416,73 @@ fn event_callback(ev: AnyEvent, _: &mut ()) {
             pacsave_ev.file(),
             pacsave_ev.file()
         ),
-        E::HookStart(hook_ev) => log_info!(
-            "Running {} hooks",
-            match hook_ev.when() {
-                HookWhen::PreTransaction => "pre transaction",
-                HookWhen::PostTransaction => "post transaction",
-            }
-        ),
+        E::HookStart(hook_ev) => {
+            let post_transaction = matches!(hook_ev.when(), HookWhen::PostTransaction);
+            HOOKS_POST_TRANSACTION.store(post_transaction, Ordering::Relaxed);
+
+            log_info!(
+                "Running {} hooks",
+                if post_transaction {
+                    "post transaction"
+                } else {
+                    "pre transaction"
+                }
+            )
+        }
         E::HookDone(_hook_ev) => (),
-        E::HookRunStart(hook_run_ev) => log_info!(
-            "Running hook {}/{}: {}",
-            hook_run_ev.position(),
-            hook_run_ev.total(),
-            hook_run_ev
-                .desc()
-                .unwrap_or(hook_run_ev.name())
-                .trim_end_matches("...")
-        ),
-        E::HookRunDone(_hook_run_ev) => (),
+        E::HookRunStart(hook_run_ev) => {
+            if let Ok(mut hook_run) = HOOK_RUN.lock() {
+                *hook_run = Some(HookRun {
+                    name: hook_run_ev.name().to_string(),
+                    post_transaction: HOOKS_POST_TRANSACTION.load(Ordering::Relaxed),
+                    running: true,
+                    output: Vec::new(),
+                });
+            }
+
+            log_info!(
+                "Running hook {}/{}: {}",
+                hook_run_ev.position(),
+                hook_run_ev.total(),
+                hook_run_ev
+                    .desc()
+                    .unwrap_or(hook_run_ev.name())
+                    .trim_end_matches("...")
+            )
+        }
+        E::HookRunDone(_hook_run_ev) => {
+            if let Ok(mut hook_run) = HOOK_RUN.lock()
+                && let Some(run) = hook_run.as_mut()
+            {
+                run.running = false;
+            }
+        }
     };
 }
 
+// alpm reports its errors through return values too, so its messages only go to the log file
+fn log_callback(level: LogLevel, msg: &str, _: &mut ()) {
+    if level.contains(LogLevel::DEBUG) {
+        log_debug!("alpm: {}", msg.trim_end());
+    } else if level.contains(LogLevel::ERROR) {
+        crate::log::write_log_file("ALPM ERROR", msg);
+
+        // libalpm ignores failing post transaction hooks, the packages are in place already
+        if let Ok(hook_run) = HOOK_RUN.lock()
+            && let Some(run) = hook_run
+                .as_ref()
+                .filter(|run| run.running && run.post_transaction)
+        {
+            log_warn!(
+                "Hook {ANSI_YELLOW}{}{ANSI_RESET} failed, the packages are updated but what the hook does may be missing",
+                run.name
+            );
+        }
+    } else if level.contains(LogLevel::WARNING) {
+        crate::log::write_log_file("ALPM WARN", msg);
+    }
+}
+
 fn question_callback(q: AnyQuestion, _: &mut ()) {
     use alpm::Question as Q;
     use std::path::Path;
@@ -282,7 +503,9 @@ fn question_callback(q: AnyQuestion, _: &mut ()) {
         Q::Replace(x) => {
             let old = x.oldpkg().name();
             let new = x.newpkg().name();
-            let prompt = format!("Replace package {ANSI_CYAN}{old} with {ANSI_CYAN}{new}?");
+            let prompt = format!(
+                "Replace package {ANSI_CYAN}{old}{ANSI_RESET} with {ANSI_CYAN}{new}{ANSI_RESET}?"
+            );
 
             match confirm(&prompt, true) {
                 Ok(ans) => x.set_replace(ans),
@@ -331,7 +554,34 @@ fn question_callback(q: AnyQuestion, _: &mut ()) {
                 Err(err) => err.die(),
             }
         }
-        _ => (),
+        Q::InstallIgnorepkg(mut x) => {
+            let name = x.pkg().name();
+            let prompt = format!(
+                "{ANSI_CYAN}{name}{ANSI_RESET} is in IgnorePkg/IgnoreGroup. Install anyway?"
+            );
+
+            match confirm(&prompt, true) {
+                Ok(ans) => x.set_install(ans),
+                Err(err) => err.die(),
+            }
+        }
+        Q::RemovePkgs(mut x) => {
+            let names = x
+                .packages()
+                .iter()
+                .map(|pkg| Pkg::format_name(pkg.name(), Some(pkg.version())))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let prompt = format!(
+                "These packages cannot be upgraded due to unresolvable dependencies: {names}. Skip them for this upgrade?"
+            );
+
+            // skipping leaves a partial upgrade behind, so it is never the default
+            match confirm(&prompt, false) {
+                Ok(ans) => x.set_skip(ans),
+                Err(err) => err.die(),
+            }
+        }
     }
 }
 
@@ -367,17 +617,24 @@ fn download_callback(
             let mut bars_guard = bars.lock().unwrap();
             let (_, bar_map) = &mut *bars_guard;
 
+            let message = match result {
+                DownloadResult::Success => format!("{file} done"),
+                DownloadResult::UpToDate => format!("{file} up to date"),
+                DownloadResult::Failed => format!("{file} failed"),
+            };
+
             if let Some(pb) = bar_map.remove(file) {
                 pb.set_position(total as u64);
+                if let DownloadResult::Failed = result {
+                    pb.set_style(Napm::progress_bar_style(true).clone());
+                }
+                pb.finish_with_message(message.clone());
+            }
+
+            if !progress_enabled() {
                 match result {
-                    DownloadResult::Success => pb.finish_with_message(format!("{file} done")),
-                    DownloadResult::UpToDate => {
-                        pb.finish_with_message(format!("{file} up to date"))
-                    }
-                    DownloadResult::Failed => {
-                        pb.set_style(Napm::progress_bar_style(true).clone());
-                        pb.finish_with_message(format!("{file} failed"));
-                    }
+                    DownloadResult::Failed => log_warn!("{message}"),
+                    _ => log_info!("{message}"),
                 }
             }
         }
@@ -410,3 +667,84 @@ fn download_callback(
 //     pb.set_length(percent as u64);
 //     pb.set_message(format!("{file} {:?} {}/{}", progress, current, how_many));
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napm::fixtures::Fixture;
+    use alpm::TransFlag;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("napm-root-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn canonical_root_resolves_relative_paths() {
+        let dir = temp_dir("relative");
+        let cwd = std::env::current_dir().unwrap();
+
+        // the same directory, reached from the working directory without a leading /
+        let up = "../".repeat(cwd.components().count() - 1);
+        let relative = format!("{up}{}/./", dir.strip_prefix("/").unwrap().display());
+
+        assert_eq!(
+            Napm::canonical_root(&relative).unwrap(),
+            fs::canonicalize(&dir).unwrap().to_string_lossy()
+        );
+        assert_eq!(
+            Napm::canonical_root(".").unwrap(),
+            cwd.canonicalize().unwrap().to_string_lossy()
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn canonical_root_rejects_files_and_missing_paths() {
+        let dir = temp_dir("invalid");
+        let file = dir.join("file");
+        fs::write(&file, "").unwrap();
+
+        assert!(matches!(
+            Napm::canonical_root(&file.to_string_lossy()),
+            Err(Error::InvalidRoot(..))
+        ));
+        assert!(matches!(
+            Napm::canonical_root(&dir.join("missing").to_string_lossy()),
+            Err(Error::InvalidRoot(..))
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn fails_after_trans_init(napm: &mut Napm) -> Result<()> {
+        napm.trans_init(TransFlag::NONE)?;
+
+        Err(Error::NothingToDo)
+    }
+
+    #[test]
+    fn drop_releases_a_transaction_left_by_an_early_return() {
+        let mut fixture = Fixture::new("drop");
+        fixture.local("foo", "1.0-1");
+        let lock = fixture.dir.join("db/db.lck");
+
+        {
+            let mut napm = fixture.napm();
+
+            assert!(fails_after_trans_init(&mut napm).is_err());
+            assert!(napm.trans_active);
+            assert!(lock.exists());
+        }
+
+        assert!(!lock.exists());
+
+        // the next handle is not locked out
+        let mut napm = fixture.napm();
+        napm.trans_init(TransFlag::NONE).unwrap();
+        napm.trans_release().unwrap();
+    }
+}
@@ -1,14 +1,107 @@
-use std::path::Path;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
-use alpm::TransFlag;
+use alpm::{AlpmListMut, Db, Depend, Package, PackageReason, TransFlag};
 
-use crate::util::run_upgrade;
+use crate::util::{human_size, noconfirm, offline, run_system_upgrade, run_upgrade};
 use crate::{log_action_required, napm::*};
 use crate::{log_fatal, log_info, log_warn};
 
+const MAX_VERSION_WIDTH: usize = 24;
+
+#[derive(Debug, Clone, Default)]
+pub struct UpgradeOptions {
+    pub allow_downgrade: bool,
+    pub verbose: bool,
+    pub sort_by_size: bool,
+    pub download_only: bool,
+    pub overwrite: Vec<String>,
+    pub print_uris: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    pub reason: Option<PackageReason>,
+    pub needed: bool,
+    pub download_only: bool,
+    pub overwrite: Vec<String>,
+    // removed in the same transaction, like swapping a package for a conflicting one
+    pub remove: Vec<String>,
+    pub print_uris: bool,
+    pub allow_partial: bool,
+}
+
+impl InstallOptions {
+    fn trans_flags(&self) -> TransFlag {
+        let flags = match self.reason {
+            Some(PackageReason::Depend) => TransFlag::ALL_DEPS,
+            Some(PackageReason::Explicit) => TransFlag::ALL_EXPLICIT,
+            None => TransFlag::NONE,
+        };
+
+        if self.download_only {
+            flags | TransFlag::DOWNLOAD_ONLY
+        } else if self.print_uris {
+            // nothing is committed, so it works without root
+            flags | TransFlag::NO_LOCK
+        } else {
+            flags
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListFilter {
+    #[default]
+    All,
+    Explicit,
+    Deps,
+    // not in any sync database, like AUR or manually built packages
+    Foreign,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RemoveOptions {
+    pub deep: bool,
+    pub unneeded: bool,
+    pub no_save: bool,
+}
+
+impl RemoveOptions {
+    // cascading would remove the very dependents --unneeded protects,
+    // modified config files are kept as .pacsave unless no_save, deep or not
+    fn trans_flags(&self) -> TransFlag {
+        let flags = match (self.deep, self.unneeded) {
+            (true, true) => TransFlag::RECURSE | TransFlag::UNNEEDED,
+            (true, false) => TransFlag::RECURSE | TransFlag::CASCADE,
+            (false, true) => TransFlag::UNNEEDED,
+            (false, false) => TransFlag::NONE,
+        };
+
+        if self.no_save {
+            flags | TransFlag::NO_SAVE
+        } else {
+            flags
+        }
+    }
+}
+
 impl Napm {
-    pub fn install_pkgs(&mut self, pkgs: &[Pkg]) -> Result<()> {
-        let mut to_install = pkgs.to_vec();
+    pub fn install_pkgs(
+        &mut self,
+        pkgs: &[Pkg],
+        pkg_files: &[PathBuf],
+        pkg_urls: &[String],
+        to_remove: &[Pkg],
+        options: &InstallOptions,
+    ) -> Result<()> {
+        let mut seen = HashSet::new();
+        let mut to_install = pkgs
+            .iter()
+            .filter(|pkg| seen.insert(pkg.name.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
 
         match self.init_system() {
             Ok(init_system) => {
@@ -42,7 +135,8 @@ impl Napm {
                                 pkg.desc
                             ),
                             true,
-                        )? {
+                        )? && !to_install.iter().any(|p| p.name == pkg.name)
+                        {
                             to_install.push(pkg);
                         }
                     }
@@ -52,36 +146,252 @@ impl Napm {
             Err(e) => return Err(e),
         }
 
-        let result = self.install_pkgs_attempt(&to_install);
+        if options.needed {
+            let localdb = self.h().localdb();
 
-        if let Err(Error::UpgradeRequired) = &result {
-            log_warn!("Stale database detected, update and upgrade required");
+            to_install.retain(|pkg| {
+                let up_to_date = localdb
+                    .pkg(pkg.name.as_str())
+                    .is_ok_and(|local| local.version().as_str() == pkg.version);
+
+                if up_to_date {
+                    log_warn!(
+                        "{} is up to date, skipping",
+                        Pkg::format_name(&pkg.name, Some(&pkg.version))
+                    );
+                }
+
+                !up_to_date
+            });
+
+            if to_install.is_empty()
+                && pkg_files.is_empty()
+                && pkg_urls.is_empty()
+                && to_remove.is_empty()
+            {
+                return Err(Error::NothingToDo);
+            }
+        }
 
-            let lock_path = self.h().lockfile();
-            let _ = std::fs::remove_file(lock_path);
+        if !to_install.is_empty()
+            && !options.allow_partial
+            && !options.download_only
+            && !options.print_uris
+            && !self.dry_run()
+            && !offline()
+        {
+            self.check_partial_upgrade(&to_install)?;
+        }
 
-            let sync_path = Path::new(self.h().dbpath()).join("sync");
-            run_upgrade(&sync_path)?;
+        if offline() && !self.dry_run() && !pkg_urls.is_empty() {
+            return Err(Error::Offline(format!(
+                "Downloading {}",
+                pkg_urls.join(", ")
+            )));
+        }
 
-            std::fs::File::create(std::path::Path::new(&lock_path))?;
+        let fetched = if options.print_uris {
+            for url in pkg_urls {
+                println!("{url}");
+            }
+            Vec::new()
+        } else if self.dry_run() {
+            // nothing is installed from them, so they are not worth downloading
+            for url in pkg_urls {
+                log_info!("Would download {url}");
+            }
+            Vec::new()
+        } else {
+            self.fetch_pkg_urls(pkg_urls)?
+        };
+        let remote_files = fetched
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+
+        let can_upgrade =
+            self.auto_repair() && !self.dry_run() && !options.print_uris && !offline();
 
-            self.reset()?;
+        let result = self.retry_after_upgrade(can_upgrade, Self::upgrade_stale_system, |napm| {
+            napm.install_pkgs_attempt(&to_install, pkg_files, &remote_files, to_remove, options)
+        });
 
-            return self.install_pkgs_attempt(pkgs);
+        if result.is_err() {
+            Self::remove_fetched(&fetched);
         }
 
         result
     }
 
-    fn install_pkgs_attempt(&mut self, pkgs: &[Pkg]) -> Result<()> {
-        log_info!(
-            "Installing {} with all {} dependencies",
+    // like pacman -D, changes the reason of installed packages without reinstalling them
+    pub fn set_install_reason(&mut self, names: &[String], reason: PackageReason) -> Result<()> {
+        let reason_str = match reason {
+            PackageReason::Explicit => "explicitly installed",
+            PackageReason::Depend => "installed as a dependency",
+        };
+
+        if self.dry_run() {
+            for name in names {
+                log_info!(
+                    "Would mark {} as {reason_str}",
+                    Pkg::format_name(name, None)
+                );
+            }
+            return Ok(());
+        }
+
+        self.trans_init(TransFlag::NONE)?;
+
+        let result = names.iter().try_for_each(|name| {
+            let pkg = self
+                .h()
+                .localdb()
+                .pkg(name.as_str())
+                .map_err(|_| Error::PackageNotInLocalDb(name.clone()))?;
+
+            pkg.set_reason(reason)?;
+            log_info!("Marked {} as {reason_str}", Pkg::format_name(name, None));
+
+            Ok(())
+        });
+
+        self.trans_release()?;
+
+        result
+    }
+
+    fn fetch_pkg_urls(&self, urls: &[String]) -> Result<Vec<(PathBuf, bool)>> {
+        if urls.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let handle = self.h();
+
+        let already_cached = handle
+            .cachedirs()
+            .iter()
+            .flat_map(|dir| {
+                urls.iter()
+                    .filter_map(|url| url.rsplit('/').next())
+                    .map(move |file_name| Path::new(dir).join(file_name))
+            })
+            .filter(|path| path.exists())
+            .collect::<Vec<_>>();
+
+        log_info!("Downloading {}", urls.join(", "));
+
+        let fetched = handle
+            .fetch_pkgurl(urls.iter().map(String::as_str))
+            .map_err(Error::PackageDownload)?;
+
+        Ok(fetched
+            .iter()
+            .map(|path| {
+                let path = PathBuf::from(path);
+                let downloaded = !already_cached.contains(&path);
+                (path, downloaded)
+            })
+            .collect())
+    }
+
+    fn remove_fetched(fetched: &[(PathBuf, bool)]) {
+        for (path, downloaded) in fetched {
+            if !downloaded {
+                continue;
+            }
+
+            log_info!("Removing downloaded {}", path.display());
+
+            let _ = std::fs::remove_file(path);
+            let _ = std::fs::remove_file(format!("{}.sig", path.display()));
+        }
+    }
+
+    // new packages are built against the libraries of the synced databases, not the installed ones
+    fn check_partial_upgrade(&mut self, pkgs: &[Pkg]) -> Result<()> {
+        let upgradable = self.upgradable();
+
+        if upgradable.is_empty() {
+            return Ok(());
+        }
+
+        log_warn!(
+            "{} installed package(s) have updates, installing {} without them is a partial upgrade",
+            upgradable.len(),
             pkgs.iter()
-                .map(|pkg| pkg.formatted_name(true))
+                .map(|pkg| pkg.formatted_name(false))
                 .collect::<Vec<_>>()
-                .join(", "),
-            if pkgs.len() == 1 { "its" } else { "their" }
+                .join(", ")
         );
+        log_info!("Pass {ANSI_YELLOW}--allow-partial{ANSI_RESET} to skip this check");
+
+        // upgrading the whole system is never done behind the user's back
+        if noconfirm() || !self.auto_repair() {
+            return Ok(());
+        }
+
+        if !confirm("Upgrade the system before the installation?", false)? {
+            log_warn!("Continuing with a partial upgrade");
+            return Ok(());
+        }
+
+        run_system_upgrade()?;
+
+        self.reset()
+    }
+
+    // a stale database makes the download fail, the attempt is repeated once after the upgrade
+    fn retry_after_upgrade(
+        &mut self,
+        can_upgrade: bool,
+        upgrade: impl FnOnce(&mut Self) -> Result<()>,
+        mut attempt: impl FnMut(&mut Self) -> Result<()>,
+    ) -> Result<()> {
+        let result = attempt(self);
+
+        if let Err(Error::UpgradeRequired) = &result
+            && can_upgrade
+        {
+            log_warn!("Stale database detected, update and upgrade required");
+
+            upgrade(self)?;
+
+            log_info!("Retrying the installation");
+            return attempt(self);
+        }
+
+        result
+    }
+
+    fn upgrade_stale_system(&mut self) -> Result<()> {
+        // the spawned napm processes need the lock held by the current transaction
+        let lock_path = self.h().lockfile().to_string();
+        let _ = std::fs::remove_file(&lock_path);
+
+        let sync_path = Path::new(self.h().dbpath()).join("sync");
+        let upgrade_result = run_upgrade(&sync_path);
+
+        std::fs::File::create(Path::new(&lock_path))?;
+
+        upgrade_result?;
+
+        self.reset()
+    }
+
+    fn install_pkgs_attempt(
+        &mut self,
+        pkgs: &[Pkg],
+        pkg_files: &[PathBuf],
+        remote_pkg_files: &[PathBuf],
+        to_remove: &[Pkg],
+        options: &InstallOptions,
+    ) -> Result<()> {
+        if let Some(size) = self.cached_download_size(pkgs) {
+            log_info!(
+                "Download size without dependencies: {}",
+                human_size(size as i64)
+            );
+        }
 
         {
             let handle = self.handle.take().unwrap();
@@ -95,6 +405,16 @@ impl Napm {
 
             self.handle = Some(handle);
 
+            // conflicting with a package that goes away in the same transaction is fine
+            let conflicts = conflicts
+                .iter()
+                .filter(|c| {
+                    !to_remove.iter().any(|pkg| {
+                        pkg.name == c.package1().name() || pkg.name == c.package2().name()
+                    })
+                })
+                .collect::<Vec<_>>();
+
             if !conflicts.is_empty() {
                 log_fatal!("Conflicts occured");
                 for c in conflicts {
@@ -104,43 +424,590 @@ impl Napm {
             }
         }
 
-        self.trans_init(TransFlag::NONE)?;
+        self.trans_init(options.trans_flags())?;
+        self.add_overwrite_globs(&options.overwrite)?;
 
+        for pkg in pkgs {
+            self.trans_add_pkg(pkg)?;
+        }
+
+        let local_siglevel = self.h().local_file_siglevel();
+        for pkg_file in pkg_files {
+            self.trans_add_pkg_file(pkg_file, local_siglevel)?;
+        }
+
+        let remote_siglevel = self.h().remote_file_siglevel();
+        for pkg_file in remote_pkg_files {
+            self.trans_add_pkg_file(pkg_file, remote_siglevel)?;
+        }
+
+        for pkg in to_remove {
+            self.trans_remove_pkg(pkg)?;
+        }
+
+        self.trans_prepare()?;
+
+        if offline()
+            && let Some(missing) = self.trans_uncached_pkg()
         {
-            let handle = self.handle.take().unwrap();
+            self.trans_release()?;
+            return Err(Error::NotCachedOffline(missing));
+        }
 
-            for pkg in pkgs {
-                let package = pkg.clone().into_package_ref(&handle)?;
-                handle
-                    .trans_add_pkg(package)
-                    .map_err(|_| Error::TransAddPkg)?;
-            }
+        if options.print_uris {
+            return self.trans_print_uris();
+        }
 
-            self.handle = Some(handle);
+        if self.dry_run() {
+            return self.trans_dry_run();
         }
 
+        self.print_trans_summary();
+        self.confirm_trans(
+            if to_remove.is_empty() {
+                "Proceed with the installation?"
+            } else {
+                "Proceed with the installation and removal?"
+            },
+            true,
+        )?;
+
+        self.trans_commit_or_download("install", options.download_only)
+    }
+
+    pub fn upgrade(&mut self, options: &UpgradeOptions) -> Result<()> {
+        if self.napm_config.refresh_keyring_before_upgrade
+            && !self.dry_run()
+            && !options.download_only
+            && !options.print_uris
+        {
+            self.upgrade_keyrings()?;
+        }
+
+        log_info!("Upgrading the system");
+
+        self.trans_init(if options.download_only {
+            TransFlag::DOWNLOAD_ONLY
+        } else if options.print_uris {
+            TransFlag::NO_LOCK
+        } else {
+            TransFlag::NONE
+        })?;
+        self.add_overwrite_globs(&options.overwrite)?;
+
+        self.h_mut().sync_sysupgrade(options.allow_downgrade)?;
+
         self.trans_prepare()?;
 
+        // without --allow-downgrade these are kept, which is otherwise easy to miss
+        let kept_newer = if options.allow_downgrade {
+            Vec::new()
+        } else {
+            self.newer_than_repos()
+        };
+
+        if !kept_newer.is_empty() {
+            log_warn!("Some installed packages are newer than the repositories and are kept:");
+
+            for (local, repo) in &kept_newer {
+                eprintln!(
+                    " {ANSI_YELLOW}!{ANSI_RESET} {} {} (repository has {})",
+                    local.formatted_name(false),
+                    local.version,
+                    repo.version
+                );
+            }
+
+            log_info!("Use --allow-downgrade to downgrade them to the repository versions");
+        }
+
+        // sysupgrade silently leaves these out, a partial upgrade should not be a surprise
+        let held_back = self.trans_held_back();
+
+        if !held_back.is_empty() {
+            log_warn!("Some updates are held back:");
+
+            for (old, new, reason) in &held_back {
+                eprintln!(
+                    " {ANSI_YELLOW}!{ANSI_RESET} {} {} => {} {ANSI_YELLOW}({reason}){ANSI_RESET}",
+                    old.formatted_name(false),
+                    old.version,
+                    new.version
+                );
+            }
+        }
+
+        if self.h().trans_add().is_empty() && self.h().trans_remove().is_empty() {
+            self.trans_release()?;
+            return Err(Error::NothingToDo);
+        }
+
+        if options.print_uris {
+            return self.trans_print_uris();
+        }
+
+        let verbose = options.verbose || self.config.verbose_pkg_lists;
+
+        if self.dry_run() {
+            log_info!("Dry run, nothing will be changed");
+        }
+
+        if verbose {
+            self.print_trans_table(options.sort_by_size);
+        } else {
+            self.print_trans_summary();
+        }
+
+        if self.dry_run() {
+            return self.trans_release();
+        }
+
+        let downgrades = self.trans_downgrades();
+
+        if !downgrades.is_empty() {
+            log_warn!(
+                "Some packages will be downgraded, this may break packages depending on them"
+            );
+
+            for (old, new) in &downgrades {
+                eprintln!(
+                    " {ANSI_RED}-{ANSI_RESET} {} {ANSI_RED}{} => {}{ANSI_RESET}",
+                    old.formatted_name(false),
+                    old.version,
+                    new.version
+                );
+            }
+        }
+
+        self.confirm_trans("Proceed with the upgrade?", downgrades.is_empty())?;
+
+        self.trans_commit_or_download("upgrade", options.download_only)
+    }
+
+    fn trans_uncached_pkg(&self) -> Option<String> {
+        let handle = self.h();
+
+        handle
+            .trans_add()
+            .iter()
+            .filter(|pkg| pkg.db().is_some_and(|db| db.name() != "local"))
+            .filter_map(|pkg| pkg.filename())
+            .find(|file| {
+                !handle
+                    .cachedirs()
+                    .iter()
+                    .any(|dir| Path::new(dir).join(file).exists())
+            })
+            .map(str::to_string)
+    }
+
+    // nothing changed on the system, so there is nothing to record either
+    fn trans_commit_or_download(&mut self, operation: &str, download_only: bool) -> Result<()> {
+        if !download_only {
+            return self.trans_commit_recorded(operation);
+        }
+
         self.trans_commit()?;
 
+        log_info!("Packages downloaded to the cache, run the {operation} again to apply them");
+
         Ok(())
     }
 
-    pub fn upgrade(&mut self) -> Result<()> {
-        log_info!("Upgrading the system");
+    // an outdated keyring makes the signatures of the other packages fail to verify,
+    // the keyring packages populate the new keys from their install scripts
+    fn upgrade_keyrings(&mut self) -> Result<()> {
+        let keyrings = self.pending_keyrings();
 
-        // TODO: list upgradable packages and maybe ask for confimration
+        if keyrings.is_empty() {
+            return Ok(());
+        }
+
+        log_info!(
+            "Upgrading {} before the rest of the system",
+            keyrings
+                .iter()
+                .map(|pkg| pkg.formatted_name(true))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
 
         self.trans_init(TransFlag::NONE)?;
 
-        self.h_mut().sync_sysupgrade(false)?;
+        for pkg in &keyrings {
+            self.trans_add_pkg(pkg)?;
+        }
 
         self.trans_prepare()?;
+        self.trans_commit_recorded("upgrade")?;
 
-        self.trans_commit()
+        self.trans_release()
     }
 
-    pub fn remove_pkgs(&mut self, pkgs: &[Pkg], deep: bool) -> Result<()> {
+    fn pending_keyrings(&self) -> Vec<Pkg> {
+        let handle = self.h();
+
+        handle
+            .localdb()
+            .pkgs()
+            .into_iter()
+            .filter(|pkg| pkg.name().ends_with("-keyring"))
+            .filter_map(|pkg| pkg.sync_new_version(handle.syncdbs()))
+            .map(Pkg::from)
+            .collect()
+    }
+
+    // the first server of the repo is the one libalpm tries first, package files are local
+    fn trans_print_uris(&mut self) -> Result<()> {
+        for pkg in self.h().trans_add() {
+            let Some(filename) = pkg.filename() else {
+                continue;
+            };
+
+            match pkg.db() {
+                Some(db) => match db.servers().first() {
+                    Some(server) => println!("{}/{filename}", server.trim_end_matches('/')),
+                    None => log_warn!("{} has no server", db.name()),
+                },
+                None => println!("file://{filename}"),
+            }
+        }
+
+        self.trans_release()
+    }
+
+    fn trans_dry_run(&mut self) -> Result<()> {
+        log_info!("Dry run, nothing will be changed");
+
+        self.print_trans_summary();
+
+        self.trans_release()
+    }
+
+    fn confirm_trans(&mut self, prompt: &str, default_yes: bool) -> Result<()> {
+        if confirm(prompt, default_yes)? {
+            return Ok(());
+        }
+
+        self.trans_release()?;
+
+        Err(Error::Stopped)
+    }
+
+    fn print_trans_summary(&self) {
+        let handle = self.h();
+        let localdb = handle.localdb();
+
+        let mut download_size = 0;
+        let mut size_change = 0;
+
+        println!(
+            "Packages ({}):",
+            handle.trans_add().len() + handle.trans_remove().len()
+        );
+
+        for new in handle.trans_add() {
+            download_size += new.download_size();
+            size_change += new.isize();
+
+            let new_name = Pkg::format_name(new.name(), Some(new.version()));
+
+            match localdb.pkg(new.name()) {
+                Ok(old) => {
+                    size_change -= old.isize();
+
+                    let action = match new.version().vercmp(old.version()) {
+                        Ordering::Greater => format!("{ANSI_GREEN}upgrade{ANSI_RESET}  "),
+                        Ordering::Less => format!("{ANSI_RED}downgrade{ANSI_RESET}"),
+                        Ordering::Equal => format!("{ANSI_YELLOW}reinstall{ANSI_RESET}"),
+                    };
+
+                    println!(
+                        " {action} {} {ANSI_MAGENTA}{}{ANSI_RESET} => {ANSI_MAGENTA}{}{ANSI_RESET}",
+                        Pkg::format_name(new.name(), None),
+                        old.version(),
+                        new.version()
+                    );
+                }
+                Err(_) => println!(" {ANSI_GREEN}install{ANSI_RESET}   {new_name}"),
+            }
+        }
+
+        for old in handle.trans_remove() {
+            size_change -= old.isize();
+
+            println!(
+                " {ANSI_RED}remove{ANSI_RESET}    {}",
+                Pkg::format_name(old.name(), Some(old.version()))
+            );
+        }
+
+        println!();
+        println!("Download size  : {}", human_size(download_size));
+        println!("Installed size : {}", human_size(size_change));
+    }
+
+    // requirements between the targets themselves do not count, they go away together
+    fn still_required(&self, pkgs: &[Pkg]) -> Vec<(Pkg, Vec<String>)> {
+        let localdb = self.h().localdb();
+
+        pkgs.iter()
+            .filter_map(|pkg| {
+                let required_by = localdb
+                    .pkg(pkg.name.as_str())
+                    .ok()?
+                    .required_by()
+                    .into_iter()
+                    .filter(|name| !pkgs.iter().any(|p| p.name == *name))
+                    .collect::<Vec<_>>();
+
+                (!required_by.is_empty()).then(|| (pkg.clone(), required_by))
+            })
+            .collect()
+    }
+
+    fn print_removal_summary(&self, requested: &[Pkg]) {
+        let removed = self.h().trans_remove();
+        let mut freed = 0;
+
+        println!("Packages ({}):", removed.len());
+
+        for old in removed {
+            freed += old.isize();
+
+            let pulled_in = if requested.iter().any(|pkg| pkg.name == old.name()) {
+                ""
+            } else {
+                " (dependency or dependent)"
+            };
+
+            println!(
+                " {ANSI_RED}remove{ANSI_RESET}    {}{ANSI_YELLOW}{pulled_in}{ANSI_RESET}",
+                Pkg::format_name(old.name(), Some(old.version()))
+            );
+        }
+
+        println!();
+        println!("Freed size     : {}", human_size(freed));
+    }
+
+    fn print_trans_table(&self, sort_by_size: bool) {
+        let handle = self.h();
+        let localdb = handle.localdb();
+        let truncate = Self::truncate_version;
+
+        let mut download_size = 0;
+        let mut size_change = 0;
+
+        // name, old version, new version, net change, download size
+        let mut rows = Vec::new();
+
+        for new in handle.trans_add() {
+            let old = localdb.pkg(new.name()).ok();
+            let change = new.isize() - old.map_or(0, |old| old.isize());
+
+            download_size += new.download_size();
+            size_change += change;
+
+            rows.push((
+                new.name().to_string(),
+                old.map_or(String::new(), |old| truncate(old.version())),
+                truncate(new.version()),
+                change,
+                new.download_size(),
+            ));
+        }
+
+        for old in handle.trans_remove() {
+            size_change -= old.isize();
+
+            rows.push((
+                old.name().to_string(),
+                truncate(old.version()),
+                String::new(),
+                -old.isize(),
+                0,
+            ));
+        }
+
+        if sort_by_size {
+            rows.sort_by_key(|row| std::cmp::Reverse((row.4, row.3.abs())));
+        }
+
+        let rows = rows
+            .into_iter()
+            .map(|(name, old, new, change, download)| {
+                [name, old, new, human_size(change), human_size(download)]
+            })
+            .collect::<Vec<_>>();
+
+        let header = [
+            format!("Package ({})", rows.len()),
+            "Old Version".to_string(),
+            "New Version".to_string(),
+            "Net Change".to_string(),
+            "Download Size".to_string(),
+        ];
+
+        let mut lines = Self::table_lines(&header, &rows).into_iter();
+
+        if let Some(header) = lines.next() {
+            println!("{header}");
+            println!();
+        }
+
+        for line in lines {
+            println!("{line}");
+        }
+
+        println!();
+        println!("Total Download Size  : {}", human_size(download_size));
+        println!("Total Installed Size : {}", human_size(size_change));
+    }
+
+    fn truncate_version(version: &str) -> String {
+        if version.chars().count() > MAX_VERSION_WIDTH {
+            let kept = version
+                .chars()
+                .take(MAX_VERSION_WIDTH - 1)
+                .collect::<String>();
+            format!("{kept}…")
+        } else {
+            version.to_string()
+        }
+    }
+
+    // the header first, then one line per row
+    fn table_lines(header: &[String; 5], rows: &[[String; 5]]) -> Vec<String> {
+        let mut widths = header.each_ref().map(|cell| cell.chars().count());
+        for row in rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let format_row = |row: &[String; 5], colors: [&str; 5]| {
+            row.iter()
+                .zip(widths)
+                .zip(colors)
+                .enumerate()
+                .map(|(i, ((cell, width), color))| {
+                    // names and versions are left aligned, sizes right aligned
+                    if i < 3 {
+                        format!("{color}{cell:<width$}{ANSI_RESET}")
+                    } else {
+                        format!("{color}{cell:>width$}{ANSI_RESET}")
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("  ")
+        };
+
+        std::iter::once(format_row(header, [ANSI_BOLD; 5]))
+            .chain(
+                rows.iter()
+                    .map(|row| format_row(row, [ANSI_CYAN, ANSI_MAGENTA, ANSI_MAGENTA, "", ""])),
+            )
+            .collect()
+    }
+
+    fn trans_downgrades(&self) -> Vec<(Pkg, Pkg)> {
+        let handle = self.h();
+
+        handle
+            .trans_add()
+            .into_iter()
+            .filter_map(|new| {
+                let old = handle.localdb().pkg(new.name()).ok()?;
+
+                (new.version().vercmp(old.version()) == Ordering::Less)
+                    .then(|| (Pkg::from(old), Pkg::from(new)))
+            })
+            .collect()
+    }
+
+    // installed packages whose version in the first repo providing them is older, what
+    // sysupgrade would downgrade with --allow-downgrade
+    fn newer_than_repos(&self) -> Vec<(Pkg, Pkg)> {
+        let handle = self.h();
+        let added: HashSet<&str> = handle.trans_add().iter().map(|pkg| pkg.name()).collect();
+
+        handle
+            .localdb()
+            .pkgs()
+            .into_iter()
+            .filter(|local| !added.contains(local.name()))
+            .filter_map(|local| {
+                let repo = handle
+                    .syncdbs()
+                    .iter()
+                    .find_map(|db| db.pkg(local.name()).ok())?;
+
+                (repo.version().vercmp(local.version()) == Ordering::Less)
+                    .then(|| (Pkg::from(local), Pkg::from(repo)))
+            })
+            .collect()
+    }
+
+    // installed packages with a newer sync version that the transaction doesn't upgrade
+    fn trans_held_back(&self) -> Vec<(Pkg, Pkg, &'static str)> {
+        let handle = self.h();
+
+        let added: HashSet<&str> = handle.trans_add().iter().map(|pkg| pkg.name()).collect();
+        let removed: HashSet<&str> = handle.trans_remove().iter().map(|pkg| pkg.name()).collect();
+
+        handle
+            .localdb()
+            .pkgs()
+            .into_iter()
+            .filter(|old| !added.contains(old.name()))
+            .filter_map(|old| {
+                let new = old.sync_new_version(handle.syncdbs())?;
+
+                let reason = if new.should_ignore() || old.should_ignore() {
+                    "ignored"
+                } else if removed.contains(old.name()) {
+                    "replaced"
+                } else {
+                    "conflict"
+                };
+
+                Some((Pkg::from(old), Pkg::from(new), reason))
+            })
+            .collect()
+    }
+
+    pub fn remove_pkgs(&mut self, pkgs: &[Pkg], options: &RemoveOptions) -> Result<Vec<Pkg>> {
+        let deep = options.deep;
+
+        let skipped = if options.unneeded {
+            self.still_required(pkgs)
+        } else {
+            Vec::new()
+        };
+
+        for (pkg, required_by) in &skipped {
+            log_warn!(
+                "Skipping {}, it is required by {}",
+                pkg.formatted_name(false),
+                required_by
+                    .iter()
+                    .map(|name| Pkg::format_name(name, None))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        let pkgs = pkgs
+            .iter()
+            .filter(|pkg| !skipped.iter().any(|(s, _)| s.name == pkg.name))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if pkgs.is_empty() {
+            return Err(Error::NothingToDo);
+        }
+
         log_info!(
             "Removing {}{}",
             pkgs.iter()
@@ -157,30 +1024,46 @@ impl Napm {
             }
         );
 
-        self.trans_init(if deep {
-            TransFlag::RECURSE | TransFlag::CASCADE | TransFlag::NO_SAVE
-        } else {
-            TransFlag::NONE
-        })?;
+        self.trans_init(options.trans_flags())?;
 
-        {
-            let handle = self.handle.take().unwrap();
+        for pkg in &pkgs {
+            self.trans_remove_pkg(pkg)?;
+        }
 
-            for pkg in pkgs {
-                let package = pkg.clone().into_package_ref(&handle)?;
-                handle
-                    .trans_remove_pkg(package)
-                    .map_err(|_| Error::TransRemovePkg)?;
-            }
+        self.trans_prepare()?;
 
-            self.handle = Some(handle);
+        // includes the packages pulled in by RECURSE and CASCADE
+        let removed = self
+            .h()
+            .trans_remove()
+            .into_iter()
+            .map(Pkg::from)
+            .collect::<Vec<_>>();
+
+        if self.dry_run() {
+            log_info!("Dry run, nothing will be changed");
+            self.print_removal_summary(&pkgs);
+            self.trans_release()?;
+            return Ok(removed);
         }
 
-        self.trans_prepare()?;
+        self.print_removal_summary(&pkgs);
 
-        self.trans_commit()?;
+        let extra = removed
+            .iter()
+            .filter(|pkg| !pkgs.iter().any(|p| p.name == pkg.name))
+            .count();
+        if extra > 0 {
+            log_warn!(
+                "{extra} package(s) that were not requested will be removed too, check the list above"
+            );
+        }
 
-        Ok(())
+        self.confirm_trans("Proceed with the removal?", extra == 0)?;
+
+        self.trans_commit_recorded("remove")?;
+
+        Ok(removed)
     }
 
     pub fn find(&mut self, mut file: String, exact: bool) -> Result<Vec<(Pkg, String)>> {
@@ -191,24 +1074,493 @@ impl Napm {
         };
 
         if exact {
-            for part in ["bin", "lib", "lib64", "sbin"] {
-                if file.starts_with(&format!("/{part}/")) {
-                    file = format!("/usr{file}");
-                    log_warn!("/{part} is a symlink, finding {file} instead");
-                    break;
-                }
+            let resolved = match Self::resolve_leading_symlinks(Path::new(self.h().root()), &file) {
+                Some(resolved) => Some(resolved).filter(|resolved| *resolved != file),
+                None => Self::usr_merged(&file),
+            };
+
+            if let Some(resolved) = resolved {
+                log_warn!("{file} goes through a symlink, finding {resolved} instead");
+                file = resolved;
             }
         }
 
         self.find_packages_by_file(&file, exact)
     }
 
-    pub fn list(&self) -> Vec<Pkg> {
-        self.h()
+    // only the directories are resolved, a symlinked file is owned under its own name,
+    // absolute link targets are taken relative to the root
+    fn resolve_leading_symlinks(root: &Path, file: &str) -> Option<String> {
+        if !root.is_dir() {
+            return None;
+        }
+
+        let mut parts = file
+            .trim_start_matches('/')
+            .split('/')
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let name = parts.pop()?;
+
+        let mut resolved: Vec<String> = Vec::new();
+        let mut hops = 0;
+
+        while !parts.is_empty() {
+            let part = parts.remove(0);
+
+            match part.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    resolved.pop();
+                    continue;
+                }
+                _ => {}
+            }
+
+            resolved.push(part);
+
+            let Ok(target) = std::fs::read_link(root.join(resolved.join("/"))) else {
+                continue;
+            };
+
+            hops += 1;
+            if hops > 40 {
+                return None;
+            }
+
+            resolved.pop();
+            if target.is_absolute() {
+                resolved.clear();
+            }
+
+            let target = target.to_string_lossy().to_string();
+            parts.splice(0..0, target.split('/').map(str::to_string));
+        }
+
+        resolved.push(name);
+
+        Some(format!("/{}", resolved.join("/")))
+    }
+
+    fn usr_merged(file: &str) -> Option<String> {
+        ["bin", "lib", "lib64", "sbin"]
+            .iter()
+            .any(|part| file.starts_with(&format!("/{part}/")))
+            .then(|| format!("/usr{file}"))
+    }
+
+    pub fn owns(&self, path: &str) -> Result<Vec<(Pkg, String)>> {
+        let root = Path::new(self.h().root());
+        let absolute = std::path::absolute(path)?;
+
+        // a path given through the root on the host, like /mnt/usr/bin/ls for --root /mnt
+        let absolute = match absolute.strip_prefix(root) {
+            Ok(inside) if root != Path::new("/") => Path::new("/").join(inside),
+            _ => absolute,
+        }
+        .to_string_lossy()
+        .to_string();
+
+        // a symlinked file is owned under its own name, only the directories are resolved,
+        // missing ones still get the /usr normalization
+        let file = Self::resolve_leading_symlinks(root, &absolute)
+            .or_else(|| Self::usr_merged(&absolute))
+            .unwrap_or(absolute);
+
+        let relative = file.trim_start_matches('/');
+        let as_dir = format!("{}/", relative.trim_end_matches('/'));
+
+        Ok(self
+            .h()
+            .localdb()
+            .pkgs()
+            .into_iter()
+            .filter_map(|pkg| {
+                let files = pkg.files();
+                let owned = files
+                    .contains(relative)
+                    .or_else(|| files.contains(as_dir.as_str()))?;
+                Some((
+                    Pkg::from(pkg),
+                    format!("/{}", String::from_utf8_lossy(owned.name())),
+                ))
+            })
+            .collect())
+    }
+
+    // alpm's own matching, like pacman -S with the repo alone: the package of that name if it
+    // satisfies the target, otherwise the first of its packages providing it
+    pub(super) fn satisfier<'a>(db: &'a Db, target: &str) -> Option<&'a Package> {
+        std::iter::once(db)
+            .collect::<AlpmListMut<_>>()
+            .list()
+            .find_satisfier(target)
+    }
+
+    pub fn provides(&mut self, target: &str) -> Result<Vec<(Pkg, String)>> {
+        if target.starts_with('/') {
+            return self.find(target.to_string(), true);
+        }
+
+        let target_dep = Depend::new(target);
+        let mut results = Vec::new();
+
+        for db in self.h().syncdbs() {
+            let Some(pkg) = Self::satisfier(db, target) else {
+                continue;
+            };
+
+            // only for display, alpm already decided that it matches
+            let provided = if pkg.name() == target_dep.name() {
+                pkg.name().to_string()
+            } else {
+                pkg.provides()
+                    .iter()
+                    .find(|p| p.name() == target_dep.name())
+                    .map_or_else(|| target.to_string(), |p| p.to_string())
+            };
+
+            results.push((Pkg::from(pkg), provided));
+        }
+
+        Ok(results)
+    }
+
+    pub fn list(&self, filter: ListFilter) -> Vec<Pkg> {
+        let handle = self.h();
+
+        handle
             .localdb()
             .pkgs()
             .into_iter()
+            .filter(|pkg| match filter {
+                ListFilter::All => true,
+                ListFilter::Explicit => pkg.reason() == PackageReason::Explicit,
+                ListFilter::Deps => pkg.reason() == PackageReason::Depend,
+                ListFilter::Foreign => self.origin_repo(pkg.name()).is_none(),
+            })
             .map(Pkg::from)
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napm::fixtures::Fixture;
+    use alpm::Error as AlpmErr;
+
+    fn repos() -> Fixture {
+        let mut fixture = Fixture::new("satisfier");
+        fixture
+            .sync("core", &[("foo", "1.0-1", &[]), ("bash", "5.2-1", &["sh"])])
+            .sync(
+                "extra",
+                &[("foo", "2.0-1", &[]), ("dash", "0.5-1", &["sh=0.5"])],
+            );
+        fixture
+    }
+
+    fn names(pkgs: &[(Pkg, String)]) -> Vec<&str> {
+        pkgs.iter().map(|(pkg, _)| pkg.name.as_str()).collect()
+    }
+
+    #[test]
+    fn pkg_constrained_picks_the_newest_satisfying_version() {
+        let fixture = repos();
+        let napm = fixture.napm();
+
+        let pkg = napm.pkg_constrained("foo>=1.5").unwrap();
+        assert_eq!(
+            (pkg.version.as_str(), pkg.repo.as_str()),
+            ("2.0-1", "extra")
+        );
+
+        let pkg = napm.pkg_constrained("foo<1.5").unwrap();
+        assert_eq!((pkg.version.as_str(), pkg.repo.as_str()), ("1.0-1", "core"));
+
+        assert!(matches!(
+            napm.pkg_constrained("foo>3"),
+            Err(Error::NoSatisfyingVersion(..))
+        ));
+        assert!(matches!(
+            napm.pkg_constrained("missing>1"),
+            Err(Error::PackageNotFound(..))
+        ));
+    }
+
+    #[test]
+    fn provides_follows_alpm_matching() {
+        let fixture = repos();
+        let mut napm = fixture.napm();
+
+        assert_eq!(names(&napm.provides("sh").unwrap()), ["bash", "dash"]);
+        // an unversioned provide never satisfies a versioned target
+        assert_eq!(names(&napm.provides("sh>=0.1").unwrap()), ["dash"]);
+        assert_eq!(names(&napm.provides("foo>1.5").unwrap()), ["foo"]);
+    }
+
+    #[test]
+    fn resolve_leading_symlinks_under_the_root() {
+        use std::os::unix::fs::symlink;
+
+        let fixture = Fixture::new("symlinks");
+        let root = fixture.dir.join("root");
+
+        for dir in ["usr/bin", "usr/lib"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        symlink("usr/bin", root.join("bin")).unwrap();
+        // absolute targets point inside the root, not at the host
+        symlink("/usr/lib", root.join("lib")).unwrap();
+        symlink("vim", root.join("usr/bin/vi")).unwrap();
+        symlink("loop", root.join("loop")).unwrap();
+
+        let resolve = |file| Napm::resolve_leading_symlinks(&root, file);
+
+        assert_eq!(resolve("/bin/ls").as_deref(), Some("/usr/bin/ls"));
+        assert_eq!(resolve("/lib/libc.so").as_deref(), Some("/usr/lib/libc.so"));
+        assert_eq!(resolve("/usr/bin/../lib/x").as_deref(), Some("/usr/lib/x"));
+        // the file itself keeps its name
+        assert_eq!(resolve("/usr/bin/vi").as_deref(), Some("/usr/bin/vi"));
+        assert_eq!(resolve("/loop/x"), None);
+
+        assert_eq!(
+            Napm::resolve_leading_symlinks(&fixture.dir.join("missing"), "/bin/ls"),
+            None
+        );
+    }
+
+    #[test]
+    fn usr_merged_only_for_merged_directories() {
+        assert_eq!(Napm::usr_merged("/bin/ls").as_deref(), Some("/usr/bin/ls"));
+        assert_eq!(
+            Napm::usr_merged("/lib64/ld.so").as_deref(),
+            Some("/usr/lib64/ld.so")
+        );
+        assert_eq!(Napm::usr_merged("/usr/bin/ls"), None);
+        assert_eq!(Napm::usr_merged("/binary/x"), None);
+    }
+
+    fn strip_ansi(line: &str) -> String {
+        let mut out = String::new();
+        let mut chars = line.chars();
+
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|c| *c == 'm');
+            } else {
+                out.push(c);
+            }
+        }
+
+        out
+    }
+
+    fn row(cells: [&str; 5]) -> [String; 5] {
+        cells.map(str::to_string)
+    }
+
+    #[test]
+    fn table_columns_are_aligned() {
+        let header = row([
+            "Package (2)",
+            "Old Version",
+            "New Version",
+            "Net Change",
+            "Download Size",
+        ]);
+        let rows = [
+            row([
+                "linux-firmware",
+                "20240101-1",
+                "20240201-1",
+                "1.5 MiB",
+                "300.0 MiB",
+            ]),
+            row(["ca", "", "1:2.0-1", "-12 B", "0 B"]),
+        ];
+
+        let lines = Napm::table_lines(&header, &rows)
+            .iter()
+            .map(|line| strip_ansi(line))
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 3);
+
+        // every line is as wide, so the right aligned sizes end in the same column
+        let width = lines[0].chars().count();
+        assert!(lines.iter().all(|line| line.chars().count() == width));
+
+        // the left aligned columns start in the same place
+        let new_version = lines[0].find("New Version");
+        assert_eq!(lines[1].find("20240201-1"), new_version);
+        assert_eq!(lines[2].find("1:2.0-1"), new_version);
+        assert_eq!(lines[1].find("20240101-1"), lines[0].find("Old Version"));
+        assert!(lines[2].starts_with("ca "));
+        assert!(lines[2].ends_with("  0 B"));
+        assert!(lines[1].ends_with("300.0 MiB"));
+    }
+
+    #[test]
+    fn long_versions_are_truncated() {
+        let short = "1.0.0-1";
+        let long = "1.0.0.r1234.g0123456789abcdef-1";
+
+        assert_eq!(Napm::truncate_version(short), short);
+
+        let truncated = Napm::truncate_version(long);
+        assert_eq!(truncated.chars().count(), MAX_VERSION_WIDTH);
+        assert!(truncated.ends_with('…'));
+        assert!(long.starts_with(truncated.trim_end_matches('…')));
+    }
+
+    fn local_newer() -> Fixture {
+        let mut fixture = Fixture::new("downgrade");
+        fixture
+            .local("foo", "2.0-1")
+            .local("bar", "1.0-1")
+            .sync("core", &[("foo", "1.0-1", &[]), ("bar", "1.0-1", &[])]);
+        fixture
+    }
+
+    fn pair_names(pairs: &[(Pkg, Pkg)]) -> Vec<(&str, &str)> {
+        pairs
+            .iter()
+            .map(|(old, new)| (old.version.as_str(), new.version.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn default_upgrade_reports_packages_kept_newer() {
+        let fixture = local_newer();
+        let mut napm = fixture.napm();
+
+        napm.trans_init(TransFlag::NO_LOCK).unwrap();
+        napm.h_mut().sync_sysupgrade(false).unwrap();
+
+        assert!(napm.trans_downgrades().is_empty());
+        assert_eq!(pair_names(&napm.newer_than_repos()), [("2.0-1", "1.0-1")]);
+
+        napm.trans_release().unwrap();
+    }
+
+    #[test]
+    fn allowed_downgrades_are_in_the_transaction() {
+        let fixture = local_newer();
+        let mut napm = fixture.napm();
+
+        napm.trans_init(TransFlag::NO_LOCK).unwrap();
+        napm.h_mut().sync_sysupgrade(true).unwrap();
+
+        assert_eq!(pair_names(&napm.trans_downgrades()), [("2.0-1", "1.0-1")]);
+        assert!(napm.newer_than_repos().is_empty());
+
+        napm.trans_release().unwrap();
+    }
+
+    #[test]
+    fn keyrings_are_upgraded_alone_first() {
+        let mut fixture = Fixture::new("keyring");
+        fixture
+            .local("archlinux-keyring", "20240101-1")
+            .local("foo", "1.0-1")
+            .sync(
+                "core",
+                &[
+                    ("archlinux-keyring", "20240201-1", &[]),
+                    ("foo", "2.0-1", &[]),
+                ],
+            );
+        let mut napm = fixture.napm();
+
+        let keyrings = napm.pending_keyrings();
+        assert_eq!(
+            keyrings
+                .iter()
+                .map(|pkg| (pkg.name.as_str(), pkg.version.as_str()))
+                .collect::<Vec<_>>(),
+            [("archlinux-keyring", "20240201-1")]
+        );
+
+        // the transaction upgrade_keyrings commits before the system upgrade starts
+        napm.trans_init(TransFlag::NO_LOCK).unwrap();
+        for pkg in &keyrings {
+            napm.trans_add_pkg(pkg).unwrap();
+        }
+        napm.trans_prepare().unwrap();
+
+        let added = napm
+            .h()
+            .trans_add()
+            .iter()
+            .map(|pkg| pkg.name().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(added, ["archlinux-keyring"]);
+
+        napm.trans_release().unwrap();
+    }
+
+    // what a download from a mirror ahead of the local databases fails with
+    fn retrieve_error(napm: &mut Napm) -> Result<()> {
+        napm.on_alpm_error(AlpmErr::Retrieve, NapmErrorData::Empty)
+    }
+
+    #[test]
+    fn install_is_retried_once_after_the_upgrade() {
+        let fixture = Fixture::new("retry");
+        let mut napm = fixture.napm();
+        let (mut attempts, mut upgrades) = (0, 0);
+
+        let result = napm.retry_after_upgrade(
+            true,
+            |_| {
+                upgrades += 1;
+                Ok(())
+            },
+            |napm| {
+                attempts += 1;
+                if attempts == 1 {
+                    retrieve_error(napm)
+                } else {
+                    Ok(())
+                }
+            },
+        );
+
+        assert!(result.is_ok());
+        assert_eq!((attempts, upgrades), (2, 1));
+    }
+
+    #[test]
+    fn install_is_not_retried_without_an_upgrade() {
+        let fixture = Fixture::new("no-retry");
+        let mut napm = fixture.napm();
+
+        // --dry-run, --print-uris, --offline or no repair
+        let mut attempts = 0;
+        let result = napm.retry_after_upgrade(
+            false,
+            |_| panic!("upgraded"),
+            |napm| {
+                attempts += 1;
+                retrieve_error(napm)
+            },
+        );
+        assert!(matches!(result, Err(Error::UpgradeRequired)));
+        assert_eq!(attempts, 1);
+
+        // a failed upgrade is reported instead of retrying into the same error
+        let mut attempts = 0;
+        let result = napm.retry_after_upgrade(
+            true,
+            |_| Err(Error::Update),
+            |napm| {
+                attempts += 1;
+                retrieve_error(napm)
+            },
+        );
+        assert!(matches!(result, Err(Error::Update)));
+        assert_eq!(attempts, 1);
+    }
+}
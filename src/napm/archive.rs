@@ -0,0 +1,237 @@
+use flate2::read::GzDecoder;
+use std::{
+    fs,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    process::{Child, ChildStdout, Command, Stdio},
+};
+use tar::Archive;
+
+use crate::error::{Error, Result};
+
+pub(super) type ArchiveEntry<'a> = tar::Entry<'a, Box<dyn Read>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    // repo-add picks the compression from the extension, but the db is always symlinked
+    // as plain .db/.files, so only the content tells
+    fn detect(magic: &[u8]) -> Self {
+        match magic {
+            [0x1f, 0x8b, ..] => Self::Gzip,
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Self::Xz,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Self::Zstd,
+            [b'B', b'Z', b'h', ..] => Self::Bzip2,
+            _ => Self::None,
+        }
+    }
+
+    fn external_decompressor(&self) -> Option<&'static str> {
+        match self {
+            Self::Xz => Some("xz"),
+            Self::Zstd => Some("zstd"),
+            Self::Bzip2 => Some("bzip2"),
+            Self::None | Self::Gzip => None,
+        }
+    }
+}
+
+// no decoder crate for these, the command line tools are part of any system running pacman
+struct ExternalDecoder {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl ExternalDecoder {
+    fn spawn(program: &str, file: fs::File) -> Result<Self> {
+        let mut child = Command::new(program)
+            .args(["-d", "-c"])
+            .stdin(file)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|_| Error::OpenArchive)?;
+
+        let stdout = child.stdout.take().ok_or(Error::OpenArchive)?;
+
+        Ok(Self { child, stdout })
+    }
+}
+
+impl Read for ExternalDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+
+        // a corrupt or truncated input only shows in the exit status, the output just ends
+        if read == 0 && !buf.is_empty() && !self.child.wait()?.success() {
+            return Err(std::io::Error::other(Error::ExtractArchive));
+        }
+
+        Ok(read)
+    }
+}
+
+impl Drop for ExternalDecoder {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub(super) fn open_archive(path: &Path) -> Result<Archive<Box<dyn Read>>> {
+    let mut file = fs::File::open(path).map_err(|_| Error::OpenArchive)?;
+
+    let mut magic = Vec::with_capacity(6);
+    file.by_ref()
+        .take(6)
+        .read_to_end(&mut magic)
+        .map_err(|_| Error::OpenArchive)?;
+    file.seek(SeekFrom::Start(0))
+        .map_err(|_| Error::OpenArchive)?;
+
+    let compression = Compression::detect(&magic);
+
+    let reader: Box<dyn Read> = match compression {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(GzDecoder::new(file)),
+        _ => Box::new(ExternalDecoder::spawn(
+            compression.external_decompressor().unwrap(),
+            file,
+        )?),
+    };
+
+    Ok(Archive::new(reader))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compression as GzLevel, write::GzEncoder};
+    use std::io::Write;
+
+    const FILES: &[(&str, &str)] = &[
+        ("foo-1.0-1/desc", "%NAME%\nfoo\n"),
+        ("bar-2.0-1/desc", "%NAME%\nbar\n"),
+    ];
+
+    fn tarball() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        for (path, contents) in FILES {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, contents.as_bytes())
+                .unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn compress_with(program: &str, data: &[u8]) -> Option<Vec<u8>> {
+        let mut child = Command::new(program)
+            .arg("-c")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+
+        child.stdin.take()?.write_all(data).ok()?;
+        let output = child.wait_with_output().ok()?;
+
+        output.status.success().then_some(output.stdout)
+    }
+
+    fn compress(compression: Compression, data: &[u8]) -> Option<Vec<u8>> {
+        match compression {
+            Compression::None => Some(data.to_vec()),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), GzLevel::default());
+                encoder.write_all(data).ok()?;
+                encoder.finish().ok()
+            }
+            _ => compress_with(compression.external_decompressor()?, data),
+        }
+    }
+
+    fn write_temp(name: &str, data: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("napm-archive-{name}-{}", std::process::id()));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn read_all(path: &Path) -> Result<Vec<(String, String)>> {
+        let mut archive = open_archive(path)?;
+        let mut files = Vec::new();
+
+        for entry in archive.entries().map_err(|_| Error::ExtractArchive)? {
+            let mut entry = entry.map_err(|_| Error::ExtractArchive)?;
+            let path = entry.path().map_err(|_| Error::ExtractArchive)?;
+            let path = path.to_string_lossy().to_string();
+
+            let mut contents = String::new();
+            entry
+                .read_to_string(&mut contents)
+                .map_err(|_| Error::ExtractArchive)?;
+
+            files.push((path, contents));
+        }
+
+        Ok(files)
+    }
+
+    #[test]
+    fn round_trips_every_format() {
+        let tarball = tarball();
+
+        for compression in [
+            Compression::None,
+            Compression::Gzip,
+            Compression::Xz,
+            Compression::Zstd,
+            Compression::Bzip2,
+        ] {
+            // the external tools are not necessarily installed where the tests run
+            let Some(data) = compress(compression, &tarball) else {
+                continue;
+            };
+            assert_eq!(Compression::detect(&data), compression);
+
+            let path = write_temp(&format!("{compression:?}"), &data);
+            let files = read_all(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+
+            let expected = FILES
+                .iter()
+                .map(|(path, contents)| (path.to_string(), contents.to_string()))
+                .collect::<Vec<_>>();
+            assert_eq!(files, expected, "{compression:?}");
+        }
+    }
+
+    #[test]
+    fn truncated_external_archive_fails() {
+        for compression in [Compression::Xz, Compression::Zstd, Compression::Bzip2] {
+            let Some(mut data) = compress(compression, &tarball()) else {
+                continue;
+            };
+            data.truncate(data.len() / 2);
+
+            let path = write_temp(&format!("{compression:?}-truncated"), &data);
+            let result = read_all(&path);
+            fs::remove_file(&path).unwrap();
+
+            assert!(result.is_err(), "{compression:?}");
+        }
+    }
+}
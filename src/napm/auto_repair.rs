@@ -1,95 +1,205 @@
-use alpm::{CommitData, Error as AlpmErr, PrepareData, TransFlag};
+use alpm::{CommitData, Error as AlpmErr, LoadedPackage, PrepareData, SigLevel, TransFlag};
+use std::collections::HashSet;
+use std::os::unix::{ffi::OsStrExt, fs::MetadataExt};
+use std::path::Path;
+use std::time::Duration;
 
 use crate::napm::*;
-use crate::{log_fatal, log_info};
+use crate::{log_fatal, log_info, log_warn};
+
+const SYNC_RETRIES: u32 = 3;
+const SYNC_RETRY_DELAY: Duration = Duration::from_secs(1);
 
 macro_rules! log_repair {
     ($($arg:tt)*) => {{
         use crate::ansi::*;
-        eprintln!("[{ANSI_CYAN}AUTO REPAIR{ANSI_RESET}] {}", format!($($arg)*));
+        let msg = format!($($arg)*);
+        eprintln!("[{ANSI_CYAN}AUTO REPAIR{ANSI_RESET}] {}", msg);
+        crate::log::write_log_file("REPAIR", &msg);
     }};
 }
 
+fn proc_strings(pid: u32, file: &str) -> Vec<String> {
+    std::fs::read(format!("/proc/{pid}/{file}"))
+        .unwrap_or_default()
+        .split(|b| *b == 0)
+        .filter(|s| !s.is_empty())
+        .map(|s| String::from_utf8_lossy(s).to_string())
+        .collect()
+}
+
+// the command line wins over NAPM_ROOT, like clap does for the running napm
+fn root_from_args(args: &[String], environ: &[String]) -> Option<String> {
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if matches!(arg.as_str(), "--root" | "-r" | "--sysroot") {
+            if let Some(root) = args.next() {
+                return Some(root.clone());
+            }
+        } else if let Some(root) = arg
+            .strip_prefix("--root=")
+            .or_else(|| arg.strip_prefix("--sysroot="))
+        {
+            return Some(root.to_string());
+        }
+    }
+
+    environ
+        .iter()
+        .find_map(|var| var.strip_prefix("NAPM_ROOT="))
+        .filter(|root| !root.is_empty())
+        .map(str::to_string)
+}
+
+fn cmdline_root(pid: u32) -> String {
+    let root = root_from_args(&proc_strings(pid, "cmdline"), &proc_strings(pid, "environ"));
+
+    match root {
+        // relative to the directory the process was started from
+        Some(root) if !root.starts_with('/') => std::fs::read_link(format!("/proc/{pid}/cwd"))
+            .map(|cwd| cwd.join(&root).to_string_lossy().to_string())
+            .unwrap_or(root),
+        Some(root) => root,
+        None => "/".to_string(),
+    }
+}
+
+fn same_root(a: &str, b: &str) -> bool {
+    let normalize = |root: &str| {
+        std::fs::canonicalize(root)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| root.trim_end_matches('/').to_string())
+    };
+
+    normalize(a) == normalize(b)
+}
+
+fn process_cmdline(pid: u32) -> String {
+    std::fs::read(format!("/proc/{pid}/cmdline"))
+        .unwrap_or_default()
+        .split(|b| *b == 0)
+        .map(|arg| String::from_utf8_lossy(arg).to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// pid and command line of each matching process, like `pgrep -a` without depending on it
+fn processes_on_root(name: &str, root: &str, exclude_pid: u32) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|pid| *pid != exclude_pid)
+        .filter(|pid| {
+            std::fs::read_to_string(format!("/proc/{pid}/comm"))
+                .is_ok_and(|comm| comm.trim() == name)
+        })
+        .filter(|pid| same_root(&cmdline_root(*pid), root))
+        .map(|pid| format!("{pid} {}", process_cmdline(pid)))
+        .collect()
+}
+
+// libalpm leaves the lock empty, but other tools may record their pid in it
+fn lock_owner_pid(lock_path: &str) -> Option<u32> {
+    std::fs::read_to_string(lock_path).ok()?.trim().parse().ok()
+}
+
 impl Napm {
-    fn on_alpm_error(&mut self, error: AlpmErr, data: NapmErrorData) -> Result<()> {
-        macro_rules! failed {
-            ($e:ident) => {{
-                log_fatal!("{}", Error::$e);
-                Err(Error::$e)
-            }};
+    // without a pid in the lock, any napm or pacman process on the same root may hold it
+    fn check_no_lock_holders(&self) -> Result<()> {
+        let failed_result = Err(Error::DbUnlock);
+        let current_pid = std::process::id();
+        let root = self.h().root().to_string();
+
+        let napm_processes = processes_on_root("napm", &root, current_pid);
+
+        if !napm_processes.is_empty() {
+            log_fatal!(
+                "Running napm processes on root {root} (except {}):\n{}",
+                current_pid,
+                napm_processes.join("\n")
+            );
+            return failed_result;
+        } else {
+            log_repair!(" - No active napm processes detected on root {root}.");
+        }
+
+        let pacman_processes = processes_on_root("pacman", &root, current_pid);
+
+        if !pacman_processes.is_empty() {
+            log_fatal!(
+                "Running pacman processes on root {root}:\n{}",
+                pacman_processes.join("\n")
+            );
+            return failed_result;
+        } else {
+            log_repair!(" - No active pacman processes detected on root {root}.");
+        }
+
+        Ok(())
+    }
+
+    // Ok means the error was repaired and the caller retries once
+    pub(super) fn on_alpm_error(&mut self, error: AlpmErr, data: NapmErrorData) -> Result<()> {
+        if !self.auto_repair() {
+            return Err(self.alpm_error(error, data));
         }
 
+        self.repair_alpm_error(error, data)
+    }
+
+    fn repair_alpm_error(&mut self, error: AlpmErr, data: NapmErrorData) -> Result<()> {
         use AlpmErr as E;
         match error {
-            E::Ok => failed!(NoAutoRepairError),
-            E::Memory => failed!(Memory),
-            E::System => failed!(System),
-            E::BadPerms => failed!(BadPerms),
-            E::NotAFile | E::NotADir => failed!(UnexpectedType),
-            E::WrongArgs => failed!(WrongArgs),
-            E::DiskSpace => failed!(DiskSpace),
-            E::HandleNull | E::HandleNotNull => failed!(Handle),
             E::HandleLock => {
-                log_repair!("Handle lock detected. Attempting safe removal.");
-
                 let failed_result = Err(Error::DbUnlock);
-                let current_pid = std::process::id();
-
-                let output_napm = std::process::Command::new("pgrep")
-                    .arg("-a")
-                    .arg("napm")
-                    .output();
-
-                match output_napm {
-                    Ok(o) if !o.stdout.is_empty() => {
-                        let output = String::from_utf8_lossy(&o.stdout);
-
-                        let lines = output
-                            .lines()
-                            .filter(|line| {
-                                if let Some(pid_str) = line.split_whitespace().next() {
-                                    match pid_str.parse::<u32>() {
-                                        Ok(pid) => pid != current_pid,
-                                        Err(_) => true,
-                                    }
-                                } else {
-                                    true
-                                }
-                            })
-                            .collect::<Vec<_>>();
-
-                        if !lines.is_empty() {
-                            log_fatal!(
-                                "Running napm processes (except {}):\n{}",
-                                current_pid,
-                                lines.join("\n")
-                            );
-                            return failed_result;
-                        } else {
-                            log_repair!(" - No active napm processes detected.");
-                        }
-                    }
-                    _ => log_repair!(" - No active napm processes detected."),
+                let lock_path = self.h().lockfile().to_string();
+
+                if self.napm_config.no_auto_unlock {
+                    return Err(self.alpm_error(error, data));
                 }
 
-                let output_pacman = std::process::Command::new("pgrep")
-                    .arg("-a")
-                    .arg("pacman")
-                    .output();
+                log_repair!("Handle lock detected. Attempting safe removal.");
+
+                // released in the meantime, retrying is enough
+                let Ok(lock_meta) = std::fs::metadata(&lock_path) else {
+                    return Ok(());
+                };
 
-                match output_pacman {
-                    Ok(o) if !o.stdout.is_empty() => {
+                match lock_owner_pid(&lock_path) {
+                    Some(pid) if Path::new(&format!("/proc/{pid}")).exists() => {
                         log_fatal!(
-                            "Running pacman processes:\n{}",
-                            String::from_utf8_lossy(&o.stdout)
+                            "The lock {lock_path} is held by running process {pid}: {}",
+                            process_cmdline(pid)
                         );
                         return failed_result;
                     }
-                    _ => log_repair!(" - No active pacman processes detected."),
+                    Some(pid) => {
+                        log_repair!(" - The lock was taken by process {pid}, which is gone.")
+                    }
+                    // the host processes cannot tell whether a chroot or container uses it
+                    None if self.foreign_root() => {
+                        log_repair!(" - Not checking the host processes on a foreign root.")
+                    }
+                    None => self.check_no_lock_holders()?,
                 }
 
-                let lock_path = self.h().lockfile();
-                if std::path::Path::new(&lock_path).exists() {
+                if !confirm(
+                    &format!("Remove the stale lock file {lock_path}?"),
+                    !self.foreign_root(),
+                )? {
+                    return failed_result;
+                }
+
+                // another process may have released and taken the lock since it was inspected
+                let same_lock =
+                    std::fs::metadata(&lock_path).is_ok_and(|meta| meta.ino() == lock_meta.ino());
+
+                if same_lock {
                     log_repair!("Removing lock file at {lock_path}");
                     let _ = std::fs::remove_file(lock_path);
                 }
@@ -112,7 +222,6 @@ impl Napm {
                 // Repository/server issue - check URL, network connectivity
                 unimplemented!("handling of {error:?} aka '{error}'");
             }
-            E::TransNotPrepared => Err(Error::NothingToDo),
             E::TransNotNull | E::TransNull => {
                 unimplemented!("handling of {error:?} aka '{error}'");
             }
@@ -121,12 +230,7 @@ impl Napm {
             | E::TransNotInitialized
             | E::TransAbort
             | E::TransType
-            | E::TransNotLocked
-            | E::TransHookFailed => {
-                unimplemented!("handling of {error:?} aka '{error}'");
-            }
-            E::PkgNotFound | E::PkgIgnored => {
-                // Package not found - show error
+            | E::TransNotLocked => {
                 unimplemented!("handling of {error:?} aka '{error}'");
             }
             E::PkgInvalid => {
@@ -157,6 +261,67 @@ impl Napm {
                 // Resync databases
                 unimplemented!("handling of {error:?} aka '{error}'");
             }
+            E::RetrievePrepare => {
+                // Downloading/preparing package failed - retry
+                unimplemented!("handling of {error:?} aka '{error}'");
+            }
+            E::InvalidRegex => {
+                // Invalid regex in package/db query - abort
+                unimplemented!("handling of {error:?} aka '{error}'");
+            }
+            E::MissingCapabilitySignatures => {
+                // Some required signatures are missing
+                unimplemented!("handling of {error:?} aka '{error}'");
+            }
+            _ => Err(self.alpm_error(error, data)),
+        }
+    }
+
+    // the error reported without repairing anything, also the result of the errors napm can't repair
+    fn alpm_error(&self, error: AlpmErr, data: NapmErrorData) -> Error {
+        macro_rules! failed {
+            ($e:ident) => {{
+                log_fatal!("{}", Error::$e);
+                Error::$e
+            }};
+        }
+
+        use AlpmErr as E;
+        match error {
+            E::Ok => failed!(NoAutoRepairError),
+            E::Memory => failed!(Memory),
+            E::System => failed!(System),
+            E::BadPerms => failed!(BadPerms),
+            E::NotAFile | E::NotADir => failed!(UnexpectedType),
+            E::WrongArgs => failed!(WrongArgs),
+            E::DiskSpace => failed!(DiskSpace),
+            E::HandleNull | E::HandleNotNull => failed!(Handle),
+            E::HandleLock => {
+                log_fatal!(
+                    "The database is locked by {}, automatic lock removal is disabled",
+                    self.h().lockfile()
+                );
+                Error::DbUnlock
+            }
+            E::TransNotPrepared => Error::NothingToDo,
+            // only pre transaction hooks with AbortOnFail make the commit fail
+            E::TransHookFailed => {
+                let (name, output) =
+                    last_hook_run().unwrap_or_else(|| ("unknown".to_string(), Vec::new()));
+
+                Error::HookFailed(
+                    name,
+                    output.iter().map(|line| format!("\n  {line}")).collect(),
+                )
+            }
+            E::PkgNotFound => match data {
+                NapmErrorData::Pkg(name) => Error::PackageNotInSyncDb(name),
+                _ => Error::FindPkg,
+            },
+            E::PkgIgnored => match data {
+                NapmErrorData::Pkg(name) => Error::PackageIgnored(name),
+                _ => Error::TransAddPkg,
+            },
             E::UnsatisfiedDeps => {
                 if let NapmErrorData::UnsatisfiedDeps(missing) = &data {
                     for dep in missing {
@@ -175,7 +340,7 @@ impl Napm {
                     }
                 }
 
-                Err(Error::UnsatisfiedDeps)
+                Error::UnsatisfiedDeps
             }
             E::ConflictingDeps => {
                 if let NapmErrorData::ConflictingDeps(conflicts) = &data {
@@ -188,39 +353,41 @@ impl Napm {
                     }
                 }
 
-                Err(Error::ConflictingDeps)
+                Error::ConflictingDeps
             }
             E::FileConflicts => {
                 if let NapmErrorData::FileConflict(conflicts) = &data {
                     for c in conflicts {
-                        log_fatal!("File conflict between {} and {}", c.pkg1.name, c.pkg2.name);
-                        // TODO: Attempt to auto-remove conflicting files
+                        match &c.owner {
+                            Some(owner) => log_fatal!(
+                                "{}: /{} is also owned by {}",
+                                c.pkg.name,
+                                c.file,
+                                owner.name
+                            ),
+                            None => {
+                                log_fatal!("{}: /{} exists in the filesystem", c.pkg.name, c.file)
+                            }
+                        }
+                    }
+
+                    if !conflicts.is_empty() {
+                        log_info!("Files known to be safe to replace can be passed to --overwrite");
                     }
                 }
 
-                Err(Error::FileConflicts)
-            }
-            E::Retrieve => Err(Error::UpgradeRequired),
-            E::RetrievePrepare => {
-                // Downloading/preparing package failed - retry
-                unimplemented!("handling of {error:?} aka '{error}'");
-            }
-            E::InvalidRegex => {
-                // Invalid regex in package/db query - abort
-                unimplemented!("handling of {error:?} aka '{error}'");
-            }
-            E::Libarchive | E::Libcurl | E::ExternalDownload | E::Gpgme => {
-                // External library failure - check system libraries
-                unimplemented!("handling of {error:?} aka '{error}'");
-            }
-            E::MissingCapabilitySignatures => {
-                // Some required signatures are missing
-                unimplemented!("handling of {error:?} aka '{error}'");
+                Error::FileConflicts
             }
+            E::Retrieve => Error::UpgradeRequired,
+            // nothing to repair on our side, but the user can act on these
+            E::Libcurl | E::ExternalDownload => Error::Network(error),
+            E::Gpgme => Error::Keyring(error),
+            E::Libarchive => Error::CorruptArchive(error),
+            _ => Error::InternalALPM(error),
         }
     }
 
-    pub fn update(&mut self, dbext: &str) -> Result<bool> {
+    pub(super) fn update_dbs(&mut self, dbext: &str, force: bool) -> Result<bool> {
         log_info!(
             "Updating {} databases",
             match dbext {
@@ -232,28 +399,186 @@ impl Napm {
 
         self.h_mut().set_dbext(dbext);
 
-        match self.h_mut().syncdbs_mut().update(false) {
+        let skip_cache_servers =
+            !self.napm_config.cache_server_sync && !self.napm_config.cache_servers.is_empty();
+
+        if skip_cache_servers {
+            self.set_cache_servers(false)?;
+        }
+
+        let result = self.update_dbs_with_repair(force);
+
+        if skip_cache_servers {
+            self.set_cache_servers(true)?;
+        }
+
+        result
+    }
+
+    fn update_dbs_with_repair(&mut self, force: bool) -> Result<bool> {
+        let retries = if self.auto_repair() {
+            self.napm_config.sync_retries.unwrap_or(SYNC_RETRIES)
+        } else {
+            0
+        };
+        let mut delay = SYNC_RETRY_DELAY;
+        let mut attempt = 0;
+
+        let result = loop {
+            self.mark_locked();
+            let result = self.h_mut().syncdbs_mut().update(force);
+            self.mark_unlocked();
+
+            match result {
+                Err(e @ (AlpmErr::Retrieve | AlpmErr::Libcurl | AlpmErr::ExternalDownload))
+                    if attempt < retries =>
+                {
+                    attempt += 1;
+                    log_warn!(
+                        "Database sync failed: {e}, retrying in {}s ({attempt}/{retries})",
+                        delay.as_secs()
+                    );
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                }
+                // the network never came back, repairing would not help
+                Err(e @ (AlpmErr::Retrieve | AlpmErr::Libcurl | AlpmErr::ExternalDownload))
+                    if retries > 0 =>
+                {
+                    return Err(Error::Network(e));
+                }
+                result => break result,
+            }
+        };
+
+        match result {
             Err(e) => {
                 self.on_alpm_error(e, NapmErrorData::Empty)?;
-                self.h_mut()
-                    .syncdbs_mut()
-                    .update(false)
-                    .map_err(|_| Error::Update)
+
+                self.mark_locked();
+                let result = self.h_mut().syncdbs_mut().update(force);
+                self.mark_unlocked();
+
+                result.map_err(|_| Error::Update)
             }
             Ok(b) => Ok(b),
         }
     }
 
     pub fn trans_init(&mut self, flags: TransFlag) -> Result<()> {
+        let flags = if self.dry_run() {
+            flags | TransFlag::NO_LOCK
+        } else {
+            flags
+        };
+
         let (error, data) = {
             match self.h_mut().trans_init(flags) {
-                Ok(()) => return Ok(()),
+                Ok(()) => {
+                    self.trans_active = true;
+                    self.mark_trans_active(true);
+                    if !flags.contains(TransFlag::NO_LOCK) {
+                        self.mark_locked();
+                    }
+                    return Ok(());
+                }
                 Err(e) => (e, NapmErrorData::Empty),
             }
         };
 
         self.on_alpm_error(error, data)?;
-        self.h_mut().trans_init(flags).map_err(|_| Error::TransInit)
+        self.h_mut()
+            .trans_init(flags)
+            .map_err(|_| Error::TransInit)?;
+        self.trans_active = true;
+        self.mark_trans_active(true);
+        if !flags.contains(TransFlag::NO_LOCK) {
+            self.mark_locked();
+        }
+
+        Ok(())
+    }
+
+    pub fn trans_release(&mut self) -> Result<()> {
+        self.h_mut()
+            .trans_release()
+            .map_err(|_| Error::TransRelease)?;
+        self.trans_active = false;
+        self.mark_trans_active(false);
+        self.mark_unlocked();
+
+        Ok(())
+    }
+
+    pub fn trans_add_pkg(&mut self, pkg: &Pkg) -> Result<()> {
+        let error = {
+            let handle = self.h();
+            let package = pkg.clone().into_package_ref(handle)?;
+
+            match handle.trans_add_pkg(package) {
+                Ok(()) => return Ok(()),
+                Err(e) => e.error,
+            }
+        };
+
+        self.on_alpm_error(error, NapmErrorData::Pkg(pkg.name.clone()))?;
+
+        let handle = self.h();
+        let package = pkg.clone().into_package_ref(handle)?;
+        handle
+            .trans_add_pkg(package)
+            .map_err(|_| Error::TransAddPkg)
+    }
+
+    fn load_pkg_file<'a>(
+        handle: &'a Alpm,
+        path: &Path,
+        siglevel: SigLevel,
+    ) -> Result<LoadedPackage<'a>> {
+        handle
+            .pkg_load(path.as_os_str().as_bytes(), true, siglevel)
+            .map_err(|e| Error::PackageFileLoad(path.display().to_string(), e))
+    }
+
+    pub fn trans_add_pkg_file(&mut self, path: &Path, siglevel: SigLevel) -> Result<()> {
+        let (error, name) = {
+            let handle = self.h();
+            let package = Self::load_pkg_file(handle, path, siglevel)?;
+            let name = package.name().to_string();
+
+            match handle.trans_add_pkg(package) {
+                Ok(()) => return Ok(()),
+                Err(e) => (e.error, name),
+            }
+        };
+
+        self.on_alpm_error(error, NapmErrorData::Pkg(name))?;
+
+        let handle = self.h();
+        let package = Self::load_pkg_file(handle, path, siglevel)?;
+        handle
+            .trans_add_pkg(package)
+            .map_err(|_| Error::TransAddPkg)
+    }
+
+    pub fn trans_remove_pkg(&mut self, pkg: &Pkg) -> Result<()> {
+        let error = {
+            let handle = self.h();
+            let package = pkg.clone().into_package_ref(handle)?;
+
+            match handle.trans_remove_pkg(package) {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            }
+        };
+
+        self.on_alpm_error(error, NapmErrorData::Pkg(pkg.name.clone()))?;
+
+        let handle = self.h();
+        let package = pkg.clone().into_package_ref(handle)?;
+        handle
+            .trans_remove_pkg(package)
+            .map_err(|_| Error::TransRemovePkg)
     }
 
     pub fn trans_prepare(&mut self) -> Result<()> {
@@ -295,6 +620,14 @@ impl Napm {
     }
 
     pub fn trans_commit(&mut self) -> Result<()> {
+        self.mark_committing(true);
+        let result = self.trans_commit_attempt();
+        self.mark_committing(false);
+
+        result
+    }
+
+    fn trans_commit_attempt(&mut self) -> Result<()> {
         let (error, data) = {
             match self.h_mut().trans_commit() {
                 Ok(()) => return Ok(()),
@@ -302,17 +635,10 @@ impl Napm {
                     (
                         e.error(),
                         match e.data() {
-                            Some(CommitData::FileConflict(_)) => NapmErrorData::FileConflict(
-                                // list
-                                //     .iter()
-                                //     .map(|c| NapmConflict {
-                                //         pkg1: Pkg::from(c.package1()),
-                                //         pkg2: Pkg::from(c.package2()),
-                                //     })
-                                //     .collect()
-                                // alpm does not work (segfaults here) // TODO: do it from scratch
-                                vec![],
-                            ),
+                            // the alpm conflict list segfaults, filled in below
+                            Some(CommitData::FileConflict(_)) => {
+                                NapmErrorData::FileConflict(Vec::new())
+                            }
                             Some(CommitData::PkgInvalid(list)) => {
                                 NapmErrorData::PkgInvalid(list.iter().map(String::from).collect())
                             }
@@ -323,7 +649,166 @@ impl Napm {
             }
         };
 
+        let data = match data {
+            NapmErrorData::FileConflict(_) => {
+                NapmErrorData::FileConflict(self.trans_file_conflicts())
+            }
+            data => data,
+        };
+
         self.on_alpm_error(error, data)?;
         self.h_mut().trans_commit().map_err(|_| Error::TransCommit)
     }
+
+    // file lists of sync packages come from the cache, package files carry their own
+    fn trans_file_conflicts(&self) -> Vec<NapmFileConflict> {
+        let handle = self.h();
+        let root = Path::new(handle.root());
+        let localdb = handle.localdb();
+
+        let removed = handle
+            .trans_remove()
+            .iter()
+            .map(|pkg| pkg.name().to_string())
+            .collect::<HashSet<_>>();
+
+        let mut new_owners: HashMap<String, Pkg> = HashMap::new();
+        let mut conflicts = Vec::new();
+
+        for package in handle.trans_add() {
+            let pkg = Pkg::from(package);
+
+            let files = if package.files().files().is_empty() {
+                Self::cached_pkg_files(&pkg.repo, &pkg.name)
+            } else {
+                package
+                    .files()
+                    .files()
+                    .iter()
+                    .map(|f| String::from_utf8_lossy(f.name()).to_string())
+                    .collect()
+            };
+
+            for file in files.into_iter().filter(|f| !f.ends_with('/')) {
+                if let Some(other) = new_owners.get(&file) {
+                    conflicts.push(NapmFileConflict {
+                        pkg: pkg.clone(),
+                        file,
+                        owner: Some(other.clone()),
+                    });
+                    continue;
+                }
+
+                new_owners.insert(file, pkg.clone());
+            }
+        }
+
+        for (file, pkg) in new_owners {
+            if std::fs::symlink_metadata(root.join(&file)).is_err() {
+                continue;
+            }
+
+            let owner = localdb
+                .pkgs()
+                .into_iter()
+                .find(|local| local.files().contains(file.as_str()).is_some());
+
+            match owner {
+                Some(owner) if owner.name() == pkg.name || removed.contains(owner.name()) => {}
+                owner => conflicts.push(NapmFileConflict {
+                    pkg,
+                    file,
+                    owner: owner.map(Pkg::from),
+                }),
+            }
+        }
+
+        conflicts.sort_by(|a, b| a.file.cmp(&b.file));
+        conflicts
+    }
+
+    pub(super) fn add_overwrite_globs(&mut self, globs: &[String]) -> Result<()> {
+        for glob in globs {
+            self.h_mut()
+                .add_overwrite_file(glob.trim_start_matches('/'))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napm::fixtures::Fixture;
+    use std::fs;
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn root_from_args_reads_every_form() {
+        let env = strings(&["NAPM_ROOT=/env"]);
+
+        for (args, root) in [
+            (&["napm", "--root", "/mnt", "install"][..], "/mnt"),
+            (&["napm", "-r", "/mnt"], "/mnt"),
+            (&["napm", "--root=/mnt"], "/mnt"),
+            (&["napm", "--sysroot", "/mnt"], "/mnt"),
+            (&["napm", "--sysroot=/mnt"], "/mnt"),
+            (&["napm", "install", "foo"], "/env"),
+        ] {
+            assert_eq!(root_from_args(&strings(args), &env).as_deref(), Some(root));
+        }
+    }
+
+    #[test]
+    fn root_from_args_defaults_to_none() {
+        let args = strings(&["pacman", "-Syu"]);
+
+        assert_eq!(root_from_args(&args, &[]), None);
+        assert_eq!(root_from_args(&args, &strings(&["NAPM_ROOT="])), None);
+    }
+
+    #[test]
+    fn no_repair_returns_the_mapped_error() {
+        let fixture = Fixture::new("no-repair");
+        let lock = fixture.dir.join("db/db.lck");
+        fs::write(&lock, "").unwrap();
+
+        let mut napm = fixture.napm();
+        napm.options.no_repair = true;
+
+        // arms without a repair yet must not be reached
+        for error in [
+            AlpmErr::DbInvalid,
+            AlpmErr::TransAbort,
+            AlpmErr::PkgInvalidSig,
+            AlpmErr::SigInvalid,
+        ] {
+            assert!(matches!(
+                napm.on_alpm_error(error, NapmErrorData::Empty),
+                Err(Error::InternalALPM(e)) if e == error
+            ));
+        }
+
+        assert!(matches!(
+            napm.on_alpm_error(AlpmErr::PkgNotFound, NapmErrorData::Pkg("foo".to_string())),
+            Err(Error::PackageNotInSyncDb(name)) if name == "foo"
+        ));
+        assert!(matches!(
+            napm.on_alpm_error(AlpmErr::HandleLock, NapmErrorData::Empty),
+            Err(Error::DbUnlock)
+        ));
+        assert!(lock.exists());
+
+        napm.options.no_repair = false;
+        napm.napm_config.no_auto_repair = true;
+
+        assert!(matches!(
+            napm.on_alpm_error(AlpmErr::DbInvalid, NapmErrorData::Empty),
+            Err(Error::InternalALPM(AlpmErr::DbInvalid))
+        ));
+    }
 }
@@ -1,23 +1,138 @@
-use flate2::read::GzDecoder;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar};
 use rusqlite::Connection;
 use std::{
+    cmp::Ordering,
     collections::{HashMap, HashSet},
     fs,
     io::Read,
-    path::Path,
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        mpsc::{self, Receiver, SyncSender},
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tar::Archive;
 
 use crate::error::{Error, Result};
 use crate::log_warn;
+use crate::napm::archive::{ArchiveEntry, open_archive};
+use crate::napm::info::{InfoSource, PkgInfo};
 use crate::napm::*;
-use crate::util::require_cache;
+use crate::util::{multi_progress, offline, progress_enabled, require_cache, run_cache_update};
 
 pub const NAPM_CACHE_FILE: &str = "/var/cache/napm.sqlite";
+const NAPM_CACHE_LOCK_FILE: &str = "/var/cache/napm.sqlite.lock";
+const CACHE_BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+const CACHE_SCHEMA_VERSION: i32 = 6;
+
+const CACHE_STALE_AFTER: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+// warned at most once per run, and not at all when the user chose not to sync
+static STALE_WARNING_DONE: AtomicBool = AtomicBool::new(false);
+
+pub fn suppress_stale_cache_warning() {
+    STALE_WARNING_DONE.store(true, AtomicOrdering::Relaxed);
+}
+
+// negative cache_size is in KiB
+const CACHE_READER_CACHE_SIZE: i64 = -16 * 1024;
+const CACHE_READER_MMAP_SIZE: i64 = 256 * 1024 * 1024;
+
+// parsed rows waiting for the writer, bounded so the workers can't outrun it by much
+const CACHE_WRITE_QUEUE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchField {
+    #[default]
+    All,
+    Name,
+    Desc,
+}
+
+impl SearchField {
+    fn name(&self) -> bool {
+        *self != SearchField::Desc
+    }
+
+    fn desc(&self) -> bool {
+        *self != SearchField::Name
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    pub repos: Vec<String>,
+    pub exclude_repos: Vec<String>,
+    pub exclude_installed: bool,
+    pub match_all: bool,
+    pub field: SearchField,
+    pub min_score: f64,
+}
+
+struct RepoCacheJob {
+    repo: String,
+    path: PathBuf,
+    already_cached: HashSet<String>,
+    previous_versions: HashMap<String, String>,
+    kind: CacheKind,
+}
+
+// descriptions come from the small .db files, file lists need the much larger .files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    DescOnly,
+    Full,
+}
+
+struct CachedDesc {
+    pkg: Pkg,
+    csize: i64,
+    isize: i64,
+    depends: Vec<String>,
+    provides: Vec<String>,
+}
+
+enum CacheWrite {
+    Desc(CachedDesc),
+    Files {
+        repo: String,
+        name: String,
+        paths: Vec<String>,
+    },
+}
+
+// a read-only connection can't open a WAL database once its -wal and -shm files are gone,
+// and non-root users can't create them under /var/cache, so a writer always switches back,
+// also when it stops on an error
+pub(super) struct CacheWriter(Connection);
+
+impl Deref for CacheWriter {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.0
+    }
+}
+
+impl DerefMut for CacheWriter {
+    fn deref_mut(&mut self) -> &mut Connection {
+        &mut self.0
+    }
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.pragma_update(None, "journal_mode", "DELETE") {
+            log_warn!("Failed to switch the cache back to rollback journaling: {e}");
+        }
+    }
+}
 
 impl Napm {
-    fn init_cache_schema(conn: &Connection) -> Result<()> {
+    pub(super) fn init_cache_schema(conn: &Connection) -> Result<()> {
         conn.execute(
             "
             CREATE TABLE package_desc (
@@ -25,6 +140,9 @@ impl Napm {
                 version TEXT NOT NULL,
                 desc TEXT,
                 repo TEXT NOT NULL,
+                csize INTEGER NOT NULL DEFAULT 0,
+                isize INTEGER NOT NULL DEFAULT 0,
+                depends TEXT NOT NULL DEFAULT '',
                 files_done BOOL NOT NULL,
                 CONSTRAINT package_desc_repo_name_unique UNIQUE (repo, name)
             );
@@ -44,6 +162,28 @@ impl Napm {
             (),
         )?;
 
+        conn.execute(
+            "
+            CREATE TABLE package_provides (
+                repo TEXT NOT NULL,
+                name TEXT NOT NULL,
+                provides TEXT NOT NULL,
+                CONSTRAINT package_provides_unique UNIQUE (repo, name, provides)
+            );
+            ",
+            (),
+        )?;
+
+        // the passes the cache is complete for, 'desc' and 'files'
+        conn.execute(
+            "
+            CREATE TABLE cache_passes (
+                pass TEXT PRIMARY KEY
+            );
+            ",
+            (),
+        )?;
+
         conn.execute(
             "
             CREATE INDEX idx_package_desc_repo_name ON package_desc(repo, name);
@@ -51,6 +191,13 @@ impl Napm {
             (),
         )?;
 
+        conn.execute(
+            "
+            CREATE INDEX idx_package_provides_provides ON package_provides(provides);
+            ",
+            (),
+        )?;
+
         conn.execute(
             "
             CREATE INDEX idx_package_files_name ON package_files(name);
@@ -65,9 +212,248 @@ impl Napm {
             (),
         )?;
 
+        // the system sqlite may be built without FTS5, search falls back to the fuzzy path then,
+        // the index reads its text from package_desc and is rebuilt once per update
+        let _ = conn.execute(
+            "
+            CREATE VIRTUAL TABLE package_fts USING fts5(
+                name,
+                desc,
+                repo UNINDEXED,
+                content = 'package_desc'
+            );
+            ",
+            (),
+        );
+
+        Ok(())
+    }
+
+    fn drop_cache_tables(conn: &Connection) -> Result<()> {
+        conn.execute_batch(
+            "
+            DROP TABLE IF EXISTS package_fts;
+            DROP TABLE IF EXISTS cache_passes;
+            DROP TABLE IF EXISTS package_provides;
+            DROP TABLE IF EXISTS package_files;
+            DROP TABLE IF EXISTS package_desc;
+            ",
+        )?;
+
+        Ok(())
+    }
+
+    fn has_fts(conn: &Connection) -> bool {
+        conn.prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'package_fts'")
+            .and_then(|mut stmt| stmt.exists([]))
+            .unwrap_or(false)
+    }
+
+    fn cache_schema_version(conn: &Connection) -> Result<i32> {
+        Ok(conn.pragma_query_value(None, "user_version", |row| row.get(0))?)
+    }
+
+    // the schema version is only written once a build completes, so an interrupted
+    // first build or a truncated file is reported as invalid
+    fn cache_is_valid(conn: &Connection) -> bool {
+        let version_ok =
+            Self::cache_schema_version(conn).is_ok_and(|version| version == CACHE_SCHEMA_VERSION);
+
+        let tables_ok = conn
+            .query_row("SELECT COUNT(*) FROM package_desc", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .is_ok();
+
+        version_ok && tables_ok
+    }
+
+    // held for the whole update_cache, the file stays readable for non-root readers
+    fn lock_cache_for_update(lock_path: &Path) -> Result<fs::File> {
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_path)?;
+
+        if let Err(fs::TryLockError::WouldBlock) = lock.try_lock() {
+            log_warn!("Another napm process is updating the cache, waiting for it to finish");
+            lock.lock()?;
+        }
+
+        Ok(lock)
+    }
+
+    fn cache_update_running() -> bool {
+        fs::File::open(NAPM_CACHE_LOCK_FILE)
+            .is_ok_and(|lock| matches!(lock.try_lock_shared(), Err(fs::TryLockError::WouldBlock)))
+    }
+
+    fn wait_for_cache_update() -> Result<()> {
+        let Ok(lock) = fs::File::open(NAPM_CACHE_LOCK_FILE) else {
+            return Ok(());
+        };
+
+        if let Err(fs::TryLockError::WouldBlock) = lock.try_lock_shared() {
+            log_info!("The package cache is being rebuilt by another process, waiting for it");
+            lock.lock_shared()?;
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn open_cache() -> Result<Connection> {
+        let conn = Connection::open(NAPM_CACHE_FILE)?;
+        conn.busy_timeout(CACHE_BUSY_TIMEOUT)?;
+
+        conn.pragma_update(None, "query_only", true)?;
+        conn.pragma_update(None, "cache_size", CACHE_READER_CACHE_SIZE)?;
+        conn.pragma_update(None, "mmap_size", CACHE_READER_MMAP_SIZE)?;
+
+        Ok(conn)
+    }
+
+    pub(super) fn open_cache_writer() -> Result<CacheWriter> {
+        Self::open_cache_writer_at(Path::new(NAPM_CACHE_FILE))
+    }
+
+    fn open_cache_writer_at(cache_path: &Path) -> Result<CacheWriter> {
+        let conn = Connection::open(cache_path)?;
+        conn.busy_timeout(CACHE_BUSY_TIMEOUT)?;
+
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+
+        Ok(CacheWriter(conn))
+    }
+
+    fn has_files_pass(conn: &Connection) -> bool {
+        conn.prepare("SELECT 1 FROM cache_passes WHERE pass = 'files'")
+            .and_then(|mut stmt| stmt.exists([]))
+            .unwrap_or(false)
+    }
+
+    fn open_valid_cache(kind: CacheKind) -> Result<Connection> {
+        require_cache(kind)?;
+        Self::wait_for_cache_update()?;
+
+        let conn = Self::open_cache()?;
+
+        if !Self::cache_is_valid(&conn) {
+            drop(conn);
+
+            run_cache_update(
+                "The package cache is invalid or incomplete and needs to be rebuilt",
+                kind,
+            )?;
+
+            return Self::open_cache();
+        }
+
+        // built for search or info only, the file lists are added on first use
+        if kind == CacheKind::Full && !Self::has_files_pass(&conn) {
+            drop(conn);
+
+            run_cache_update("The file lists are not cached yet", kind)?;
+
+            return Self::open_cache();
+        }
+
+        Self::warn_if_stale(&conn);
+        Ok(conn)
+    }
+
+    // unlike open_cache, never triggers a rebuild
+    pub(super) fn open_existing_cache() -> Option<Connection> {
+        let cache_path = Path::new(NAPM_CACHE_FILE);
+
+        if !cache_path.exists() || Self::cache_update_running() {
+            return None;
+        }
+
+        let conn = Self::open_cache().ok()?;
+
+        Self::cache_is_valid(&conn).then_some(conn)
+    }
+
+    fn init_repo_updates_schema(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "
+            CREATE TABLE IF NOT EXISTS repo_updates (
+                repo TEXT PRIMARY KEY,
+                last_updated INTEGER NOT NULL
+            );
+            ",
+            (),
+        )?;
+
         Ok(())
     }
 
+    fn unix_now() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64)
+    }
+
+    // the oldest repo when none is given
+    fn cache_age_with(conn: &Connection, repo: Option<&str>) -> Option<Duration> {
+        let last_updated: i64 = conn
+            .query_row(
+                "SELECT MIN(last_updated) FROM repo_updates WHERE ?1 IS NULL OR repo = ?1",
+                [repo],
+                |row| row.get(0),
+            )
+            .ok()?;
+
+        Some(Duration::from_secs(
+            (Self::unix_now() - last_updated).max(0) as u64,
+        ))
+    }
+
+    // a cache being rebuilt counts as fresh, readers wait for it
+    pub fn cache_is_fresh() -> bool {
+        if Self::cache_update_running() {
+            return Path::new(NAPM_CACHE_FILE).exists();
+        }
+
+        Self::open_existing_cache()
+            .and_then(|conn| Self::cache_age_with(&conn, None))
+            .is_some_and(|age| age <= CACHE_STALE_AFTER)
+    }
+
+    pub fn cache_age(&self, repo: &str) -> Option<Duration> {
+        Self::cache_age_with(&Self::open_existing_cache()?, Some(repo))
+    }
+
+    fn warn_if_stale(conn: &Connection) {
+        if offline() || STALE_WARNING_DONE.swap(true, AtomicOrdering::Relaxed) {
+            return;
+        }
+
+        if let Some(age) = Self::cache_age_with(conn, None)
+            && age > CACHE_STALE_AFTER
+        {
+            log_warn!(
+                "The package cache is {} days old, run {ANSI_YELLOW}napm sync --files{ANSI_RESET} to refresh it",
+                age.as_secs() / (24 * 60 * 60)
+            );
+        }
+    }
+
+    pub(super) fn cached_pkg_files(repo: &str, name: &str) -> Vec<String> {
+        let Some(conn) = Self::open_existing_cache() else {
+            return Vec::new();
+        };
+
+        conn.prepare("SELECT path FROM package_files WHERE repo = ?1 AND name = ?2")
+            .and_then(|mut stmt| {
+                stmt.query_map((repo, name), |row| row.get(0))?
+                    .collect::<rusqlite::Result<Vec<String>>>()
+            })
+            .unwrap_or_default()
+    }
+
     fn repo_priority(&self) -> String {
         self.repo_priority_with_column_name("repo")
     }
@@ -85,16 +471,19 @@ impl Napm {
         )
     }
 
-    fn pkg_exists(conn: &Connection, pkg_name: &str) -> Result<bool> {
+    fn pkg_exists(conn: &Connection, pkg_name: &str, repos: &[String]) -> Result<bool> {
+        let mut params = vec![pkg_name.to_string()];
+        let repo_filter = Self::only_repos_filter("repo", repos, &mut params);
+
         Ok(conn
-            .prepare("SELECT 1 FROM package_desc WHERE name = ?1")?
-            .exists([pkg_name])?)
+            .prepare(&format!(
+                "SELECT 1 FROM package_desc WHERE name = ?1{repo_filter}"
+            ))?
+            .exists(rusqlite::params_from_iter(params))?)
     }
 
     fn count_archive_files(path: &Path) -> Result<usize> {
-        let file = fs::File::open(path).map_err(|_| Error::OpenArchive)?;
-        let decoder = GzDecoder::new(file);
-        let mut archive = Archive::new(decoder);
+        let mut archive = open_archive(path)?;
         Ok(archive
             .entries()
             .map_err(|_| Error::ExtractArchive)?
@@ -105,29 +494,21 @@ impl Napm {
         mp: &MultiProgress,
         total_pb: &ProgressBar,
         path: &Path,
+        len: usize,
         repo: &str,
         action: &str,
         mut f: F,
     ) -> Result<()>
     where
-        F: FnMut(&mut tar::Entry<GzDecoder<fs::File>>) -> Result<()>,
+        F: FnMut(&mut ArchiveEntry) -> Result<()>,
     {
-        let file = fs::File::open(path).map_err(|_| Error::OpenArchive)?;
-        let decoder = GzDecoder::new(file);
-        let mut archive = Archive::new(decoder);
-
-        let len = Self::count_archive_files(path)?;
+        let mut archive = open_archive(path)?;
 
         let pb = mp.insert_before(total_pb, ProgressBar::new(len as u64));
-        pb.set_style(
-            ProgressStyle::with_template(
-                "[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% {msg} {pos}/{len}",
-            )
-            .unwrap()
-            .progress_chars("=> "),
-        );
+        pb.set_style(Self::progress_style(
+            "[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% {msg} {pos}/{len}",
+        ));
         pb.set_message(format!("caching {repo}: {action}..."));
-        pb.set_length(Self::count_archive_files(path)? as u64);
 
         for entry in archive.entries().map_err(|_| Error::ExtractArchive)? {
             pb.inc(1);
@@ -141,17 +522,19 @@ impl Napm {
             f(&mut entry)?;
         }
 
-        pb.set_style(
-            ProgressStyle::with_template("[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% {msg}")
-                .unwrap()
-                .progress_chars("=> "),
-        );
+        pb.set_style(Self::progress_style(
+            "[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% {msg}",
+        ));
         pb.finish_with_message(format!("caching {repo}: {action} done"));
 
+        if !progress_enabled() {
+            log_info!("Cached {repo}: {action}");
+        }
+
         Ok(())
     }
 
-    fn parse_entry_path(entry: &tar::Entry<GzDecoder<fs::File>>) -> Result<(String, String)> {
+    fn parse_entry_path(entry: &ArchiveEntry) -> Result<(String, String)> {
         let path = entry.path().map_err(|_| Error::ExtractArchive)?;
         let parts: Vec<_> = path.iter().map(|os| os.to_string_lossy()).collect();
         if parts.len() < 2 {
@@ -160,48 +543,303 @@ impl Napm {
         Ok((parts[0].to_string(), parts[1].to_string()))
     }
 
-    pub fn update_cache(&self) -> Result<()> {
-        log_info!("Updating cache");
+    fn parse_desc_entry(repo: &str, contents: &str) -> Result<CachedDesc> {
+        let mut name = None;
+        let mut version = None;
+        let mut desc = None;
+        let mut csize = 0i64;
+        let mut isize = 0i64;
+        let mut depends = Vec::new();
+        let mut provides = Vec::new();
+
+        let mut lines = contents.lines().peekable();
+        while let Some(tag) = lines.next() {
+            match tag {
+                "%NAME%" => name = lines.next().map(str::to_string),
+                "%VERSION%" => version = lines.next().map(str::to_string),
+                "%DESC%" => {
+                    let mut block = Vec::new();
+                    while let Some(line) = lines.next_if(|l| !l.is_empty() && !Self::is_desc_tag(l))
+                    {
+                        block.push(line);
+                    }
+                    desc = Some(block.join(" "));
+                }
+                "%CSIZE%" => csize = lines.next().and_then(|l| l.parse().ok()).unwrap_or(0),
+                "%ISIZE%" => isize = lines.next().and_then(|l| l.parse().ok()).unwrap_or(0),
+                "%DEPENDS%" => {
+                    depends = lines
+                        .by_ref()
+                        .take_while(|l| !l.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                "%PROVIDES%" => {
+                    provides = lines
+                        .by_ref()
+                        .take_while(|l| !l.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                }
+                _ => {}
+            }
+        }
 
-        let cache_path = Path::new(NAPM_CACHE_FILE);
-        let needs_init = !cache_path.exists();
-        let mut conn = Connection::open(cache_path)?;
+        let (Some(name), Some(version)) = (name, version) else {
+            return Err(Error::ExtractArchive);
+        };
+
+        Ok(CachedDesc {
+            pkg: Pkg {
+                repo: repo.to_string(),
+                name,
+                version,
+                desc: desc.unwrap_or_default(),
+            },
+            csize,
+            isize,
+            depends,
+            provides,
+        })
+    }
 
-        if needs_init {
-            log_warn!("Creating the cache from scratch, this will take some time...");
-            Self::init_cache_schema(&conn)?;
-        }
+    // runs on a worker thread per repo, the parsed rows go to the single sqlite writer
+    fn parse_repo_archive(
+        mp: &MultiProgress,
+        total_pb: &ProgressBar,
+        job: &RepoCacheJob,
+        sender: SyncSender<CacheWrite>,
+    ) -> Result<Vec<String>> {
+        let RepoCacheJob {
+            repo,
+            path,
+            already_cached,
+            previous_versions,
+            kind,
+        } = job;
 
-        let handle = self.h();
-        let sync_dir = Path::new(handle.dbpath()).join("sync");
+        let len = Self::count_archive_files(path)?;
+        total_pb.inc_length(match kind {
+            CacheKind::DescOnly => len as u64,
+            CacheKind::Full => 2 * len as u64,
+        });
+
+        let mut regressions = Vec::new();
+        let mut id_to_pkg: HashMap<String, String> = HashMap::new();
+
+        Self::process_archive(mp, total_pb, path, len, repo, "descriptions", |entry| {
+            let (identifier, file_name) = Self::parse_entry_path(entry)?;
+            if file_name != "desc" || already_cached.contains(&identifier) {
+                return Ok(());
+            }
 
-        let mut total_work = 0usize;
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            let contents = String::from_utf8(contents).map_err(|_| Error::ExtractArchive)?;
 
-        for entry in fs::read_dir(&sync_dir)? {
-            let entry = entry?;
-            let path = entry.path();
+            let desc = Self::parse_desc_entry(repo, &contents)?;
 
-            let Some(fname) = path.file_name().and_then(|n| n.to_str()) else {
-                continue;
+            id_to_pkg.insert(identifier, desc.pkg.name.clone());
+
+            if let Some(old) = previous_versions.get(&desc.pkg.name)
+                && alpm::vercmp(desc.pkg.version.as_str(), old.as_str()) == Ordering::Less
+            {
+                regressions.push(format!(
+                    "{repo}/{} {old} => {}",
+                    Pkg::format_name(&desc.pkg.name, None),
+                    desc.pkg.version
+                ));
+            }
+
+            sender
+                .send(CacheWrite::Desc(desc))
+                .map_err(|_| Error::System)
+        })?;
+
+        if *kind == CacheKind::DescOnly {
+            return Ok(regressions);
+        }
+
+        // every desc of the repo is queued before its first file list
+        Self::process_archive(mp, total_pb, path, len, repo, "files", |entry| {
+            let (identifier, file_name) = Self::parse_entry_path(entry)?;
+            if file_name != "files" || already_cached.contains(&identifier) {
+                return Ok(());
+            }
+
+            let Some(package_name) = id_to_pkg.get(&identifier) else {
+                log_warn!("Package {identifier} found in files, but not in desc");
+                return Ok(());
             };
-            if !fname.ends_with(".files") {
-                continue;
+
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            let contents = String::from_utf8(contents).map_err(|_| Error::ExtractArchive)?;
+
+            sender
+                .send(CacheWrite::Files {
+                    repo: repo.clone(),
+                    name: package_name.clone(),
+                    paths: contents.lines().skip(1).map(str::to_string).collect(),
+                })
+                .map_err(|_| Error::System)
+        })?;
+
+        Ok(regressions)
+    }
+
+    // returns the number of descriptions written
+    fn write_cache(
+        conn: &mut Connection,
+        has_fts: bool,
+        receiver: Receiver<CacheWrite>,
+    ) -> Result<usize> {
+        let tx = conn.transaction()?;
+        let mut descs_written = 0;
+
+        {
+            let mut insert_desc = tx.prepare(
+                "INSERT OR REPLACE INTO package_desc (name, version, desc, repo, csize, isize, depends, files_done) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, false)",
+            )?;
+            let mut delete_files =
+                tx.prepare("DELETE FROM package_files WHERE repo = ?1 AND name = ?2")?;
+            let mut insert_file =
+                tx.prepare("INSERT INTO package_files (repo, name, path) VALUES (?1, ?2, ?3)")?;
+            let mut delete_provides =
+                tx.prepare("DELETE FROM package_provides WHERE repo = ?1 AND name = ?2")?;
+            let mut insert_provides = tx.prepare(
+                "INSERT OR IGNORE INTO package_provides (repo, name, provides) VALUES (?1, ?2, ?3)",
+            )?;
+            let mut mark_done = tx.prepare(
+                "UPDATE package_desc SET files_done = true WHERE repo = ?1 AND name = ?2",
+            )?;
+
+            for write in receiver {
+                match write {
+                    CacheWrite::Desc(CachedDesc {
+                        pkg,
+                        csize,
+                        isize,
+                        depends,
+                        provides,
+                    }) => {
+                        insert_desc.execute((
+                            &pkg.name,
+                            &pkg.version,
+                            &pkg.desc,
+                            &pkg.repo,
+                            csize,
+                            isize,
+                            depends.join("\n"),
+                        ))?;
+
+                        descs_written += 1;
+
+                        delete_provides.execute((&pkg.repo, &pkg.name))?;
+
+                        for provided in &provides {
+                            insert_provides.execute((&pkg.repo, &pkg.name, provided))?;
+                        }
+                    }
+                    CacheWrite::Files { repo, name, paths } => {
+                        delete_files.execute((&repo, &name))?;
+
+                        for path in &paths {
+                            insert_file.execute((&repo, &name, path))?;
+                        }
+
+                        mark_done.execute((&repo, &name))?;
+                    }
+                }
             }
+        }
 
-            total_work += 2 * Self::count_archive_files(&path)?;
+        // deleting single rows would scan the whole index, its columns can't be looked up
+        if has_fts && descs_written > 0 {
+            tx.execute(
+                "INSERT INTO package_fts (package_fts) VALUES ('rebuild')",
+                (),
+            )?;
         }
 
-        let mp = MultiProgress::new();
-        let total_pb = mp.add(ProgressBar::new(total_work as u64));
+        tx.commit()?;
 
-        total_pb.set_style(
-            ProgressStyle::with_template(
-                "[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% caching total {pos}/{len} ETA {eta}"
-            )
-            .unwrap()
-            .progress_chars("=> "),
+        Ok(descs_written)
+    }
+
+    pub fn update_cache(&self, force: bool, kind: CacheKind) -> Result<()> {
+        let regressions = self.update_cache_at(
+            Path::new(NAPM_CACHE_FILE),
+            Path::new(NAPM_CACHE_LOCK_FILE),
+            force,
+            kind,
+        )?;
+
+        // may be a legitimate rollback, but also a stale or malicious mirror
+        if !regressions.is_empty() {
+            log_warn!("Some package versions went backwards since the last update:");
+            for regression in &regressions {
+                log_warn!(" - {regression}");
+            }
+        }
+
+        Ok(())
+    }
+
+    // returns the packages whose version went backwards
+    fn update_cache_at(
+        &self,
+        cache_path: &Path,
+        lock_path: &Path,
+        force: bool,
+        kind: CacheKind,
+    ) -> Result<Vec<String>> {
+        log_info!(
+            "Updating cache{}",
+            if kind == CacheKind::DescOnly {
+                " of the package descriptions"
+            } else {
+                ""
+            }
         );
 
+        let _lock = Self::lock_cache_for_update(lock_path)?;
+
+        let mut fresh_file = !cache_path.exists();
+        let mut needs_init = fresh_file;
+        let mut conn = Self::open_cache_writer_at(cache_path)?;
+
+        if !needs_init && !Self::cache_is_valid(&conn) {
+            log_warn!("The cache is invalid, incomplete or outdated, rebuilding it");
+
+            // keeps the transaction history, unless the file itself is broken
+            if Self::drop_cache_tables(&conn).is_err() {
+                drop(conn);
+                fs::remove_file(cache_path)?;
+                conn = Self::open_cache_writer_at(cache_path)?;
+                fresh_file = true;
+            }
+
+            needs_init = true;
+        }
+
+        if needs_init {
+            log_warn!("Creating the cache from scratch, this will take some time...");
+            Self::init_cache_schema(&conn)?;
+
+            // a build interrupted midway is detected through user_version and rebuilt anyway,
+            // but the transaction history in an existing file must stay durable
+            if fresh_file {
+                conn.pragma_update(None, "synchronous", "OFF")?;
+                conn.pragma_update(None, "journal_mode", "MEMORY")?;
+            }
+        }
+
+        let handle = self.h();
+        let sync_dir = Path::new(handle.dbpath()).join("sync");
+
+        let mut jobs = Vec::new();
+
         for entry in fs::read_dir(&sync_dir)? {
             let entry = entry?;
             let path = entry.path();
@@ -209,131 +847,151 @@ impl Napm {
             let Some(fname) = path.file_name().and_then(|n| n.to_str()) else {
                 continue;
             };
-            if !fname.ends_with(".files") {
-                continue;
-            }
+            let ext = match kind {
+                CacheKind::DescOnly => ".db",
+                CacheKind::Full => ".files",
+            };
 
-            let repo = fname.trim_end_matches(".files");
+            let Some(repo) = fname.strip_suffix(ext).map(str::to_string) else {
+                continue;
+            };
 
-            let already_cached: HashSet<String> = {
-                let mut stmt = conn.prepare("SELECT name || '-' || version FROM package_desc WHERE repo = ?1 AND files_done")?;
+            // forcing re-reads every package, a bad file list would otherwise stick around
+            let already_cached: HashSet<String> = if force {
+                HashSet::new()
+            } else {
+                let mut stmt = conn.prepare(match kind {
+                    CacheKind::DescOnly => "SELECT name || '-' || version FROM package_desc WHERE repo = ?1",
+                    CacheKind::Full => "SELECT name || '-' || version FROM package_desc WHERE repo = ?1 AND files_done",
+                })?;
 
                 stmt.query_map([&repo], |row| row.get(0))?
                     .filter_map(|r| r.ok())
                     .collect()
             };
 
-            let mut id_to_pkg: HashMap<String, String> = HashMap::new();
+            let previous_versions: HashMap<String, String> = {
+                let mut stmt =
+                    conn.prepare("SELECT name, version FROM package_desc WHERE repo = ?1")?;
 
-            Self::process_archive(&mp, &total_pb, &path, repo, "descriptions", |entry| {
-                let (identifier, file_name) = Self::parse_entry_path(entry)?;
-                if file_name != "desc" || already_cached.contains(&identifier) {
-                    return Ok(());
-                }
+                stmt.query_map([&repo], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .filter_map(|r| r.ok())
+                    .collect()
+            };
 
-                let mut contents = Vec::new();
-                entry.read_to_end(&mut contents)?;
-                let contents = String::from_utf8(contents).map_err(|_| Error::ExtractArchive)?;
-
-                let mut name = None;
-                let mut version = None;
-                let mut desc = None;
-
-                let mut lines = contents.lines();
-                while let Some(tag) = lines.next() {
-                    match tag {
-                        "%NAME%" => name = lines.next().map(str::to_string),
-                        "%VERSION%" => version = lines.next().map(str::to_string),
-                        "%DESC%" => desc = lines.next().map(str::to_string),
-                        _ => {}
-                    }
-                }
+            jobs.push(RepoCacheJob {
+                repo,
+                path,
+                already_cached,
+                previous_versions,
+                kind,
+            });
+        }
 
-                let pkg_name = name.clone().unwrap();
-                id_to_pkg.insert(identifier.clone(), pkg_name.clone());
+        let has_fts = Self::has_fts(&conn);
 
-                let pkg = Pkg {
-                    repo: repo.to_string(),
-                    name: pkg_name,
-                    version: version.unwrap(),
-                    desc: desc.unwrap_or_default(),
-                };
+        let mp = multi_progress();
+        // the workers add their share once they have counted their archive
+        let total_pb = mp.add(ProgressBar::new(0));
 
-                conn.execute(
-                    "INSERT OR REPLACE INTO package_desc (name, version, desc, repo, files_done) VALUES (?1, ?2, ?3, ?4, false)",
-                    (&pkg.name, &pkg.version, &pkg.desc, &pkg.repo),
-                )?;
+        total_pb.set_style(Self::progress_style(
+            "[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% {msg} {pos}/{len} ETA {eta}",
+        ));
+        total_pb.set_message("caching total");
 
-                Ok(())
-            })?;
+        // sqlite only takes one writer, so the workers just parse and this thread writes
+        let (sender, receiver) = mpsc::sync_channel(CACHE_WRITE_QUEUE);
 
-            Self::process_archive(&mp, &total_pb, &path, repo, "files", |entry| {
-                let (identifier, file_name) = Self::parse_entry_path(entry)?;
-                if file_name != "files" || already_cached.contains(&identifier) {
-                    return Ok(());
-                }
+        let (written, parsed) = thread::scope(|scope| {
+            let workers: Vec<_> = jobs
+                .iter()
+                .map(|job| {
+                    let sender = sender.clone();
+                    let (mp, total_pb) = (&mp, &total_pb);
+                    scope.spawn(move || Self::parse_repo_archive(mp, total_pb, job, sender))
+                })
+                .collect();
 
-                if let Some(package_name) = id_to_pkg.get(&identifier) {
-                    let tx = conn.transaction()?;
+            drop(sender);
 
-                    tx.execute(
-                        "DELETE FROM package_files WHERE repo = ?1 AND name = ?2",
-                        (&repo, &package_name),
-                    )?;
+            let written = Self::write_cache(&mut conn, has_fts, receiver);
 
-                    let mut contents = Vec::new();
-                    entry.read_to_end(&mut contents)?;
-                    let contents =
-                        String::from_utf8(contents).map_err(|_| Error::ExtractArchive)?;
+            let parsed: Vec<_> = workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap_or(Err(Error::System)))
+                .collect();
 
-                    for line in contents.lines().skip(1) {
-                        tx.execute(
-                            "INSERT INTO package_files (repo, name, path) VALUES (?1, ?2, ?3)",
-                            (&repo, &package_name, &line),
-                        )?;
-                    }
+            (written, parsed)
+        });
 
-                    tx.execute(
-                        "UPDATE package_desc SET files_done = true WHERE repo = ?1 AND name = ?2",
-                        (&repo, &package_name),
-                    )?;
+        // a failed write closes the channel, which makes the workers fail as well
+        let descs_written = written?;
 
-                    tx.commit()?;
-                } else {
-                    log_warn!("Package {identifier} found in files, but not in desc");
-                }
+        conn.execute(
+            "INSERT OR IGNORE INTO cache_passes (pass) VALUES ('desc')",
+            (),
+        )?;
 
-                Ok(())
-            })?;
+        // new packages have no file list yet, the next files pass has to add them
+        match kind {
+            CacheKind::Full => {
+                conn.execute(
+                    "INSERT OR IGNORE INTO cache_passes (pass) VALUES ('files')",
+                    (),
+                )?;
+            }
+            CacheKind::DescOnly if descs_written > 0 => {
+                conn.execute("DELETE FROM cache_passes WHERE pass = 'files'", ())?;
+            }
+            CacheKind::DescOnly => {}
         }
 
-        total_pb.set_style(
-            ProgressStyle::with_template(
-                "[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% caching done",
-            )
-            .unwrap()
-            .progress_chars("=> "),
-        );
-        total_pb.finish();
+        Self::init_repo_updates_schema(&conn)?;
 
-        Ok(())
-    }
+        let now = Self::unix_now();
+        for job in &jobs {
+            conn.execute(
+                "INSERT OR REPLACE INTO repo_updates (repo, last_updated) VALUES (?1, ?2)",
+                (&job.repo, now),
+            )?;
+        }
+
+        let mut regressions = Vec::new();
+        for result in parsed {
+            regressions.extend(result?);
+        }
 
-    pub fn info(&self, pkg_name: &str) -> Result<Pkg> {
-        require_cache()?;
+        total_pb.set_style(Self::progress_style(
+            "[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% {msg}",
+        ));
+        total_pb.finish_with_message("caching done");
 
-        let cache_path = Path::new(NAPM_CACHE_FILE);
+        conn.pragma_update(None, "user_version", CACHE_SCHEMA_VERSION)?;
 
-        let conn = Connection::open(cache_path)?;
+        Ok(regressions)
+    }
+
+    fn is_desc_tag(line: &str) -> bool {
+        line.len() > 2 && line.starts_with('%') && line.ends_with('%')
+    }
+
+    pub(super) fn cached_info(
+        &self,
+        conn: &Connection,
+        pkg_name: &str,
+        repos: &[String],
+    ) -> Result<PkgInfo> {
+        let mut params = vec![pkg_name.to_string()];
+        let repo_filter = Self::only_repos_filter("repo", repos, &mut params);
 
         let mut stmt = conn.prepare(&format!(
             "
-            SELECT name, version, repo, desc
+            SELECT name, version, repo, desc, csize, isize, depends
             FROM package_desc
             WHERE name = ?1 AND repo = (
                 SELECT repo
                 FROM package_desc
-                WHERE name = ?1
+                WHERE name = ?1{repo_filter}
                 ORDER BY {}
                 LIMIT 1
             )
@@ -342,31 +1000,61 @@ impl Napm {
         ))?;
 
         use rusqlite::Error as E;
-        match stmt.query_one([pkg_name], |row| {
-            Ok(Pkg {
-                name: row.get(0)?,
-                version: row.get(1)?,
-                repo: row.get(2)?,
-                desc: row.get(3)?,
+        match stmt.query_one(rusqlite::params_from_iter(params), |row| {
+            Ok(PkgInfo {
+                pkg: Pkg {
+                    name: row.get(0)?,
+                    version: row.get(1)?,
+                    repo: row.get(2)?,
+                    desc: row.get(3)?,
+                },
+                csize: row.get::<_, i64>(4)?.max(0) as u64,
+                isize: row.get::<_, i64>(5)?.max(0) as u64,
+                depends: row
+                    .get::<_, String>(6)?
+                    .lines()
+                    .map(str::to_string)
+                    .collect(),
+                source: InfoSource::Cache,
             })
         }) {
-            Ok(pkg) => Ok(pkg),
+            Ok(info) => Ok(info),
             Err(E::QueryReturnedNoRows) => Err(Error::PackageNotFound(pkg_name.to_string())),
             Err(err) => Err(Error::CacheDatabaseError(err)),
         }
     }
 
-    pub fn files(&self, pkg_name: &str, with_dirs: bool) -> Result<Vec<String>> {
-        require_cache()?;
+    pub fn cached_download_size(&self, pkgs: &[Pkg]) -> Option<u64> {
+        let conn = Self::open_existing_cache()?;
 
-        let cache_path = Path::new(NAPM_CACHE_FILE);
+        let mut stmt = conn
+            .prepare("SELECT csize FROM package_desc WHERE repo = ?1 AND name = ?2")
+            .ok()?;
 
-        let conn = Connection::open(cache_path)?;
+        let mut total = 0u64;
 
-        if !Self::pkg_exists(&conn, pkg_name)? {
+        for pkg in pkgs {
+            let csize: i64 = stmt
+                .query_one((&pkg.repo, &pkg.name), |row| row.get(0))
+                .ok()?;
+            total += csize.max(0) as u64;
+        }
+
+        Some(total)
+    }
+
+    pub fn files(&self, pkg_name: &str, with_dirs: bool, repos: &[String]) -> Result<Vec<String>> {
+        self.check_repos(repos)?;
+
+        let conn = Self::open_valid_cache(CacheKind::Full)?;
+
+        if !Self::pkg_exists(&conn, pkg_name, repos)? {
             return Err(Error::PackageNotFound(pkg_name.to_string()));
         }
 
+        let mut params = vec![pkg_name.to_string()];
+        let repo_filter = Self::only_repos_filter("repo", repos, &mut params);
+
         let mut stmt = conn.prepare(&format!(
             "
             SELECT '/' || path
@@ -374,81 +1062,166 @@ impl Napm {
             WHERE name = ?1 AND repo = (
                 SELECT repo
                 FROM package_desc
-                WHERE name = ?1
+                WHERE name = ?1{repo_filter}
                 ORDER BY {}
                 LIMIT 1
             ) {}
             ",
-            self.repo_priority(),
-            if with_dirs {
-                ""
-            } else {
-                "AND path NOT LIKE '%/'"
-            }
+            self.repo_priority(),
+            if with_dirs {
+                ""
+            } else {
+                "AND path NOT LIKE '%/'"
+            }
+        ))?;
+
+        Ok(stmt
+            .query_map(rusqlite::params_from_iter(params), |row| row.get(0))?
+            .filter_map(|r| r.ok())
+            .collect())
+    }
+
+    pub fn find_packages_by_file(&self, path: &str, exact: bool) -> Result<Vec<(Pkg, String)>> {
+        let conn = Self::open_valid_cache(CacheKind::Full)?;
+
+        Self::query_packages_by_file(&conn, &self.repo_priority(), path, exact)
+    }
+
+    fn query_packages_by_file(
+        conn: &Connection,
+        repo_priority: &str,
+        path: &str,
+        exact: bool,
+    ) -> Result<Vec<(Pkg, String)>> {
+        let (op, pattern) = if exact {
+            ("=", path.to_string())
+        } else {
+            ("LIKE", format!("%{path}"))
+        };
+
+        // the winning repo of each package is resolved once instead of for every matched file
+        let mut stmt = conn.prepare(&format!(
+            "
+            WITH winner AS MATERIALIZED (
+                SELECT name, repo
+                FROM (
+                    SELECT
+                        name,
+                        repo,
+                        ROW_NUMBER() OVER (PARTITION BY name ORDER BY {}) AS rank
+                    FROM package_desc
+                )
+                WHERE rank = 1
+            )
+            SELECT
+                d.name,
+                d.version,
+                d.desc,
+                d.repo,
+                '/' || f.path
+            FROM package_files AS f
+            JOIN winner        AS w ON f.name = w.name AND f.repo = w.repo
+            JOIN package_desc  AS d ON d.name = w.name AND d.repo = w.repo
+            WHERE '/' || f.path {op} ?1
+            ORDER BY d.name, f.path;
+            ",
+            repo_priority,
         ))?;
 
         Ok(stmt
-            .query_map([pkg_name], |row| row.get(0))?
+            .query_map([&pattern], |row| {
+                Ok((
+                    Pkg {
+                        name: row.get(0)?,
+                        version: row.get(1)?,
+                        desc: row.get(2)?,
+                        repo: row.get(3)?,
+                    },
+                    row.get(4)?,
+                ))
+            })?
             .filter_map(|r| r.ok())
             .collect())
     }
 
-    pub fn find_packages_by_file(&self, path: &str, exact: bool) -> Result<Vec<(Pkg, String)>> {
-        require_cache()?;
+    // libssl.so.3 is provided as libssl.so=3-64, the soname version and the architecture bits
+    fn parse_soname(target: &str) -> Option<(&str, Option<&str>)> {
+        if target.contains('/') {
+            return None;
+        }
 
-        let cache_path = Path::new(NAPM_CACHE_FILE);
+        let end = target.find(".so")? + ".so".len();
+        let (base, rest) = target.split_at(end);
 
-        let conn = Connection::open(cache_path)?;
+        match rest.chars().next() {
+            None => Some((base, None)),
+            Some('.' | '=') => Some((base, Some(&rest[1..]).filter(|v| !v.is_empty()))),
+            Some(_) => None,
+        }
+    }
 
+    pub fn find_packages_by_soname(&self, target: &str) -> Result<Vec<(Pkg, String)>> {
+        let Some((base, version)) = Self::parse_soname(target) else {
+            return Ok(Vec::new());
+        };
+
+        let conn = Self::open_valid_cache(CacheKind::DescOnly)?;
+
+        // the same winner per package as for files, see find_packages_by_file
         let mut stmt = conn.prepare(&format!(
             "
+            WITH winner AS MATERIALIZED (
+                SELECT name, repo
+                FROM (
+                    SELECT
+                        name,
+                        repo,
+                        ROW_NUMBER() OVER (PARTITION BY name ORDER BY {}) AS rank
+                    FROM package_desc
+                )
+                WHERE rank = 1
+            )
             SELECT
                 d.name,
                 d.version,
                 d.desc,
                 d.repo,
-                '/' || f.path
-            FROM package_files AS f
-            JOIN package_desc  AS d ON f.name = d.name AND f.repo = d.repo
-            WHERE
-                {}
-            AND d.repo = (
-                SELECT d2.repo
-                FROM package_desc AS d2
-                WHERE d2.name = d.name
-                ORDER BY {}
-                LIMIT 1
-            )
-            ORDER BY d.name, f.path;
+                p.provides
+            FROM package_provides AS p
+            JOIN winner           AS w ON p.name = w.name AND p.repo = w.repo
+            JOIN package_desc     AS d ON d.name = w.name AND d.repo = w.repo
+            WHERE p.provides = ?1 OR substr(p.provides, 1, length(?1) + 1) = ?1 || '='
+            ORDER BY d.name, p.provides;
             ",
-            if exact {
-                "'/' || f.path = ?1"
-            } else {
-                "'/' || f.path LIKE ?1"
-            },
-            self.repo_priority_with_column_name("d2.repo"),
+            self.repo_priority(),
         ))?;
 
+        let matches_version = |provided: &str| {
+            let Some(version) = version else {
+                return true;
+            };
+
+            let provided = provided
+                .split_once('=')
+                .map_or("", |(_, v)| v.split('-').next().unwrap_or(v));
+
+            version == provided || version.starts_with(&format!("{provided}."))
+        };
+
         Ok(stmt
-            .query_map(
-                [&if exact {
-                    path.to_string()
-                } else {
-                    format!("%{path}")
-                }],
-                |row| {
-                    Ok((
-                        Pkg {
-                            name: row.get(0)?,
-                            version: row.get(1)?,
-                            desc: row.get(2)?,
-                            repo: row.get(3)?,
-                        },
-                        row.get(4)?,
-                    ))
-                },
-            )?
+            .query_map([base], |row| {
+                Ok((
+                    Pkg {
+                        name: row.get(0)?,
+                        version: row.get(1)?,
+                        desc: row.get(2)?,
+                        repo: row.get(3)?,
+                    },
+                    row.get::<_, String>(4)?,
+                ))
+            })?
             .filter_map(|r| r.ok())
+            .filter(|(_, provided)| matches_version(provided))
             .collect())
     }
 
@@ -459,23 +1232,43 @@ impl Napm {
             .collect()
     }
 
-    fn select_candidates(&self, conn: &Connection, query_words: &[String]) -> Result<Vec<Pkg>> {
+    fn select_candidates(
+        &self,
+        conn: &Connection,
+        word_groups: &[Vec<String>],
+        options: &SearchOptions,
+    ) -> Result<Vec<Pkg>> {
         let mut where_clauses = Vec::new();
         let mut params = Vec::new();
 
-        for q in query_words {
-            where_clauses.push("(LOWER(name) LIKE ? OR LOWER(desc) LIKE ?)");
-            let like = format!("%{}%", q);
-            params.push(like.clone());
-            params.push(like);
+        for group in word_groups {
+            let mut group_clauses = Vec::new();
+
+            for q in group {
+                let like = format!("%{}%", q);
+
+                if options.field.name() {
+                    group_clauses.push("LOWER(name) LIKE ?");
+                    params.push(like.clone());
+                }
+
+                if options.field.desc() {
+                    group_clauses.push("LOWER(desc) LIKE ?");
+                    params.push(like);
+                }
+            }
+
+            where_clauses.push(format!("({})", group_clauses.join(" OR ")));
         }
 
+        let repo_filter = Self::repo_filter("repo", options, &mut params);
+
         let sql = format!(
             "
             WITH matched AS (
                 SELECT *
                 FROM package_desc
-                WHERE {}
+                WHERE ({}) {}
             )
             SELECT name, version, desc, repo
             FROM matched AS d
@@ -487,7 +1280,8 @@ impl Napm {
                 LIMIT 1
             )
             ",
-            where_clauses.join(" OR "),
+            where_clauses.join(if options.match_all { " AND " } else { " OR " }),
+            repo_filter,
             self.repo_priority_with_column_name("d2.repo")
         );
 
@@ -505,7 +1299,103 @@ impl Napm {
         Ok(rows.filter_map(rusqlite::Result::ok).collect())
     }
 
+    fn repo_filter(col_name: &str, options: &SearchOptions, params: &mut Vec<String>) -> String {
+        let mut filter = Self::only_repos_filter(col_name, &options.repos, params);
+
+        if !options.exclude_repos.is_empty() {
+            params.extend(options.exclude_repos.iter().cloned());
+
+            filter.push_str(&format!(
+                " AND {col_name} NOT IN ({})",
+                vec!["?"; options.exclude_repos.len()].join(", ")
+            ));
+        }
+
+        filter
+    }
+
+    fn only_repos_filter(col_name: &str, repos: &[String], params: &mut Vec<String>) -> String {
+        if repos.is_empty() {
+            return String::new();
+        }
+
+        params.extend(repos.iter().cloned());
+
+        format!(" AND {col_name} IN ({})", vec!["?"; repos.len()].join(", "))
+    }
+
+    fn fts_search(
+        &self,
+        conn: &Connection,
+        word_groups: &[Vec<String>],
+        options: &SearchOptions,
+    ) -> Result<Vec<(f64, Pkg)>> {
+        let match_expr = word_groups
+            .iter()
+            .map(|group| {
+                let alternatives = group
+                    .iter()
+                    .map(|w| format!("\"{}\"*", w.replace('"', "\"\"")))
+                    .collect::<Vec<_>>()
+                    .join(" OR ");
+                format!("({alternatives})")
+            })
+            .collect::<Vec<_>>()
+            .join(if options.match_all { " AND " } else { " OR " });
+
+        let match_expr = match options.field {
+            SearchField::All => match_expr,
+            SearchField::Name => format!("name : ({match_expr})"),
+            SearchField::Desc => format!("desc : ({match_expr})"),
+        };
+
+        let mut params = vec![match_expr];
+        let repo_filter = Self::repo_filter("d.repo", options, &mut params);
+
+        let sql = format!(
+            "
+            WITH matched AS MATERIALIZED (
+                SELECT d.name, d.version, d.desc, d.repo, bm25(package_fts, 5.0, 1.5) AS rank
+                FROM package_fts AS f
+                JOIN package_desc AS d ON d.rowid = f.rowid
+                WHERE package_fts MATCH ? {}
+            )
+            SELECT name, version, desc, repo, rank
+            FROM matched AS d
+            WHERE repo = (
+                SELECT repo
+                FROM matched AS d2
+                WHERE d2.name = d.name
+                ORDER BY {}
+                LIMIT 1
+            )
+            ORDER BY rank
+            ",
+            repo_filter,
+            self.repo_priority_with_column_name("d2.repo")
+        );
+
+        let mut stmt = conn.prepare(&sql)?;
+
+        // bm25 is negative, lower is better
+        let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+            Ok((
+                -row.get::<_, f64>(4)?,
+                Pkg {
+                    name: row.get(0)?,
+                    version: row.get(1)?,
+                    desc: row.get(2)?,
+                    repo: row.get(3)?,
+                },
+            ))
+        })?;
+
+        Ok(rows.filter_map(rusqlite::Result::ok).collect())
+    }
+
     fn levenshtein_cutoff(a: &str, b: &str, max_dist: usize) -> Option<usize> {
+        let a = a.chars().collect::<Vec<_>>();
+        let b = b.chars().collect::<Vec<_>>();
         let la = a.len();
         let lb = b.len();
 
@@ -516,11 +1406,11 @@ impl Napm {
         let mut prev: Vec<usize> = (0..=lb).collect();
         let mut curr = vec![0; lb + 1];
 
-        for (i, ca) in a.chars().enumerate() {
+        for (i, ca) in a.iter().enumerate() {
             curr[0] = i + 1;
             let mut min_row = curr[0];
 
-            for (j, cb) in b.chars().enumerate() {
+            for (j, cb) in b.iter().enumerate() {
                 let cost = if ca == cb { 0 } else { 1 };
                 curr[j + 1] = (prev[j + 1] + 1).min(curr[j] + 1).min(prev[j] + cost);
 
@@ -538,7 +1428,8 @@ impl Napm {
         (d <= max_dist).then_some(d)
     }
 
-    fn expand_query_words(conn: &Connection, query_words: &[String]) -> Result<Vec<String>> {
+    // one group per query word, holding the word and its typo expansions
+    fn expand_query_words(conn: &Connection, query_words: &[String]) -> Result<Vec<Vec<String>>> {
         let mut stmt = conn.prepare("SELECT DISTINCT LOWER(name) FROM package_desc")?;
 
         let dict: Vec<String> = stmt
@@ -546,26 +1437,87 @@ impl Napm {
             .filter_map(rusqlite::Result::ok)
             .collect();
 
-        const MAX_DISTANCE: usize = 2;
-        const MAX_LEN_DIFF: usize = 2;
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = dict.len().div_ceil(threads).max(1);
 
-        let mut expanded = std::collections::HashSet::new();
+        let mut groups = Vec::new();
 
         for q in query_words {
+            let mut expanded = HashSet::new();
             expanded.insert(q.clone());
 
-            for w in &dict {
-                if w.len().abs_diff(q.len()) > MAX_LEN_DIFF {
-                    continue;
-                }
+            std::thread::scope(|scope| {
+                let workers = dict
+                    .chunks(chunk_size)
+                    .map(|chunk| scope.spawn(move || Self::close_words(chunk, q)))
+                    .collect::<Vec<_>>();
 
-                if Self::levenshtein_cutoff(w, q, MAX_DISTANCE).is_some() {
-                    expanded.insert(w.clone());
+                for worker in workers {
+                    expanded.extend(worker.join().unwrap());
                 }
-            }
+            });
+
+            groups.push(expanded.into_iter().collect());
         }
 
-        Ok(expanded.into_iter().collect())
+        Ok(groups)
+    }
+
+    pub fn cached_pkg_names(prefix: &str) -> Vec<String> {
+        let Some(conn) = Self::open_existing_cache() else {
+            return Vec::new();
+        };
+
+        let Ok(mut stmt) = conn.prepare(
+            "SELECT DISTINCT name FROM package_desc WHERE substr(name, 1, length(?1)) = ?1 ORDER BY name",
+        ) else {
+            return Vec::new();
+        };
+
+        match stmt.query_map([prefix], |row| row.get(0)) {
+            Ok(rows) => rows.filter_map(rusqlite::Result::ok).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn suggest_names(&self, name: &str) -> Vec<String> {
+        const MAX_SUGGESTIONS: usize = 3;
+
+        let Some(conn) = Self::open_existing_cache() else {
+            return Vec::new();
+        };
+
+        let Ok(mut stmt) = conn.prepare("SELECT DISTINCT name FROM package_desc") else {
+            return Vec::new();
+        };
+
+        let dict: Vec<String> = match stmt.query_map([], |row| row.get(0)) {
+            Ok(rows) => rows.filter_map(rusqlite::Result::ok).collect(),
+            Err(_) => return Vec::new(),
+        };
+
+        let mut suggestions = Self::close_words(&dict, name)
+            .into_iter()
+            .filter(|w| w != name)
+            .collect::<Vec<_>>();
+
+        suggestions.sort_by_key(|w| Self::levenshtein_cutoff(w, name, usize::MAX));
+        suggestions.truncate(MAX_SUGGESTIONS);
+
+        suggestions
+    }
+
+    fn close_words(dict: &[String], q: &str) -> Vec<String> {
+        const MAX_DISTANCE: usize = 2;
+        const MAX_LEN_DIFF: usize = 2;
+
+        let q_len = q.chars().count();
+
+        dict.iter()
+            .filter(|w| w.chars().count().abs_diff(q_len) <= MAX_LEN_DIFF)
+            .filter(|w| Self::levenshtein_cutoff(w, q, MAX_DISTANCE).is_some())
+            .cloned()
+            .collect()
     }
 
     fn compute_df(candidates: &[Pkg], query_words: &[String]) -> HashMap<String, usize> {
@@ -594,6 +1546,7 @@ impl Napm {
         candidates: Vec<Pkg>,
         query_words: &[String],
         df: &HashMap<String, usize>,
+        field: SearchField,
     ) -> Vec<(f64, Pkg)> {
         const MAX_DISTANCE: usize = 2;
         const MAX_LEN_DIFF: usize = 2;
@@ -605,14 +1558,17 @@ impl Napm {
             let mut score = 0.0;
 
             let name_lc = pkg.name.to_lowercase();
-            let desc_lc = pkg.desc.to_lowercase();
-            let desc_tokens = Self::tokenize(&desc_lc);
+            let desc_tokens = if field.desc() {
+                Self::tokenize(&pkg.desc.to_lowercase())
+            } else {
+                Vec::new()
+            };
 
             for q in query_words {
                 let df_q = *df.get(q).unwrap_or(&1) as f64;
                 let idf = (total_docs / df_q).ln();
 
-                if name_lc.contains(q) {
+                if field.name() && name_lc.contains(q) {
                     score += 5.0 * idf;
                 }
 
@@ -620,10 +1576,11 @@ impl Napm {
                     score += 1.5 * idf;
                 }
 
-                for token in
-                    std::iter::once(name_lc.as_str()).chain(desc_tokens.iter().map(String::as_str))
+                for token in std::iter::once(name_lc.as_str())
+                    .filter(|_| field.name())
+                    .chain(desc_tokens.iter().map(String::as_str))
                 {
-                    if token.len().abs_diff(q.len()) > MAX_LEN_DIFF {
+                    if token.chars().count().abs_diff(q.chars().count()) > MAX_LEN_DIFF {
                         continue;
                     }
 
@@ -641,11 +1598,42 @@ impl Napm {
         scored
     }
 
-    pub fn search(&self, search_terms: Vec<String>) -> Result<Vec<Pkg>> {
-        require_cache()?;
+    // ties go to an exact name match, then the shorter name, then alphabetical order,
+    // so the output does not depend on the row order of the cache
+    fn sort_scored(scored: &mut [(f64, Pkg)], query: &str) {
+        let query = query.to_lowercase();
+
+        scored.sort_by(|(a_score, a), (b_score, b)| {
+            b_score
+                .total_cmp(a_score)
+                .then_with(|| {
+                    (b.name.to_lowercase() == query).cmp(&(a.name.to_lowercase() == query))
+                })
+                .then_with(|| a.name.len().cmp(&b.name.len()))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+    }
 
-        let conn = Connection::open(NAPM_CACHE_FILE)?;
+    // scores are relative to the best match, between 0 and 1
+    pub fn search(
+        &self,
+        search_terms: Vec<String>,
+        options: &SearchOptions,
+    ) -> Result<Vec<(f64, Pkg)>> {
+        self.check_repos(&options.repos)?;
+        self.check_repos(&options.exclude_repos)?;
 
+        let conn = Self::open_valid_cache(CacheKind::DescOnly)?;
+
+        self.search_in(&conn, search_terms, options)
+    }
+
+    fn search_in(
+        &self,
+        conn: &Connection,
+        search_terms: Vec<String>,
+        options: &SearchOptions,
+    ) -> Result<Vec<(f64, Pkg)>> {
         let query = search_terms.join(" ");
         let query_words = Self::tokenize(&query);
 
@@ -653,18 +1641,618 @@ impl Napm {
             return Ok(Vec::new());
         }
 
-        let expanded = Self::expand_query_words(&conn, &query_words)?;
-        let candidates = self.select_candidates(&conn, &expanded)?;
+        let expanded = Self::expand_query_words(conn, &query_words)?;
 
-        if candidates.is_empty() {
-            return Ok(Vec::new());
+        let mut results = if Self::has_fts(conn) {
+            self.fts_search(conn, &expanded, options)?
+        } else {
+            let candidates = self.select_candidates(conn, &expanded, options)?;
+
+            if candidates.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let df = Self::compute_df(&candidates, &query_words);
+            Self::score_packages(candidates, &query_words, &df, options.field)
+        };
+
+        // stable, the same name from several repos keeps the repo priority order
+        Self::sort_scored(&mut results, &query);
+
+        // results are ordered by relevance and repo priority, so the first one of a name wins
+        let mut seen = HashSet::new();
+        results.retain(|(_, pkg)| seen.insert(pkg.name.clone()));
+
+        let top_score = results.first().map_or(0.0, |(score, _)| *score);
+        if top_score > 0.0 {
+            for (score, _) in results.iter_mut() {
+                *score /= top_score;
+            }
+        }
+
+        results.retain(|(score, _)| *score >= options.min_score);
+
+        // filtered only after scoring, so installed packages still count towards idf
+        if options.exclude_installed {
+            let localdb = self.h().localdb();
+            results.retain(|(_, pkg)| localdb.pkg(pkg.name.as_str()).is_err());
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napm::fixtures::Fixture;
+
+    // find_packages_by_file before the winning repo moved into a CTE
+    const CORRELATED_FILE_QUERY: &str = "
+        SELECT d.name, d.version, d.desc, d.repo, '/' || f.path
+        FROM package_files AS f
+        JOIN package_desc  AS d ON f.name = d.name AND f.repo = d.repo
+        WHERE '/' || f.path {op} ?1
+        AND d.repo = (
+            SELECT d2.repo
+            FROM package_desc AS d2
+            WHERE d2.name = d.name
+            ORDER BY CASE d2.repo WHEN 'core' THEN 0 WHEN 'extra' THEN 1 ELSE 1000 END
+            LIMIT 1
+        )
+        ORDER BY d.name, f.path;
+    ";
+
+    const REPO_PRIORITY: &str = "CASE repo WHEN 'core' THEN 0 WHEN 'extra' THEN 1 ELSE 1000 END";
+
+    fn synthetic_cache() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        Napm::init_cache_schema(&conn).unwrap();
+
+        // foo is in both repos, core wins; bar only in extra; baz in an unlisted repo
+        for (repo, name, version, files) in [
+            (
+                "core",
+                "foo",
+                "1.0-1",
+                &["usr/bin/foo", "usr/share/foo/data"][..],
+            ),
+            ("extra", "foo", "2.0-1", &["usr/bin/foo", "usr/lib/foo.so"]),
+            (
+                "extra",
+                "bar",
+                "1.0-1",
+                &["usr/bin/bar", "usr/share/foo/bar"],
+            ),
+            ("custom", "baz", "1.0-1", &["usr/bin/foo"]),
+        ] {
+            conn.execute(
+                "INSERT INTO package_desc (name, version, desc, repo, files_done)
+                 VALUES (?1, ?2, '', ?3, 1)",
+                (name, version, repo),
+            )
+            .unwrap();
+
+            for path in files {
+                conn.execute(
+                    "INSERT INTO package_files (repo, name, path) VALUES (?1, ?2, ?3)",
+                    (repo, name, path),
+                )
+                .unwrap();
+            }
+        }
+
+        conn
+    }
+
+    // vlc is in core, mpv in extra and testing, celluloid only in testing
+    fn search_cache() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        Napm::init_cache_schema(&conn).unwrap();
+
+        for (repo, name, version, desc) in [
+            ("core", "vlc", "3.0-1", "multi-platform media player"),
+            ("extra", "mpv", "0.38-1", "a free media player"),
+            ("testing", "mpv", "0.39-1", "a free media player"),
+            (
+                "testing",
+                "celluloid",
+                "0.27-1",
+                "simple media player frontend for mpv",
+            ),
+        ] {
+            conn.execute(
+                "INSERT INTO package_desc (name, version, desc, repo, files_done)
+                 VALUES (?1, ?2, ?3, ?4, 1)",
+                (name, version, desc, repo),
+            )
+            .unwrap();
+        }
+
+        if Napm::has_fts(&conn) {
+            conn.execute(
+                "INSERT INTO package_fts (package_fts) VALUES ('rebuild')",
+                (),
+            )
+            .unwrap();
+        }
+
+        conn
+    }
+
+    fn search_fixture() -> Fixture {
+        let mut fixture = Fixture::new("search");
+        fixture
+            .sync("core", &[])
+            .sync("extra", &[])
+            .sync("testing", &[]);
+        fixture
+    }
+
+    fn repo_names(pkgs: &[Pkg]) -> Vec<(&str, &str)> {
+        let mut names = pkgs
+            .iter()
+            .map(|pkg| (pkg.repo.as_str(), pkg.name.as_str()))
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    // update_cache_at on a cache file of its own next to the fixture databases
+    fn build_cache_reporting(fixture: &Fixture) -> (Connection, Vec<String>) {
+        let napm = fixture.napm();
+        let cache_path = fixture.dir.join("napm.sqlite");
+
+        let regressions = napm
+            .update_cache_at(
+                &cache_path,
+                &fixture.dir.join("napm.sqlite.lock"),
+                false,
+                CacheKind::DescOnly,
+            )
+            .unwrap();
+
+        (Connection::open(cache_path).unwrap(), regressions)
+    }
+
+    fn build_cache(fixture: &Fixture) -> Connection {
+        build_cache_reporting(fixture).0
+    }
+
+    fn cached_versions(conn: &Connection) -> Vec<(String, String, String)> {
+        conn.prepare("SELECT repo, name, version FROM package_desc ORDER BY repo, name")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect()
+    }
+
+    fn rows(results: Vec<(Pkg, String)>) -> Vec<(String, String, String, String)> {
+        results
+            .into_iter()
+            .map(|(pkg, path)| (pkg.name, pkg.version, pkg.repo, path))
+            .collect()
+    }
+
+    fn correlated(
+        conn: &Connection,
+        path: &str,
+        exact: bool,
+    ) -> Vec<(String, String, String, String)> {
+        let (op, pattern) = if exact {
+            ("=", path.to_string())
+        } else {
+            ("LIKE", format!("%{path}"))
+        };
+
+        conn.prepare(&CORRELATED_FILE_QUERY.replace("{op}", op))
+            .unwrap()
+            .query_map([&pattern], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(3)?, row.get(4)?))
+            })
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn file_query_matches_the_correlated_query() {
+        let conn = synthetic_cache();
+
+        for (path, exact) in [
+            ("/usr/bin/foo", true),
+            ("/usr/lib/foo.so", true),
+            ("bin/foo", false),
+            ("foo%", false),
+            ("", false),
+            ("/missing", true),
+        ] {
+            let new =
+                rows(Napm::query_packages_by_file(&conn, REPO_PRIORITY, path, exact).unwrap());
+
+            assert_eq!(new, correlated(&conn, path, exact), "{path} exact={exact}");
+        }
+    }
+
+    #[test]
+    fn file_query_only_reports_the_winning_repo() {
+        let conn = synthetic_cache();
+
+        let found = rows(
+            Napm::query_packages_by_file(&conn, REPO_PRIORITY, "/usr/lib/foo.so", true).unwrap(),
+        );
+
+        // extra/foo has the file, but core/foo is the one that would be installed
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn is_desc_tag_needs_percent_on_both_ends() {
+        for tag in ["%NAME%", "%DESC%", "%BUILDDATE%"] {
+            assert!(Napm::is_desc_tag(tag), "{tag}");
+        }
+        for line in ["%", "%%", "50%", "%NAME", "a % b %", ""] {
+            assert!(!Napm::is_desc_tag(line), "{line}");
+        }
+    }
+
+    #[test]
+    fn parse_desc_entry_reads_every_block() {
+        let contents = "\
+%FILENAME%
+foo-1.0-1-x86_64.pkg.tar.zst
+
+%NAME%
+foo
+
+%VERSION%
+1.0-1
+
+%DESC%
+A first line
+that goes on, 100% done
+
+%CSIZE%
+1024
+
+%ISIZE%
+4096
+
+%DEPENDS%
+glibc
+bar>=2
+
+%PROVIDES%
+libfoo.so=1-64
+";
+        let entry = Napm::parse_desc_entry("core", contents).unwrap();
+
+        assert_eq!(entry.pkg.name, "foo");
+        assert_eq!(entry.pkg.version, "1.0-1");
+        assert_eq!(entry.pkg.repo, "core");
+        assert_eq!(entry.pkg.desc, "A first line that goes on, 100% done");
+        assert_eq!((entry.csize, entry.isize), (1024, 4096));
+        assert_eq!(entry.depends, ["glibc", "bar>=2"]);
+        assert_eq!(entry.provides, ["libfoo.so=1-64"]);
+    }
+
+    #[test]
+    fn parse_desc_entry_stops_the_description_at_the_next_tag() {
+        let contents = "%NAME%\nfoo\n%DESC%\nshort\n%VERSION%\n1.0-1\n";
+        let entry = Napm::parse_desc_entry("core", contents).unwrap();
+
+        assert_eq!(entry.pkg.desc, "short");
+        assert_eq!(entry.pkg.version, "1.0-1");
+        assert_eq!((entry.csize, entry.isize), (0, 0));
+    }
+
+    #[test]
+    fn parse_desc_entry_requires_name_and_version() {
+        assert!(Napm::parse_desc_entry("core", "%NAME%\nfoo\n").is_err());
+        assert!(Napm::parse_desc_entry("core", "%VERSION%\n1.0-1\n").is_err());
+    }
+
+    fn scored(entries: &[(f64, &str)]) -> Vec<(f64, Pkg)> {
+        entries
+            .iter()
+            .map(|(score, name)| {
+                let pkg = Pkg {
+                    name: name.to_string(),
+                    version: "1.0-1".to_string(),
+                    repo: "core".to_string(),
+                    desc: String::new(),
+                };
+                (*score, pkg)
+            })
+            .collect()
+    }
+
+    fn sorted_names(entries: &[(f64, &str)], query: &str) -> Vec<String> {
+        let mut scored = scored(entries);
+        Napm::sort_scored(&mut scored, query);
+        scored.into_iter().map(|(_, pkg)| pkg.name).collect()
+    }
+
+    #[test]
+    fn sort_scored_puts_higher_scores_first() {
+        assert_eq!(
+            sorted_names(&[(0.2, "a"), (0.9, "b"), (0.5, "c")], "x"),
+            ["b", "c", "a"]
+        );
+    }
+
+    #[test]
+    fn sort_scored_breaks_ties_deterministically() {
+        let ties = [
+            (0.5, "vim-plug"),
+            (0.5, "neovim"),
+            (0.5, "Vim"),
+            (0.5, "gvim"),
+            (0.5, "avim"),
+        ];
+
+        // exact match first (case-insensitive), then shorter names, then alphabetical
+        let expected = ["Vim", "avim", "gvim", "neovim", "vim-plug"];
+        assert_eq!(sorted_names(&ties, "vim"), expected);
+
+        let mut reversed = ties;
+        reversed.reverse();
+        assert_eq!(sorted_names(&reversed, "vim"), expected);
+    }
+
+    #[test]
+    fn parallel_expansion_matches_sequential() {
+        let conn = Connection::open_in_memory().unwrap();
+        Napm::init_cache_schema(&conn).unwrap();
+
+        // enough names to be split across every worker, with near misses of the query words
+        let mut names = (0..500).map(|i| format!("pkg{i}")).collect::<Vec<_>>();
+        names.extend(
+            [
+                "vim",
+                "gvim",
+                "vi",
+                "nvim",
+                "vim-plug",
+                "firefox",
+                "firefix",
+                "Firefox-esr",
+            ]
+            .map(String::from),
+        );
+
+        for (i, name) in names.iter().enumerate() {
+            conn.execute(
+                "INSERT INTO package_desc (name, version, desc, repo, files_done)
+                 VALUES (?1, '1.0-1', '', ?2, 1)",
+                (name, if i % 2 == 0 { "core" } else { "extra" }),
+            )
+            .unwrap();
+        }
+
+        let dict = names
+            .iter()
+            .map(|name| name.to_lowercase())
+            .collect::<Vec<_>>();
+        let query = ["vim", "firefx", "pkg12"].map(String::from);
+
+        let groups = Napm::expand_query_words(&conn, &query).unwrap();
+        assert_eq!(groups.len(), query.len());
+
+        for (q, group) in query.iter().zip(groups) {
+            let parallel = group.into_iter().collect::<HashSet<_>>();
+
+            let mut sequential = Napm::close_words(&dict, q)
+                .into_iter()
+                .collect::<HashSet<_>>();
+            sequential.insert(q.clone());
+
+            assert_eq!(parallel, sequential, "{q}");
+        }
+    }
+
+    #[test]
+    fn writer_restores_rollback_journal_on_error() {
+        let path =
+            std::env::temp_dir().join(format!("napm-cache-writer-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let failed = || -> Result<()> {
+            let conn = Connection::open(&path)?;
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            let writer = CacheWriter(conn);
+            Napm::init_cache_schema(&writer)?;
+            writer.execute("INSERT INTO missing_table VALUES (1)", [])?;
+            Ok(())
+        };
+        assert!(failed().is_err());
+
+        let mode: String = Connection::open(&path)
+            .unwrap()
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(mode, "delete");
+        assert!(!path.with_extension("db-wal").exists());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn search_drops_installed_packages_after_scoring() {
+        let mut fixture = search_fixture();
+        fixture.local("vlc", "3.0-1");
+        let napm = fixture.napm();
+        let conn = search_cache();
+        let terms = vec!["media".to_string(), "player".to_string()];
+
+        let all = napm
+            .search_in(&conn, terms.clone(), &SearchOptions::default())
+            .unwrap();
+        assert!(all.iter().any(|(_, pkg)| pkg.name == "vlc"));
+
+        let options = SearchOptions {
+            exclude_installed: true,
+            ..Default::default()
+        };
+        let not_installed = napm.search_in(&conn, terms, &options).unwrap();
+
+        // the scores are those of the full candidate set, not recomputed without vlc
+        let expected = all
+            .into_iter()
+            .filter(|(_, pkg)| pkg.name != "vlc")
+            .collect::<Vec<_>>();
+        assert_eq!(
+            not_installed
+                .iter()
+                .map(|(score, pkg)| (*score, pkg.name.as_str()))
+                .collect::<Vec<_>>(),
+            expected
+                .iter()
+                .map(|(score, pkg)| (*score, pkg.name.as_str()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn excluded_repos_are_absent_from_the_candidates() {
+        let fixture = search_fixture();
+        let napm = fixture.napm();
+        let conn = search_cache();
+        let words = vec![vec!["media".to_string()], vec!["player".to_string()]];
+
+        let options = SearchOptions {
+            exclude_repos: vec!["testing".to_string()],
+            ..Default::default()
+        };
+
+        let candidates = napm.select_candidates(&conn, &words, &options).unwrap();
+        assert_eq!(repo_names(&candidates), [("core", "vlc"), ("extra", "mpv")]);
+
+        // without the exclusion testing has its own package
+        let all = napm
+            .select_candidates(&conn, &words, &SearchOptions::default())
+            .unwrap();
+        assert!(all.iter().any(|pkg| pkg.repo == "testing"));
+
+        if Napm::has_fts(&conn) {
+            let ranked = napm
+                .fts_search(&conn, &words, &options)
+                .unwrap()
+                .into_iter()
+                .map(|(_, pkg)| pkg)
+                .collect::<Vec<_>>();
+            assert_eq!(repo_names(&ranked), [("core", "vlc"), ("extra", "mpv")]);
+        }
+    }
+
+    #[test]
+    fn zero_byte_cache_is_rebuilt() {
+        let mut fixture = Fixture::new("zero-cache");
+        fixture.sync("core", &[("foo", "1.0-1", &[])]);
+        fs::write(fixture.dir.join("napm.sqlite"), "").unwrap();
+
+        let conn = build_cache(&fixture);
+
+        assert!(Napm::cache_is_valid(&conn));
+        assert_eq!(
+            cached_versions(&conn),
+            [("core".to_string(), "foo".to_string(), "1.0-1".to_string())]
+        );
+    }
+
+    #[test]
+    fn version_regressions_are_reported() {
+        let mut fixture = Fixture::new("regression");
+        fixture.sync("core", &[("foo", "2.0-1", &[]), ("bar", "1.0-1", &[])]);
+
+        let (_, regressions) = build_cache_reporting(&fixture);
+        assert!(regressions.is_empty());
+
+        // the mirror rolled foo back and moved bar forward
+        fixture.sync("core", &[("foo", "1.5-1", &[]), ("bar", "1.1-1", &[])]);
+
+        let (conn, regressions) = build_cache_reporting(&fixture);
+        assert_eq!(regressions.len(), 1, "{regressions:?}");
+        assert!(regressions[0].starts_with("core/"));
+        assert!(regressions[0].contains("foo"));
+        assert!(regressions[0].ends_with("2.0-1 => 1.5-1"));
+
+        assert!(cached_versions(&conn).contains(&(
+            "core".to_string(),
+            "foo".to_string(),
+            "1.5-1".to_string()
+        )));
+    }
+
+    #[test]
+    fn distances_count_chars_not_bytes() {
+        assert_eq!(Napm::levenshtein_cutoff("café", "cafe", 2), Some(1));
+        assert_eq!(Napm::levenshtein_cutoff("é", "e", 2), Some(1));
+        assert_eq!(Napm::levenshtein_cutoff("é", "e", 0), None);
+        assert_eq!(Napm::levenshtein_cutoff("日本語", "日本", 2), Some(1));
+
+        // three bytes longer, but only one char
+        let dict = ["日本語", "abcde", "éclair"].map(String::from);
+        assert_eq!(Napm::close_words(&dict, "日本"), ["日本語"]);
+        assert_eq!(Napm::close_words(&dict, "eclair"), ["éclair"]);
+    }
+
+    #[test]
+    fn full_rebuild_stays_fast() {
+        const PKGS: usize = 2_000;
+        const FILES_PER_PKG: usize = 100;
+
+        let mut fixture = Fixture::new("rebuild");
+        fixture.sync("big", &[]);
+
+        let mut builder = tar::Builder::new(Vec::new());
+        for i in 0..PKGS {
+            let desc = format!("%NAME%\npkg{i}\n\n%VERSION%\n1.0-1\n\n%DESC%\npackage {i}\n\n");
+            let files = (0..FILES_PER_PKG).fold("%FILES%\n".to_string(), |files, f| {
+                files + &format!("usr/share/pkg{i}/file{f}\n")
+            });
+
+            for (name, contents) in [("desc", desc), ("files", files)] {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(contents.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                builder
+                    .append_data(
+                        &mut header,
+                        format!("pkg{i}-1.0-1/{name}"),
+                        contents.as_bytes(),
+                    )
+                    .unwrap();
+            }
         }
+        fs::write(
+            fixture.dir.join("db/sync/big.files"),
+            builder.into_inner().unwrap(),
+        )
+        .unwrap();
+
+        let napm = fixture.napm();
+        let cache_path = fixture.dir.join("napm.sqlite");
+        let start = std::time::Instant::now();
 
-        let df = Self::compute_df(&candidates, &query_words);
-        let mut scored = Self::score_packages(candidates, &query_words, &df);
+        napm.update_cache_at(
+            &cache_path,
+            &fixture.dir.join("napm.sqlite.lock"),
+            false,
+            CacheKind::Full,
+        )
+        .unwrap();
 
-        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        // loose enough for a debug build on a slow runner, a transaction per package is far slower
+        let elapsed = start.elapsed();
+        assert!(elapsed < Duration::from_secs(60), "{elapsed:?}");
 
-        Ok(scored.into_iter().map(|(_, pkg)| pkg).collect())
+        let files: i64 = Connection::open(cache_path)
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM package_files", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(files as usize, PKGS * FILES_PER_PKG);
     }
 }
@@ -0,0 +1,378 @@
+use flate2::read::GzDecoder;
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use alpm::Package;
+
+use crate::error::{Error, Result};
+use crate::log_warn;
+use crate::napm::Napm;
+use crate::pkg::Pkg;
+
+pub struct CheckResult {
+    pub pkg: Pkg,
+    pub total: usize,
+    pub missing: Vec<String>,
+    pub altered: Vec<(String, String)>,
+}
+
+impl CheckResult {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.altered.is_empty()
+    }
+}
+
+#[derive(Default, Clone)]
+struct MtreeEntry {
+    kind: String,
+    mode: Option<u32>,
+    time: Option<i64>,
+    size: Option<u64>,
+    link: Option<String>,
+    md5: Option<String>,
+}
+
+impl MtreeEntry {
+    fn set(&mut self, keyword: &str) {
+        let Some((key, value)) = keyword.split_once('=') else {
+            return;
+        };
+
+        match key {
+            "type" => self.kind = value.to_string(),
+            "mode" => self.mode = u32::from_str_radix(value, 8).ok(),
+            "time" => self.time = value.split('.').next().and_then(|s| s.parse().ok()),
+            "size" => self.size = value.parse().ok(),
+            "link" => self.link = Some(unescape(value)),
+            "md5digest" => self.md5 = Some(value.to_string()),
+            _ => {}
+        }
+    }
+}
+
+// mtree escapes special characters as \ooo octal sequences
+fn unescape(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(code) = bytes
+                .get(i + 1..i + 4)
+                .and_then(|oct| std::str::from_utf8(oct).ok())
+                .and_then(|oct| u8::from_str_radix(oct, 8).ok())
+        {
+            out.push(code);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&out).to_string()
+}
+
+impl Napm {
+    fn read_mtree(&self, package: &Package) -> Result<HashMap<String, MtreeEntry>> {
+        let path = Path::new(self.h().dbpath())
+            .join("local")
+            .join(format!("{}-{}", package.name(), package.version()))
+            .join("mtree");
+
+        let mut contents = String::new();
+        GzDecoder::new(fs::File::open(path)?).read_to_string(&mut contents)?;
+
+        let mut defaults = MtreeEntry::default();
+        let mut entries = HashMap::new();
+
+        for line in contents.lines() {
+            let mut words = line.split_whitespace();
+
+            let Some(first) = words.next() else {
+                continue;
+            };
+
+            if first == "/set" {
+                words.for_each(|w| defaults.set(w));
+                continue;
+            }
+
+            // package metadata like .PKGINFO is not installed
+            let Some(name) = first.strip_prefix("./") else {
+                continue;
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+
+            let mut entry = defaults.clone();
+            words.for_each(|w| entry.set(w));
+
+            entries.insert(format!("/{}", unescape(name)), entry);
+        }
+
+        Ok(entries)
+    }
+
+    // the error is the reason reported for the file
+    fn md5sum(path: &Path) -> std::result::Result<String, String> {
+        // libalpm only says that it failed, opening the file first tells why
+        fs::File::open(path).map_err(|e| format!("unreadable ({e})"))?;
+
+        alpm::compute_md5sum(path.as_os_str().as_bytes()).map_err(|e| format!("unreadable ({e})"))
+    }
+
+    fn check_entry(path: &Path, entry: &MtreeEntry, is_backup: bool) -> Option<String> {
+        let meta = fs::symlink_metadata(path).ok()?;
+
+        let actual_kind = if meta.file_type().is_symlink() {
+            "link"
+        } else if meta.is_dir() {
+            "dir"
+        } else {
+            "file"
+        };
+
+        if !entry.kind.is_empty() && entry.kind != actual_kind {
+            return Some(format!(
+                "type mismatch ({actual_kind} instead of {})",
+                entry.kind
+            ));
+        }
+
+        if actual_kind == "link" {
+            let target = fs::read_link(path).ok()?;
+            return entry
+                .link
+                .as_ref()
+                .filter(|link| Path::new(link) != target)
+                .map(|link| {
+                    format!(
+                        "symlink target mismatch ({} instead of {link})",
+                        target.display()
+                    )
+                });
+        }
+
+        if let Some(mode) = entry.mode
+            && meta.mode() & 0o7777 != mode
+        {
+            return Some(format!(
+                "permissions mismatch ({:o} instead of {mode:o})",
+                meta.mode() & 0o7777
+            ));
+        }
+
+        // config files are expected to be edited
+        if actual_kind == "dir" || is_backup {
+            return None;
+        }
+
+        if let Some(size) = entry.size
+            && meta.len() != size
+        {
+            return Some(format!("size mismatch ({} instead of {size})", meta.len()));
+        }
+
+        if let Some(time) = entry.time
+            && meta.mtime() != time
+        {
+            return Some("modification time mismatch".to_string());
+        }
+
+        None
+    }
+
+    pub fn check(&self, names: &[&str], deep: bool) -> Result<Vec<CheckResult>> {
+        let localdb = self.h().localdb();
+
+        let packages = if names.is_empty() {
+            localdb.pkgs().into_iter().collect::<Vec<_>>()
+        } else {
+            names
+                .iter()
+                .map(|name| {
+                    localdb
+                        .pkg(*name)
+                        .map_err(|_| Error::PackageNotInLocalDb(name.to_string()))
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        let root = Path::new(self.h().root());
+        let mut results = Vec::new();
+
+        for package in packages {
+            let files = package
+                .files()
+                .files()
+                .iter()
+                .map(|file| format!("/{}", String::from_utf8_lossy(file.name())))
+                .collect::<Vec<_>>();
+
+            // files are reported as the package lists them, but looked up under the root
+            let on_disk = |file: &str| -> PathBuf { root.join(file.trim_start_matches('/')) };
+
+            let mut result = CheckResult {
+                pkg: Pkg::from(package),
+                total: files.len(),
+                missing: Vec::new(),
+                altered: Vec::new(),
+            };
+
+            for file in &files {
+                if fs::symlink_metadata(on_disk(file)).is_err() {
+                    result.missing.push(file.clone());
+                }
+            }
+
+            if deep {
+                let mtree = match self.read_mtree(package) {
+                    Ok(mtree) => mtree,
+                    Err(e) => {
+                        log_warn!(
+                            "Skipping the deep check of {}, its mtree could not be read: {e}",
+                            result.pkg.formatted_name(false)
+                        );
+                        results.push(result);
+                        continue;
+                    }
+                };
+                let backups = package
+                    .backup()
+                    .iter()
+                    .map(|backup| format!("/{}", backup.name()))
+                    .collect::<Vec<_>>();
+
+                let mut to_hash = Vec::new();
+
+                for file in files.iter().filter(|f| !result.missing.contains(f)) {
+                    let Some(entry) = mtree.get(file) else {
+                        continue;
+                    };
+
+                    let is_backup = backups.contains(file);
+
+                    match Self::check_entry(&on_disk(file), entry, is_backup) {
+                        Some(reason) => result.altered.push((file.clone(), reason)),
+                        None if !is_backup && entry.kind == "file" && entry.md5.is_some() => {
+                            to_hash.push(file.as_str())
+                        }
+                        None => {}
+                    }
+                }
+
+                for file in to_hash {
+                    match Self::md5sum(&on_disk(file)) {
+                        Ok(sum) if Some(&sum) != mtree[file].md5.as_ref() => result
+                            .altered
+                            .push((file.to_string(), "checksum mismatch".to_string())),
+                        Ok(_) => {}
+                        Err(reason) => result.altered.push((file.to_string(), reason)),
+                    }
+                }
+            }
+
+            results.push(result);
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("napm-check-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn unescape_decodes_octal_sequences() {
+        assert_eq!(unescape("a\\040b"), "a b");
+        assert_eq!(unescape("plain"), "plain");
+        assert_eq!(unescape("trailing\\04"), "trailing\\04");
+    }
+
+    #[test]
+    fn mtree_entry_parses_keywords() {
+        let mut entry = MtreeEntry::default();
+        for keyword in [
+            "type=file",
+            "mode=644",
+            "time=1700000000.5",
+            "size=12",
+            "md5digest=abc",
+        ] {
+            entry.set(keyword);
+        }
+
+        assert_eq!(entry.kind, "file");
+        assert_eq!(entry.mode, Some(0o644));
+        assert_eq!(entry.time, Some(1700000000));
+        assert_eq!(entry.size, Some(12));
+        assert_eq!(entry.md5.as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn check_entry_reports_size_and_type() {
+        let dir = temp_dir("entry");
+        let file = dir.join("file");
+        fs::write(&file, "hello").unwrap();
+
+        let entry = MtreeEntry {
+            kind: "file".to_string(),
+            size: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(Napm::check_entry(&file, &entry, false), None);
+
+        let bigger = MtreeEntry {
+            size: Some(6),
+            ..entry.clone()
+        };
+        assert!(Napm::check_entry(&file, &bigger, false).is_some());
+        // edited config files are fine
+        assert_eq!(Napm::check_entry(&file, &bigger, true), None);
+
+        let dir_entry = MtreeEntry {
+            kind: "dir".to_string(),
+            ..Default::default()
+        };
+        assert!(
+            Napm::check_entry(&file, &dir_entry, false)
+                .is_some_and(|reason| reason.starts_with("type mismatch"))
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn md5sum_reports_unreadable_files() {
+        let dir = temp_dir("md5");
+        let file = dir.join("file");
+        fs::write(&file, "hello\n").unwrap();
+
+        assert_eq!(
+            Napm::md5sum(&file).as_deref(),
+            Ok("b1946ac92492d2347c6235b4d2611184")
+        );
+        assert!(
+            Napm::md5sum(&dir.join("missing"))
+                .is_err_and(|reason| reason.starts_with("unreadable"))
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+use cini::{Callback, CallbackKind, Ini};
+use indicatif::ProgressStyle;
+use unicode_width::UnicodeWidthChar;
+
+use crate::error::{Error, Result};
+
+pub const NAPM_CONFIG_FILE: &str = "/etc/napm.conf";
+
+#[derive(Debug, Clone, Default)]
+pub struct NapmConfig {
+    pub progress_template: Option<String>,
+    pub progress_chars: Option<String>,
+    pub refresh_keyring_before_upgrade: bool,
+    pub no_search_history: bool,
+    pub cache_servers: Vec<String>,
+    pub cache_server_sync: bool,
+    pub parallel_downloads: Option<u32>,
+    pub no_auto_unlock: bool,
+    pub no_auto_repair: bool,
+    pub sync_retries: Option<u32>,
+}
+
+impl Ini for NapmConfig {
+    type Err = Error;
+
+    fn callback(&mut self, cb: Callback) -> Result<()> {
+        let invalid = |msg: String| Error::NapmConfigInvalid(cb.line_number, msg);
+
+        match cb.kind {
+            CallbackKind::Section("options") => Ok(()),
+            CallbackKind::Section(section) => Err(invalid(format!("unknown section [{section}]"))),
+            CallbackKind::Directive(Some("options"), "NoAutoUnlock", None) => {
+                self.no_auto_unlock = true;
+                Ok(())
+            }
+            CallbackKind::Directive(Some("options"), "NoAutoRepair", None) => {
+                self.no_auto_repair = true;
+                Ok(())
+            }
+            CallbackKind::Directive(Some("options"), "NoSearchHistory", None) => {
+                self.no_search_history = true;
+                Ok(())
+            }
+            CallbackKind::Directive(Some("options"), "CacheServerSync", None) => {
+                self.cache_server_sync = true;
+                Ok(())
+            }
+            CallbackKind::Directive(Some("options"), "RefreshKeyringBeforeUpgrade", None) => {
+                self.refresh_keyring_before_upgrade = true;
+                Ok(())
+            }
+            CallbackKind::Directive(Some("options"), key, value) => {
+                let value = value
+                    .map(|v| v.trim_matches('"').to_string())
+                    .ok_or_else(|| invalid(format!("{key} requires a value")))?;
+
+                match key {
+                    "CacheServer" => self.cache_servers.push(value),
+                    "ParallelDownloads" => {
+                        let n = value.parse().ok().filter(|n| *n >= 1).ok_or_else(|| {
+                            invalid("ParallelDownloads must be at least 1".to_string())
+                        })?;
+                        self.parallel_downloads = Some(n);
+                    }
+                    "SyncRetries" => {
+                        let n = value.parse().map_err(|_| {
+                            invalid("SyncRetries must be a non-negative number".to_string())
+                        })?;
+                        self.sync_retries = Some(n);
+                    }
+                    "ProgressTemplate" => {
+                        ProgressStyle::with_template(&value)
+                            .map_err(|e| invalid(format!("invalid ProgressTemplate: {e}")))?;
+                        self.progress_template = Some(value);
+                    }
+                    "ProgressChars" => {
+                        Self::check_progress_chars(&value).map_err(invalid)?;
+                        self.progress_chars = Some(value);
+                    }
+                    _ => return Err(invalid(format!("unknown option {key}"))),
+                }
+
+                Ok(())
+            }
+            CallbackKind::Directive(_, key, _) => Err(invalid(format!(
+                "{key} is outside of the [options] section"
+            ))),
+        }
+    }
+}
+
+impl NapmConfig {
+    pub fn load() -> Result<Self> {
+        let mut config = Self::default();
+
+        match std::fs::read_to_string(NAPM_CONFIG_FILE) {
+            Ok(content) => config.parse(Some(NAPM_CONFIG_FILE), &content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        Ok(config)
+    }
+
+    // indicatif panics on fewer than 2 chars or chars of different widths
+    fn check_progress_chars(chars: &str) -> std::result::Result<(), String> {
+        let widths = chars.chars().map(|c| c.width()).collect::<Vec<_>>();
+
+        if widths.len() < 2 {
+            return Err("ProgressChars needs at least 2 characters".to_string());
+        }
+
+        if widths.iter().any(|w| *w != widths[0] || w.is_none()) {
+            return Err("ProgressChars must all have the same display width".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Result<NapmConfig> {
+        let mut config = NapmConfig::default();
+        config.parse(None, content)?;
+        Ok(config)
+    }
+
+    #[test]
+    fn progress_template_is_validated_on_load() {
+        let config =
+            parse("[options]\nProgressTemplate = \"{spinner} {bar:20.green/black} {msg}\"\n")
+                .unwrap();
+        assert_eq!(
+            config.progress_template.as_deref(),
+            Some("{spinner} {bar:20.green/black} {msg}")
+        );
+
+        for template in ["{bar:x}", "done } here"] {
+            assert!(
+                matches!(
+                    parse(&format!("[options]\nProgressTemplate = {template}\n")),
+                    Err(Error::NapmConfigInvalid(..))
+                ),
+                "{template}"
+            );
+        }
+    }
+
+    #[test]
+    fn progress_chars_need_equal_widths() {
+        assert!(NapmConfig::check_progress_chars("=> ").is_ok());
+        assert!(NapmConfig::check_progress_chars("█▉▊▋▌▍▎▏ ").is_ok());
+
+        assert!(NapmConfig::check_progress_chars("=").is_err());
+        assert!(NapmConfig::check_progress_chars("#漢-").is_err());
+        assert!(NapmConfig::check_progress_chars("=\t ").is_err());
+    }
+}
@@ -0,0 +1,204 @@
+use alpm::{Depend, Package, PackageReason};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::error::{Error, Result};
+use crate::napm::Napm;
+
+pub enum InstallReason {
+    Explicit,
+    Dependency(Vec<String>),
+    Orphan,
+}
+
+pub struct DepTreeNode {
+    pub name: String,
+    // the dependency as written, when it is satisfied through a provide
+    pub provides: Option<String>,
+    pub resolved: bool,
+    pub seen: bool,
+    pub children: Vec<DepTreeNode>,
+}
+
+impl Napm {
+    // installed package name => names of the installed packages depending on it
+    pub(super) fn local_required_by(&self) -> HashMap<String, Vec<String>> {
+        let pkgs = self.h().localdb().pkgs();
+        let mut required_by: HashMap<String, Vec<String>> = HashMap::new();
+
+        for pkg in pkgs {
+            for dep in pkg.depends() {
+                if let Some(satisfier) = pkgs.find_satisfier(dep.to_string()) {
+                    required_by
+                        .entry(satisfier.name().to_string())
+                        .or_default()
+                        .push(pkg.name().to_string());
+                }
+            }
+        }
+
+        required_by
+    }
+
+    // the chain goes from the closest explicitly installed package down to `name`
+    pub fn why(&self, name: &str) -> Result<InstallReason> {
+        let localdb = self.h().localdb();
+
+        let pkg = localdb
+            .pkg(name)
+            .map_err(|_| Error::PackageNotInLocalDb(name.to_string()))?;
+
+        if pkg.reason() == PackageReason::Explicit {
+            return Ok(InstallReason::Explicit);
+        }
+
+        let required_by = self.local_required_by();
+
+        let mut parents: HashMap<String, String> = HashMap::new();
+        let mut visited = HashSet::from([name.to_string()]);
+        let mut queue = VecDeque::from([name.to_string()]);
+
+        while let Some(current) = queue.pop_front() {
+            for dependent in required_by.get(&current).into_iter().flatten() {
+                if !visited.insert(dependent.clone()) {
+                    continue;
+                }
+
+                parents.insert(dependent.clone(), current.clone());
+
+                let explicit = localdb
+                    .pkg(dependent.as_str())
+                    .is_ok_and(|p| p.reason() == PackageReason::Explicit);
+
+                if explicit {
+                    let mut chain = vec![dependent.clone()];
+                    while let Some(next) = parents.get(chain.last().unwrap()) {
+                        chain.push(next.clone());
+                    }
+                    return Ok(InstallReason::Dependency(chain));
+                }
+
+                queue.push_back(dependent.clone());
+            }
+        }
+
+        Ok(InstallReason::Orphan)
+    }
+
+    // installed packages come from the local database, the rest from the sync databases
+    fn deptree_pkg(&self, dep: &str) -> Option<&Package> {
+        let handle = self.h();
+
+        handle
+            .localdb()
+            .pkgs()
+            .find_satisfier(dep)
+            .or_else(|| handle.syncdbs().find_satisfier(dep))
+    }
+
+    pub fn deptree(&self, name: &str, depth: Option<usize>, reverse: bool) -> Result<DepTreeNode> {
+        let pkg = self
+            .deptree_pkg(name)
+            .ok_or_else(|| Error::PackageNotFound(name.to_string()))?;
+
+        let mut seen = HashSet::new();
+
+        Ok(self.deptree_node(pkg, None, depth, reverse, &mut seen))
+    }
+
+    fn deptree_node(
+        &self,
+        pkg: &Package,
+        provides: Option<String>,
+        depth: Option<usize>,
+        reverse: bool,
+        seen: &mut HashSet<String>,
+    ) -> DepTreeNode {
+        let name = pkg.name().to_string();
+
+        // shared subtrees and cycles are only expanded the first time
+        if !seen.insert(name.clone()) {
+            return DepTreeNode {
+                name,
+                provides,
+                resolved: true,
+                seen: true,
+                children: Vec::new(),
+            };
+        }
+
+        let mut children = Vec::new();
+
+        if depth != Some(0) {
+            let depth = depth.map(|d| d - 1);
+
+            let deps: Vec<String> = if reverse {
+                pkg.required_by().into_iter().collect()
+            } else {
+                pkg.depends().iter().map(|dep| dep.to_string()).collect()
+            };
+
+            for dep in deps {
+                match self.deptree_pkg(&dep) {
+                    Some(child) => {
+                        // a versioned dependency like glibc>=2.39 is still the package itself
+                        let provides =
+                            (child.name() != Depend::new(dep.as_str()).name()).then_some(dep);
+                        children.push(self.deptree_node(child, provides, depth, reverse, seen));
+                    }
+                    None => children.push(DepTreeNode {
+                        name: dep,
+                        provides: None,
+                        resolved: false,
+                        seen: false,
+                        children: Vec::new(),
+                    }),
+                }
+            }
+        }
+
+        DepTreeNode {
+            name,
+            provides,
+            resolved: true,
+            seen: false,
+            children,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::napm::fixtures::Fixture;
+
+    #[test]
+    fn deptree_only_marks_provides_for_other_names() {
+        let mut fixture = Fixture::new("deptree");
+        fixture
+            .local_with("app", "1.0-1", &["glibc>=2.0", "sh", "missing"], &[])
+            .local("glibc", "2.39-1")
+            .local_with("bash", "5.2-1", &[], &["sh"]);
+        let napm = fixture.napm();
+
+        let tree = napm.deptree("app", None, false).unwrap();
+        let children = tree
+            .children
+            .iter()
+            .map(|child| {
+                (
+                    child.name.as_str(),
+                    child.provides.as_deref(),
+                    child.resolved,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            children,
+            [
+                ("glibc", None, true),
+                ("bash", Some("sh"), true),
+                ("missing", None, false)
+            ]
+        );
+    }
+}
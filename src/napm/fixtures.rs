@@ -0,0 +1,161 @@
+// synthetic alpm databases under the temp dir, the tests never touch the host system
+use alpm::{Alpm, SigLevel};
+use pacmanconf::{Config, Repository};
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::napm::{Napm, NapmOptions, config::NapmConfig};
+
+pub struct Fixture {
+    pub dir: PathBuf,
+    repos: Vec<String>,
+}
+
+impl Fixture {
+    pub fn new(name: &str) -> Self {
+        // the tests run in parallel, several of them may share a fixture name
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "napm-fixture-{name}-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+
+        for sub in ["root", "db/local", "db/sync"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        fs::write(dir.join("db/local/ALPM_DB_VERSION"), "9\n").unwrap();
+
+        Self {
+            dir,
+            repos: Vec::new(),
+        }
+    }
+
+    fn desc(name: &str, version: &str, extra: &str) -> String {
+        format!("%NAME%\n{name}\n\n%VERSION%\n{version}\n\n%ARCH%\nx86_64\n\n{extra}")
+    }
+
+    pub fn local(&mut self, name: &str, version: &str) -> &mut Self {
+        self.local_with(name, version, &[], &[])
+    }
+
+    pub fn local_with(
+        &mut self,
+        name: &str,
+        version: &str,
+        depends: &[&str],
+        provides: &[&str],
+    ) -> &mut Self {
+        let entry = self.dir.join("db/local").join(format!("{name}-{version}"));
+
+        let mut extra = "%REASON%\n0\n\n".to_string();
+        for (tag, values) in [("DEPENDS", depends), ("PROVIDES", provides)] {
+            if !values.is_empty() {
+                extra += &format!("%{tag}%\n{}\n\n", values.join("\n"));
+            }
+        }
+
+        fs::create_dir_all(&entry).unwrap();
+        fs::write(entry.join("desc"), Self::desc(name, version, &extra)).unwrap();
+        fs::write(entry.join("files"), "%FILES%\n\n").unwrap();
+
+        self
+    }
+
+    // relative to the root, like in the files of a real local database
+    pub fn files(&mut self, name: &str, version: &str, files: &[&str]) -> &mut Self {
+        let entry = self.dir.join("db/local").join(format!("{name}-{version}"));
+        fs::write(
+            entry.join("files"),
+            format!("%FILES%\n{}\n\n", files.join("\n")),
+        )
+        .unwrap();
+
+        self
+    }
+
+    // registered in the order they are added, like the repos of pacman.conf
+    pub fn sync(&mut self, repo: &str, pkgs: &[(&str, &str, &[&str])]) -> &mut Self {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        for (name, version, provides) in pkgs {
+            let mut extra = format!("%FILENAME%\n{name}-{version}-x86_64.pkg.tar.zst\n\n");
+            if !provides.is_empty() {
+                extra += &format!("%PROVIDES%\n{}\n\n", provides.join("\n"));
+            }
+            let desc = Self::desc(name, version, &extra);
+
+            let mut header = tar::Header::new_gnu();
+            header.set_size(desc.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(
+                    &mut header,
+                    format!("{name}-{version}/desc"),
+                    desc.as_bytes(),
+                )
+                .unwrap();
+        }
+
+        let db = self.dir.join("db/sync").join(format!("{repo}.db"));
+        fs::write(db, builder.into_inner().unwrap()).unwrap();
+
+        // writing a repo again replaces its database, like a sync from the mirror
+        if !self.repos.iter().any(|r| r == repo) {
+            self.repos.push(repo.to_string());
+        }
+
+        self
+    }
+
+    pub fn napm(&self) -> Napm {
+        let root = self.dir.join("root").to_string_lossy().to_string();
+        let db_path = self.dir.join("db").to_string_lossy().to_string();
+
+        let handle = Alpm::new(root.as_str(), db_path.as_str()).unwrap();
+        for repo in &self.repos {
+            handle
+                .register_syncdb(repo.as_str(), SigLevel::NONE)
+                .unwrap();
+        }
+
+        // the cache orders the repos by their place in pacman.conf
+        let mut config = Config::default();
+        for repo in &self.repos {
+            let mut repository = Repository::default();
+            repository.name = repo.clone();
+            config.repos.push(repository);
+        }
+
+        Napm {
+            config,
+            handle: Some(handle),
+            options: NapmOptions {
+                root: Some(root),
+                db_path: Some(db_path),
+                ..Default::default()
+            },
+            napm_config: NapmConfig::default(),
+            trans_active: false,
+        }
+    }
+
+    pub fn dry_run_napm(&self) -> Napm {
+        let mut napm = self.napm();
+        napm.options.dry_run = true;
+        napm
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,155 @@
+use alpm::Package;
+use rusqlite::Connection;
+
+use crate::error::{Error, Result};
+use crate::napm::Napm;
+use crate::pkg::Pkg;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfoSource {
+    Local,
+    Cache,
+    Sync,
+}
+
+impl InfoSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InfoSource::Local => "installed",
+            InfoSource::Cache => "cache",
+            InfoSource::Sync => "sync database",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PkgInfo {
+    pub pkg: Pkg,
+    pub csize: u64,
+    pub isize: u64,
+    pub depends: Vec<String>,
+    pub source: InfoSource,
+}
+
+impl PkgInfo {
+    fn from_package(package: &Package, source: InfoSource) -> Self {
+        Self {
+            pkg: Pkg::from(package),
+            csize: package.size().max(0) as u64,
+            isize: package.isize().max(0) as u64,
+            depends: package.depends().iter().map(|d| d.to_string()).collect(),
+            source,
+        }
+    }
+}
+
+impl Napm {
+    // local db first, then the cache (works offline), then the live sync dbs,
+    // restricting the repos skips the local db
+    pub fn info(&self, pkg_name: &str, repos: &[String]) -> Result<PkgInfo> {
+        self.info_from(pkg_name, repos, Self::open_existing_cache().as_ref())
+    }
+
+    fn info_from(
+        &self,
+        pkg_name: &str,
+        repos: &[String],
+        cache: Option<&Connection>,
+    ) -> Result<PkgInfo> {
+        self.check_repos(repos)?;
+
+        if repos.is_empty()
+            && let Ok(package) = self.h().localdb().pkg(pkg_name)
+        {
+            return Ok(PkgInfo::from_package(package, InfoSource::Local));
+        }
+
+        if let Some(conn) = cache {
+            match self.cached_info(conn, pkg_name, repos) {
+                Ok(info) => return Ok(info),
+                Err(Error::PackageNotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.h()
+            .syncdbs()
+            .iter()
+            .filter(|db| repos.is_empty() || repos.iter().any(|r| r == db.name()))
+            .find_map(|db| db.pkg(pkg_name).ok())
+            .map(|package| PkgInfo::from_package(package, InfoSource::Sync))
+            .ok_or_else(|| Error::PackageNotFound(pkg_name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napm::fixtures::Fixture;
+
+    fn cache_with(pkgs: &[(&str, &str, &str)]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        Napm::init_cache_schema(&conn).unwrap();
+
+        for (repo, name, version) in pkgs {
+            conn.execute(
+                "INSERT INTO package_desc (name, version, desc, repo, csize, files_done)
+                 VALUES (?1, ?2, '', ?3, 42, 1)",
+                (name, version, repo),
+            )
+            .unwrap();
+        }
+
+        conn
+    }
+
+    fn fixture() -> Fixture {
+        let mut fixture = Fixture::new("info");
+        fixture
+            .local("foo", "1.0-1")
+            .sync("core", &[("foo", "2.0-1", &[]), ("bar", "1.0-1", &[])]);
+        fixture
+    }
+
+    fn source(info: &PkgInfo) -> (InfoSource, &str) {
+        (info.source, info.pkg.version.as_str())
+    }
+
+    #[test]
+    fn installed_package_comes_from_the_local_db() {
+        let fixture = fixture();
+        let napm = fixture.napm();
+        let cache = cache_with(&[("core", "foo", "3.0-1")]);
+
+        let info = napm.info_from("foo", &[], Some(&cache)).unwrap();
+        assert_eq!(source(&info), (InfoSource::Local, "1.0-1"));
+    }
+
+    #[test]
+    fn cache_is_used_before_the_sync_dbs() {
+        let fixture = fixture();
+        let napm = fixture.napm();
+        let cache = cache_with(&[("core", "bar", "3.0-1")]);
+
+        let info = napm.info_from("bar", &[], Some(&cache)).unwrap();
+        assert_eq!(source(&info), (InfoSource::Cache, "3.0-1"));
+        assert_eq!(info.csize, 42);
+    }
+
+    #[test]
+    fn sync_dbs_are_the_last_resort() {
+        let fixture = fixture();
+        let napm = fixture.napm();
+        let cache = cache_with(&[("core", "foo", "3.0-1")]);
+
+        for cache in [None, Some(&cache)] {
+            let info = napm.info_from("bar", &[], cache).unwrap();
+            assert_eq!(source(&info), (InfoSource::Sync, "1.0-1"));
+        }
+
+        assert!(matches!(
+            napm.info_from("missing", &[], Some(&cache)),
+            Err(Error::PackageNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,106 @@
+use alpm_sys::alpm_handle_t;
+use std::ptr;
+use std::sync::{
+    Mutex,
+    atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+
+use crate::error::{Error, Result};
+use crate::napm::Napm;
+use crate::{log_fatal, log_warn};
+
+static LOCK_FILE: Mutex<Option<String>> = Mutex::new(None);
+static COMMITTING: AtomicBool = AtomicBool::new(false);
+static TRANS_HANDLE: AtomicPtr<alpm_handle_t> = AtomicPtr::new(ptr::null_mut());
+static SIGNALS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn install_handler() -> Result<()> {
+    ctrlc::set_handler(on_signal).map_err(|_| Error::SignalHandler)
+}
+
+fn on_signal() {
+    if SIGNALS.fetch_add(1, Ordering::SeqCst) > 0 {
+        log_fatal!("Interrupted again, exiting immediately");
+        std::process::exit(130);
+    }
+
+    if COMMITTING.load(Ordering::SeqCst) {
+        log_warn!(
+            "Interrupting a transaction commit may break the system, waiting for it to finish (interrupt again to force exit)"
+        );
+        return;
+    }
+
+    let handle = TRANS_HANDLE.swap(ptr::null_mut(), Ordering::SeqCst);
+    let lock_file = LOCK_FILE.lock().unwrap().take();
+
+    if handle.is_null() && lock_file.is_none() {
+        log_warn!("Interrupted");
+    } else {
+        log_warn!("Interrupted, releasing the database lock");
+        release(handle, lock_file);
+    }
+
+    std::process::exit(130);
+}
+
+// what Drop does, the handle lives in Napm on the main thread so only its pointer is at hand
+fn release(handle: *mut alpm_handle_t, lock_file: Option<String>) {
+    if !handle.is_null() {
+        unsafe { alpm_sys::alpm_trans_release(handle) };
+    }
+
+    if let Some(lock_file) = lock_file {
+        let _ = std::fs::remove_file(lock_file);
+    }
+}
+
+impl Napm {
+    pub(super) fn mark_locked(&self) {
+        *LOCK_FILE.lock().unwrap() = Some(self.h().lockfile().to_string());
+    }
+
+    pub(super) fn mark_unlocked(&self) {
+        *LOCK_FILE.lock().unwrap() = None;
+    }
+
+    pub(super) fn mark_trans_active(&self, active: bool) {
+        let handle = if active {
+            self.h().as_alpm_handle_t()
+        } else {
+            ptr::null_mut()
+        };
+        TRANS_HANDLE.store(handle, Ordering::SeqCst);
+    }
+
+    pub(super) fn mark_committing(&self, committing: bool) {
+        COMMITTING.store(committing, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napm::fixtures::Fixture;
+    use alpm::TransFlag;
+
+    #[test]
+    fn release_frees_the_transaction_before_the_lock() {
+        let fixture = Fixture::new("interrupt");
+        let lock = fixture.dir.join("db/db.lck");
+        let mut napm = fixture.napm();
+
+        napm.trans_init(TransFlag::NONE).unwrap();
+        assert!(lock.exists());
+
+        release(
+            napm.h().as_alpm_handle_t(),
+            Some(lock.to_string_lossy().to_string()),
+        );
+        assert!(!lock.exists());
+
+        // with the transaction gone alpm accepts a new one
+        napm.h_mut().trans_init(TransFlag::NONE).unwrap();
+        napm.trans_release().unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::error::Result;
+use crate::napm::Napm;
+use crate::pkg::Pkg;
+
+const PACFILES_DIR: &str = "/etc";
+
+// the paths are the ones inside the root, like the owners in its local database know them
+pub struct Pacfile {
+    pub path: PathBuf,
+    pub original: PathBuf,
+    pub owner: Option<Pkg>,
+    root: PathBuf,
+}
+
+impl Pacfile {
+    pub fn on_disk(&self, path: &Path) -> PathBuf {
+        self.root.join(path.strip_prefix("/").unwrap_or(path))
+    }
+}
+
+// foo.pacnew, foo.pacsave and the numbered foo.pacsave.1 left by repeated removals
+fn original_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+
+    let stem = name.strip_suffix(".pacnew").or_else(|| {
+        let (stem, suffix) = name.rsplit_once(".pacsave")?;
+        let numbered = suffix
+            .strip_prefix('.')
+            .is_some_and(|n| n.parse::<u32>().is_ok());
+
+        (suffix.is_empty() || numbered).then_some(stem)
+    })?;
+
+    Some(path.with_file_name(stem))
+}
+
+fn collect_pacfiles(dir: &Path, found: &mut Vec<(PathBuf, PathBuf)>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => collect_pacfiles(&path, found),
+            Ok(_) => {
+                if let Some(original) = original_path(&path) {
+                    found.push((path, original));
+                }
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+impl Napm {
+    pub fn pacfiles(&self) -> Result<Vec<Pacfile>> {
+        let root = PathBuf::from(self.in_root("/"));
+        let in_root = |path: PathBuf| match path.strip_prefix(&root) {
+            Ok(inside) => Path::new("/").join(inside),
+            Err(_) => path,
+        };
+
+        let mut found = Vec::new();
+        collect_pacfiles(Path::new(&self.in_root(PACFILES_DIR)), &mut found);
+
+        let mut pacfiles = found
+            .into_iter()
+            .map(|(path, original)| (in_root(path), in_root(original)))
+            .map(|(path, original)| {
+                let owner = self
+                    .owns(&original.to_string_lossy())
+                    .ok()
+                    .and_then(|owners| owners.into_iter().next())
+                    .map(|(pkg, _)| pkg);
+
+                Pacfile {
+                    path,
+                    original,
+                    owner,
+                    root: root.clone(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        pacfiles.sort_by(|a, b| {
+            let owner_name = |p: &Pacfile| p.owner.as_ref().map(|pkg| pkg.name.clone());
+            owner_name(a)
+                .cmp(&owner_name(b))
+                .then_with(|| a.path.cmp(&b.path))
+        });
+
+        Ok(pacfiles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napm::fixtures::Fixture;
+
+    #[test]
+    fn pacfiles_are_found_in_the_root() {
+        let mut fixture = Fixture::new("pacfiles");
+        fixture
+            .local("foo", "1.0-1")
+            .files("foo", "1.0-1", &["etc/", "etc/foo.conf"]);
+
+        let etc = fixture.dir.join("root/etc");
+        fs::create_dir_all(&etc).unwrap();
+        fs::write(etc.join("foo.conf"), "").unwrap();
+        fs::write(etc.join("foo.conf.pacnew"), "").unwrap();
+        fs::write(etc.join("bar.conf.pacsave.1"), "").unwrap();
+
+        let napm = fixture.napm();
+        let pacfiles = napm.pacfiles().unwrap();
+
+        let found = pacfiles
+            .iter()
+            .map(|p| {
+                (
+                    p.path.to_str().unwrap(),
+                    p.original.to_str().unwrap(),
+                    p.owner.as_ref().map(|pkg| pkg.name.as_str()),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                ("/etc/bar.conf.pacsave.1", "/etc/bar.conf", None),
+                ("/etc/foo.conf.pacnew", "/etc/foo.conf", Some("foo")),
+            ]
+        );
+
+        let foo = &pacfiles[1];
+        assert_eq!(foo.on_disk(&foo.original), etc.join("foo.conf"));
+    }
+}
@@ -0,0 +1,78 @@
+use std::{fs, path::Path};
+
+use crate::error::Result;
+use crate::napm::Napm;
+use crate::napm::cache::NAPM_CACHE_FILE;
+
+#[derive(Debug, Clone, Default)]
+pub struct PkgCacheUsage {
+    pub files: usize,
+    pub size: u64,
+    pub installed_files: usize,
+    pub installed_size: u64,
+    pub obsolete_files: usize,
+    pub obsolete_size: u64,
+    pub sqlite_size: u64,
+}
+
+// name-pkgver-pkgrel-arch.pkg.tar.zst, the name itself may contain dashes
+fn parse_pkg_file_name(file_name: &str) -> Option<(&str, String)> {
+    let (stem, _) = file_name.split_once(".pkg.tar")?;
+
+    let mut parts = stem.rsplitn(4, '-');
+    let _arch = parts.next()?;
+    let pkgrel = parts.next()?;
+    let pkgver = parts.next()?;
+    let name = parts.next()?;
+
+    Some((name, format!("{pkgver}-{pkgrel}")))
+}
+
+impl Napm {
+    pub fn pkg_cache_usage(&self) -> Result<PkgCacheUsage> {
+        let handle = self.h();
+        let localdb = handle.localdb();
+
+        let mut usage = PkgCacheUsage {
+            sqlite_size: fs::metadata(NAPM_CACHE_FILE).map_or(0, |m| m.len()),
+            ..Default::default()
+        };
+
+        for dir in handle.cachedirs() {
+            let Ok(entries) = fs::read_dir(Path::new(dir)) else {
+                continue;
+            };
+
+            for entry in entries.flatten() {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+
+                if file_name.ends_with(".sig") {
+                    continue;
+                }
+
+                let Some((name, version)) = parse_pkg_file_name(&file_name) else {
+                    continue;
+                };
+
+                let size = entry.metadata().map_or(0, |m| m.len());
+
+                usage.files += 1;
+                usage.size += size;
+
+                let installed = localdb
+                    .pkg(name)
+                    .is_ok_and(|pkg| pkg.version().as_str() == version);
+
+                if installed {
+                    usage.installed_files += 1;
+                    usage.installed_size += size;
+                } else {
+                    usage.obsolete_files += 1;
+                    usage.obsolete_size += size;
+                }
+            }
+        }
+
+        Ok(usage)
+    }
+}
@@ -2,28 +2,51 @@ use indicatif::ProgressStyle;
 use std::sync::OnceLock;
 
 use crate::napm::Napm;
+use crate::napm::config::NapmConfig;
+
+const DEFAULT_PROGRESS_TEMPLATE: &str = "[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% {msg}";
+const DEFAULT_PROGRESS_CHARS: &str = "=> ";
 
 static PROGRESS_BAR_STYLE: OnceLock<ProgressStyle> = OnceLock::new();
 static PROGRESS_BAR_STYLE_FAILED: OnceLock<ProgressStyle> = OnceLock::new();
 
+static PROGRESS_TEMPLATE: OnceLock<Option<String>> = OnceLock::new();
+static PROGRESS_CHARS: OnceLock<Option<String>> = OnceLock::new();
+
 impl Napm {
-    pub fn progress_bar_style(failed: bool) -> &'static ProgressStyle {
-        let progress_chars = "=> ";
+    pub(super) fn set_progress_config(config: &NapmConfig) {
+        let _ = PROGRESS_TEMPLATE.set(config.progress_template.clone());
+        let _ = PROGRESS_CHARS.set(config.progress_chars.clone());
+    }
+
+    fn progress_chars() -> &'static str {
+        PROGRESS_CHARS
+            .get()
+            .and_then(Option::as_deref)
+            .unwrap_or(DEFAULT_PROGRESS_CHARS)
+    }
 
+    // the configured template replaces every non-failed default, both were validated on load
+    pub fn progress_style(default_template: &str) -> ProgressStyle {
+        let template = PROGRESS_TEMPLATE
+            .get()
+            .and_then(Option::as_deref)
+            .unwrap_or(default_template);
+
+        ProgressStyle::with_template(template)
+            .unwrap()
+            .progress_chars(Self::progress_chars())
+    }
+
+    pub fn progress_bar_style(failed: bool) -> &'static ProgressStyle {
         if failed {
             PROGRESS_BAR_STYLE_FAILED.get_or_init(|| {
                 ProgressStyle::with_template("[{elapsed:>3}] [{bar:40.red/blue}] [FAILED] {msg}")
                     .unwrap()
-                    .progress_chars(progress_chars)
+                    .progress_chars(Self::progress_chars())
             })
         } else {
-            PROGRESS_BAR_STYLE.get_or_init(|| {
-                ProgressStyle::with_template(
-                    "[{elapsed:>3}] [{bar:40.cyan/blue}] {percent:>3}% {msg}",
-                )
-                .unwrap()
-                .progress_chars(progress_chars)
-            })
+            PROGRESS_BAR_STYLE.get_or_init(|| Self::progress_style(DEFAULT_PROGRESS_TEMPLATE))
         }
     }
 }
@@ -0,0 +1,144 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use crate::error::{Error, Result};
+use crate::napm::{ARCH, Napm};
+use crate::pkg::Pkg;
+
+// a previous run may have linked the local database of another root
+fn link_local_db(target: &Path, link: &Path) -> Result<()> {
+    match fs::read_link(link) {
+        Ok(current) if current == target => return Ok(()),
+        Ok(_) => fs::remove_file(link)?,
+        Err(_) => {}
+    }
+
+    symlink(target, link)?;
+    Ok(())
+}
+
+impl Napm {
+    pub(super) fn cache_server_urls(&self, repo: &str) -> Vec<String> {
+        self.napm_config
+            .cache_servers
+            .iter()
+            .map(|server| server.replace("$repo", repo).replace("$arch", ARCH))
+            .collect()
+    }
+
+    // a caching proxy usually only serves package files, so it is left out of db syncs
+    // unless CacheServerSync is set
+    pub(super) fn set_cache_servers(&mut self, enabled: bool) -> Result<()> {
+        let cache_servers: HashMap<String, Vec<String>> = self
+            .h()
+            .syncdbs()
+            .iter()
+            .map(|db| (db.name().to_string(), self.cache_server_urls(db.name())))
+            .collect();
+
+        for db in self.h_mut().syncdbs_mut() {
+            let cache_servers = &cache_servers[db.name()];
+
+            let servers: Vec<String> = cache_servers
+                .iter()
+                .filter(|_| enabled)
+                .cloned()
+                .chain(
+                    db.servers()
+                        .iter()
+                        .filter(|server| !cache_servers.iter().any(|c| c == server))
+                        .map(str::to_string),
+                )
+                .collect();
+
+            db.set_servers(servers.iter().map(String::as_str))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn sync_dbs(&mut self, force: bool) -> Result<bool> {
+        self.update_dbs(".db", force)
+    }
+
+    pub fn sync_files(&mut self, force: bool) -> Result<bool> {
+        self.update_dbs(".files", force)
+    }
+
+    // like checkupdates: the sync databases go to a directory owned by the user,
+    // the local database is shared through a symlink
+    pub fn use_private_db_path(&mut self) -> Result<PathBuf> {
+        let cache_home = env::var("XDG_CACHE_HOME")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var("HOME")
+                    .ok()
+                    .map(|home| PathBuf::from(home).join(".cache"))
+            })
+            .ok_or(Error::System)?;
+
+        let db_path = cache_home.join("napm").join("db");
+
+        fs::create_dir_all(db_path.join("sync"))?;
+
+        link_local_db(
+            &PathBuf::from(self.in_root(&self.config.db_path)).join("local"),
+            &db_path.join("local"),
+        )?;
+
+        self.options.db_path = Some(db_path.to_string_lossy().to_string());
+        self.reset()?;
+
+        Ok(db_path)
+    }
+
+    // installed package and its newer sync version
+    pub fn upgradable(&self) -> Vec<(Pkg, Pkg)> {
+        let handle = self.h();
+
+        handle
+            .localdb()
+            .pkgs()
+            .into_iter()
+            .filter_map(|pkg| {
+                let new = pkg.sync_new_version(handle.syncdbs())?;
+                Some((Pkg::from(pkg), Pkg::from(new)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_db_link_follows_the_root() {
+        let dir = env::temp_dir().join(format!("napm-sync-link-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let link = dir.join("local");
+
+        link_local_db(Path::new("/var/lib/pacman/local"), &link).unwrap();
+        assert_eq!(
+            fs::read_link(&link).unwrap(),
+            Path::new("/var/lib/pacman/local")
+        );
+
+        link_local_db(Path::new("/mnt/var/lib/pacman/local"), &link).unwrap();
+        assert_eq!(
+            fs::read_link(&link).unwrap(),
+            Path::new("/mnt/var/lib/pacman/local")
+        );
+
+        // unchanged, nothing to redo
+        link_local_db(Path::new("/mnt/var/lib/pacman/local"), &link).unwrap();
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+use rusqlite::Connection;
+use std::{
+    cmp::Ordering,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::error::Result;
+use crate::log_warn;
+use crate::napm::Napm;
+use crate::napm::cache::NAPM_CACHE_FILE;
+
+pub struct TransactionItem {
+    pub name: String,
+    pub old_version: Option<String>,
+    pub new_version: Option<String>,
+    pub action: String,
+}
+
+pub struct Transaction {
+    pub time: String,
+    pub operation: String,
+    pub items: Vec<TransactionItem>,
+}
+
+impl Napm {
+    // created on demand and never dropped by cache rebuilds
+    fn init_transaction_schema(conn: &Connection) -> Result<()> {
+        conn.execute_batch(
+            "
+            CREATE TABLE IF NOT EXISTS transactions (
+                id INTEGER PRIMARY KEY,
+                timestamp INTEGER NOT NULL,
+                operation TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS transaction_items (
+                transaction_id INTEGER NOT NULL REFERENCES transactions(id),
+                name TEXT NOT NULL,
+                old_version TEXT,
+                new_version TEXT,
+                action TEXT NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_transaction_items_name ON transaction_items(name);
+            ",
+        )?;
+
+        Ok(())
+    }
+
+    fn trans_items(&self) -> Vec<TransactionItem> {
+        let handle = self.h();
+        let localdb = handle.localdb();
+
+        let added = handle.trans_add().into_iter().map(|new| {
+            let old = localdb.pkg(new.name()).ok();
+
+            let action = match old.map(|old| new.version().vercmp(old.version())) {
+                None => "install",
+                Some(Ordering::Greater) => "upgrade",
+                Some(Ordering::Less) => "downgrade",
+                Some(Ordering::Equal) => "reinstall",
+            };
+
+            TransactionItem {
+                name: new.name().to_string(),
+                old_version: old.map(|old| old.version().to_string()),
+                new_version: Some(new.version().to_string()),
+                action: action.to_string(),
+            }
+        });
+
+        let removed = handle
+            .trans_remove()
+            .into_iter()
+            .map(|old| TransactionItem {
+                name: old.name().to_string(),
+                old_version: Some(old.version().to_string()),
+                new_version: None,
+                action: "remove".to_string(),
+            });
+
+        added.chain(removed).collect()
+    }
+
+    fn record_transaction(operation: &str, items: &[TransactionItem]) -> Result<()> {
+        let mut conn = Self::open_cache_writer()?;
+        Self::init_transaction_schema(&conn)?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO transactions (timestamp, operation) VALUES (?1, ?2)",
+            (timestamp, operation),
+        )?;
+        let id = tx.last_insert_rowid();
+
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO transaction_items (transaction_id, name, old_version, new_version, action) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+
+            for item in items {
+                insert.execute((
+                    id,
+                    &item.name,
+                    &item.old_version,
+                    &item.new_version,
+                    &item.action,
+                ))?;
+            }
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    // the history is best effort, a failure to write it never fails the transaction
+    pub(super) fn trans_commit_recorded(&mut self, operation: &str) -> Result<()> {
+        let items = self.trans_items();
+
+        for item in &items {
+            crate::log::write_log_file(
+                "TRANS",
+                &format!(
+                    "{operation}: {} {} {} -> {}",
+                    item.action,
+                    item.name,
+                    item.old_version.as_deref().unwrap_or("none"),
+                    item.new_version.as_deref().unwrap_or("none")
+                ),
+            );
+        }
+
+        let result = self.trans_commit();
+
+        crate::log::write_log_file(
+            "TRANS",
+            &format!(
+                "{operation}: {}",
+                if result.is_ok() {
+                    "committed"
+                } else {
+                    "failed"
+                }
+            ),
+        );
+
+        result?;
+
+        if let Err(e) = Self::record_transaction(operation, &items) {
+            log_warn!("Could not record the transaction in the history: {e}");
+        }
+
+        Ok(())
+    }
+
+    pub fn transactions(&self, package: Option<&str>, limit: usize) -> Result<Vec<Transaction>> {
+        let cache_path = Path::new(NAPM_CACHE_FILE);
+
+        if !cache_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let conn = Self::open_cache()?;
+
+        let has_history = conn
+            .prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'transactions'")?
+            .exists([])?;
+
+        if !has_history {
+            return Ok(Vec::new());
+        }
+
+        let mut stmt = conn.prepare(
+            "
+            SELECT DISTINCT t.id, datetime(t.timestamp, 'unixepoch', 'localtime'), t.operation
+            FROM transactions AS t
+            JOIN transaction_items AS i ON i.transaction_id = t.id
+            WHERE ?1 IS NULL OR i.name = ?1
+            ORDER BY t.id DESC
+            LIMIT ?2
+            ",
+        )?;
+
+        let headers = stmt
+            .query_map((package, limit as i64), |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .filter_map(rusqlite::Result::ok)
+            .collect::<Vec<_>>();
+
+        let mut items_stmt = conn.prepare(
+            "
+            SELECT name, old_version, new_version, action
+            FROM transaction_items
+            WHERE transaction_id = ?1 AND (?2 IS NULL OR name = ?2)
+            ORDER BY name
+            ",
+        )?;
+
+        let mut transactions = Vec::new();
+
+        // oldest first, so a package timeline reads top to bottom
+        for (id, time, operation) in headers.into_iter().rev() {
+            let items = items_stmt
+                .query_map((id, package), |row| {
+                    Ok(TransactionItem {
+                        name: row.get(0)?,
+                        old_version: row.get(1)?,
+                        new_version: row.get(2)?,
+                        action: row.get(3)?,
+                    })
+                })?
+                .filter_map(rusqlite::Result::ok)
+                .collect();
+
+            transactions.push(Transaction {
+                time,
+                operation,
+                items,
+            });
+        }
+
+        Ok(transactions)
+    }
+}
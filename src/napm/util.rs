@@ -1,4 +1,4 @@
-use alpm::{Alpm, SigLevel};
+use alpm::{Alpm, DepModVer, Depend, SigLevel};
 
 use crate::napm::*;
 
@@ -11,6 +11,23 @@ impl Napm {
         self.handle.as_mut().unwrap()
     }
 
+    pub fn dry_run(&self) -> bool {
+        self.options.dry_run
+    }
+
+    pub fn auto_repair(&self) -> bool {
+        !self.options.no_repair && !self.napm_config.no_auto_repair
+    }
+
+    // not the running system, its processes and hooks have nothing to do with the host
+    pub fn foreign_root(&self) -> bool {
+        self.options.root.as_deref().is_some_and(|root| root != "/")
+    }
+
+    pub fn search_history_enabled(&self) -> bool {
+        !self.napm_config.no_search_history
+    }
+
     pub fn local_pkg(&self, name: &str) -> Result<Pkg> {
         match self.h().localdb().pkg(name) {
             Ok(pkg) => Ok(Pkg::from(pkg)),
@@ -35,8 +52,84 @@ impl Napm {
         Err(Error::PackageNotFound(name.to_string()))
     }
 
+    // a dependency spec like linux>=6.1, the newest satisfying version across all repos wins
+    pub fn pkg_constrained(&self, spec: &str) -> Result<Pkg> {
+        let dep = Depend::new(spec);
+
+        if let DepModVer::Any = dep.depmodver() {
+            return self.pkg(spec);
+        }
+
+        let syncdbs = self.h().syncdbs();
+
+        if syncdbs.iter().all(|db| db.pkg(dep.name()).is_err()) {
+            return Err(Error::PackageNotFound(dep.name().to_string()));
+        }
+
+        // a provider is what alpm falls back to when the package itself doesn't satisfy it
+        syncdbs
+            .iter()
+            .filter_map(|db| Self::satisfier(db, spec))
+            .filter(|pkg| pkg.name() == dep.name())
+            .reduce(|newest, pkg| {
+                if pkg.version() > newest.version() {
+                    pkg
+                } else {
+                    newest
+                }
+            })
+            .map(Pkg::from)
+            .ok_or_else(|| Error::NoSatisfyingVersion(dep.name().to_string(), spec.to_string()))
+    }
+
     pub fn pkgs(&self, names: &[&str]) -> Vec<Result<Pkg>> {
-        names.iter().map(|name| self.pkg(name)).collect()
+        names
+            .iter()
+            .map(|name| self.pkg_constrained(name))
+            .collect()
+    }
+
+    // an installed package comes from the db with the same version, a name-only match is
+    // the fallback for installs that are older than the sync dbs
+    pub fn origin_repo(&self, name: &str) -> Option<String> {
+        let local = self.h().localdb().pkg(name).ok()?;
+        let syncdbs = self.h().syncdbs();
+
+        syncdbs
+            .iter()
+            .find(|db| {
+                db.pkg(name)
+                    .is_ok_and(|pkg| pkg.version() == local.version())
+            })
+            .or_else(|| syncdbs.iter().find(|db| db.pkg(name).is_ok()))
+            .map(|db| db.name().to_string())
+    }
+
+    // keeps the installed packages, attributed to the repo they came from rather than the one
+    // winning by priority, which may be another repo with the same package name
+    pub fn installed_matches<T>(&self, matches: Vec<(Pkg, T)>) -> Vec<(Pkg, T)> {
+        matches
+            .into_iter()
+            .filter_map(|(pkg, matched)| {
+                let local = self.h().localdb().pkg(pkg.name.as_str()).ok()?;
+                let pkg = Pkg {
+                    repo: self.origin_repo(&pkg.name)?,
+                    version: local.version().to_string(),
+                    ..pkg
+                };
+                Some((pkg, matched))
+            })
+            .collect()
+    }
+
+    pub fn check_repos(&self, repos: &[String]) -> Result<()> {
+        for repo in repos {
+            if !self.config.repos.iter().any(|r| &r.name == repo) {
+                return Err(Error::UnknownRepo(repo.clone()));
+            }
+        }
+
+        Ok(())
     }
 
     pub fn parse_siglevel(values: &[String]) -> Result<SigLevel> {
@@ -93,3 +186,70 @@ impl Napm {
         Ok(level)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napm::{actions::ListFilter, fixtures::Fixture};
+
+    fn pkg(name: &str, repo: &str) -> Pkg {
+        Pkg {
+            name: name.to_string(),
+            version: "2.0-1".to_string(),
+            repo: repo.to_string(),
+            desc: String::new(),
+        }
+    }
+
+    // core comes first and wins by priority, but foo was installed from custom
+    fn collision() -> Fixture {
+        let mut fixture = Fixture::new("origin");
+        fixture
+            .local("foo", "1.0-1")
+            .local("bar", "1.0-1")
+            .sync("core", &[("foo", "2.0-1", &[])])
+            .sync("custom", &[("foo", "1.0-1", &[])]);
+        fixture
+    }
+
+    #[test]
+    fn origin_repo_prefers_the_matching_version() {
+        let fixture = collision();
+        let napm = fixture.napm();
+
+        assert_eq!(napm.origin_repo("foo").as_deref(), Some("custom"));
+        assert_eq!(napm.origin_repo("bar"), None);
+        assert_eq!(napm.origin_repo("missing"), None);
+    }
+
+    #[test]
+    fn installed_matches_use_the_origin_repo() {
+        let fixture = collision();
+        let napm = fixture.napm();
+
+        let matches = napm.installed_matches(vec![
+            (pkg("foo", "core"), "/usr/bin/foo"),
+            (pkg("baz", "core"), "/usr/bin/baz"),
+        ]);
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].0.repo, "custom");
+        assert_eq!(matches[0].0.version, "1.0-1");
+    }
+
+    #[test]
+    fn foreign_packages_are_the_ones_without_origin() {
+        let fixture = collision();
+        let napm = fixture.napm();
+
+        let foreign = napm.list(ListFilter::Foreign);
+
+        assert_eq!(
+            foreign
+                .iter()
+                .map(|pkg| pkg.name.as_str())
+                .collect::<Vec<_>>(),
+            ["bar"]
+        );
+    }
+}
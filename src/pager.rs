@@ -0,0 +1,82 @@
+use std::env;
+use std::io::{ErrorKind, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::Result;
+use crate::util::terminal_height;
+
+const DEFAULT_PAGER: &str = "less -R";
+
+static NO_PAGER: AtomicBool = AtomicBool::new(false);
+
+pub fn set_no_pager(no_pager: bool) {
+    NO_PAGER.store(no_pager, Ordering::Relaxed);
+}
+
+// collects the lines of a command, they are only shown once it knows how long the output is
+#[derive(Default)]
+pub struct Pager {
+    lines: Vec<String>,
+}
+
+impl Pager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn line(&mut self, line: String) {
+        self.lines.push(line);
+    }
+
+    fn pager_command() -> Option<String> {
+        match env::var("PAGER") {
+            Ok(pager) if pager.trim().is_empty() => None,
+            Ok(pager) => Some(pager),
+            Err(_) => Some(DEFAULT_PAGER.to_string()),
+        }
+    }
+
+    pub fn show(self) -> Result<()> {
+        let mut text = self.lines.join("\n");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+
+        let fits = terminal_height().is_none_or(|height| self.lines.len() < height);
+
+        let pager = if fits || NO_PAGER.load(Ordering::Relaxed) {
+            None
+        } else {
+            Self::pager_command()
+        };
+
+        let Some(pager) = pager else {
+            print!("{text}");
+            return Ok(());
+        };
+
+        // $PAGER may carry its own arguments
+        let Ok(mut child) = Command::new("sh")
+            .arg("-c")
+            .arg(&pager)
+            .stdin(Stdio::piped())
+            .spawn()
+        else {
+            print!("{text}");
+            return Ok(());
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            // quitting the pager early closes the pipe, which is not an error
+            match stdin.write_all(text.as_bytes()) {
+                Err(e) if e.kind() != ErrorKind::BrokenPipe => return Err(e.into()),
+                _ => {}
+            }
+        }
+
+        child.wait()?;
+
+        Ok(())
+    }
+}
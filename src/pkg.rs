@@ -13,16 +13,15 @@ pub struct Pkg {
 
 impl Pkg {
     pub fn into_package_ref(self, handle: &Alpm) -> Result<&Package> {
-        let expect_msg = format!("package '{}' not found in '{}'", self.name, self.repo);
-
         if self.repo == "local" {
             handle.localdb().pkg(self.name)
         } else {
+            // the repo may be gone since the package was looked up in the cache
             handle
                 .syncdbs()
                 .iter()
                 .find(|db| *db.name() == self.repo)
-                .expect(&expect_msg)
+                .ok_or(Error::UnknownRepo(self.repo))?
                 .pkg(self.name)
         }
         .map_err(|_| Error::FindPkg)
@@ -65,3 +64,46 @@ impl From<&Package> for Pkg {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::napm::fixtures::Fixture;
+
+    fn pkg(name: &str, repo: &str) -> Pkg {
+        Pkg {
+            name: name.to_string(),
+            version: "1.0-1".to_string(),
+            repo: repo.to_string(),
+            desc: String::new(),
+        }
+    }
+
+    #[test]
+    fn package_ref_of_a_missing_repo_is_an_error() {
+        let mut fixture = Fixture::new("pkg-ref");
+        fixture
+            .local("foo", "1.0-1")
+            .sync("core", &[("foo", "1.0-1", &[])]);
+        let napm = fixture.napm();
+        let handle = napm.h();
+
+        assert!(matches!(
+            pkg("foo", "gone").into_package_ref(handle),
+            Err(Error::UnknownRepo(repo)) if repo == "gone"
+        ));
+        assert!(matches!(
+            pkg("missing", "core").into_package_ref(handle),
+            Err(Error::FindPkg)
+        ));
+
+        assert_eq!(
+            pkg("foo", "core").into_package_ref(handle).unwrap().name(),
+            "foo"
+        );
+        assert_eq!(
+            pkg("foo", "local").into_package_ref(handle).unwrap().name(),
+            "foo"
+        );
+    }
+}
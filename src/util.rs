@@ -1,17 +1,95 @@
-use std::collections::HashMap;
+use indicatif::{MultiProgress, ProgressDrawTarget};
 use std::env;
+use std::io::IsTerminal;
 use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use crate::ansi::*;
 use crate::error::{Error, Result};
-use crate::napm::cache::NAPM_CACHE_FILE;
+use crate::napm::cache::{CacheKind, NAPM_CACHE_FILE};
 use crate::{format_action_required, log_error, log_info, log_warn};
 
+static NO_CONFIRM: AtomicBool = AtomicBool::new(false);
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+static NO_REPAIR: AtomicBool = AtomicBool::new(false);
+static ROOT: OnceLock<Option<String>> = OnceLock::new();
+static SYSROOT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_noconfirm(noconfirm: bool) {
+    NO_CONFIRM.store(noconfirm, Ordering::Relaxed);
+}
+
+pub fn noconfirm() -> bool {
+    NO_CONFIRM.load(Ordering::Relaxed)
+}
+
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+pub fn offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+pub fn set_no_repair(no_repair: bool) {
+    NO_REPAIR.store(no_repair, Ordering::Relaxed);
+}
+
+pub fn no_repair() -> bool {
+    NO_REPAIR.load(Ordering::Relaxed)
+}
+
+pub fn set_root(root: Option<String>) {
+    let _ = ROOT.set(root);
+}
+
+pub fn root() -> Option<&'static str> {
+    ROOT.get().and_then(|r| r.as_deref())
+}
+
+pub fn set_sysroot(sysroot: bool) {
+    SYSROOT.store(sysroot, Ordering::Relaxed);
+}
+
+pub fn sysroot() -> bool {
+    SYSROOT.load(Ordering::Relaxed)
+}
+
+// bars are redrawn with control characters, which only make sense on a terminal
+pub fn progress_enabled() -> bool {
+    std::io::stderr().is_terminal() && crate::log::verbosity() > crate::log::VERBOSITY_QUIET
+}
+
+// bars added to a hidden MultiProgress are hidden too, callers log a plain line instead
+pub fn multi_progress() -> MultiProgress {
+    if progress_enabled() {
+        MultiProgress::new()
+    } else {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    }
+}
+
 pub fn confirm(prompt: &str, default_yes: bool) -> Result<bool> {
     use std::io::{self, Write};
 
+    if noconfirm() {
+        eprintln!(
+            "{}",
+            format_action_required!(
+                "{} [{}]: {}",
+                prompt,
+                if default_yes { "Y/n" } else { "y/N" },
+                if default_yes { "y" } else { "n" }
+            )
+        );
+
+        return Ok(default_yes);
+    }
+
     loop {
         eprint!(
             "{}",
@@ -41,6 +119,16 @@ pub fn confirm(prompt: &str, default_yes: bool) -> Result<bool> {
 pub fn choose(prompt: &str, options: &[String], default: u32) -> Result<u32> {
     use std::io::{self, Write};
 
+    if noconfirm() {
+        log_warn!("{}", prompt);
+        eprintln!(
+            "{}",
+            format_action_required!("Your choice (default = {}): {}", default, default)
+        );
+
+        return Ok(default);
+    }
+
     loop {
         log_warn!("{}", prompt);
 
@@ -77,28 +165,163 @@ pub fn choose(prompt: &str, options: &[String], default: u32) -> Result<u32> {
     }
 }
 
-pub const PE_TOOLS: &[&str] = &["sudo", "doas", "pkexec"];
+// 1-based, accepts numbers and ranges separated by spaces or commas, e.g. `1 3,5-7`
+fn parse_selection(input: &str, max: usize) -> Option<Vec<usize>> {
+    let mut selection = Vec::new();
 
-fn detect_pe_program() -> Result<String> {
-    for candidate in PE_TOOLS {
-        if which(candidate) {
-            return Ok(candidate.to_string());
+    for part in input.split([' ', ',']).filter(|p| !p.is_empty()) {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+            None => {
+                let n = part.parse().ok()?;
+                (n, n)
+            }
+        };
+
+        if start == 0 || start > end || end > max {
+            return None;
+        }
+
+        for n in start..=end {
+            if !selection.contains(&n) {
+                selection.push(n);
+            }
         }
     }
 
-    Err(Error::NoPETool)
+    Some(selection)
+}
+
+pub fn select(prompt: &str, max: usize) -> Result<Vec<usize>> {
+    use std::io::{self, Write};
+
+    if noconfirm() {
+        eprintln!("{}", format_action_required!("{prompt}: "));
+        return Ok(Vec::new());
+    }
+
+    loop {
+        eprint!("{}", format_action_required!("{prompt}: "));
+        io::stderr().flush()?;
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+
+        match parse_selection(input.trim(), max) {
+            Some(selection) => return Ok(selection),
+            None => log_error!(
+                "Invalid selection '{}', use numbers or ranges between 1 and {max}",
+                input.trim()
+            ),
+        }
+    }
+}
+
+// `*` matches any run of characters, `/` included, and `?` any single one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    let (mut p, mut t) = (0, 0);
+    // where the last `*` was and what it matched up to, to backtrack on a mismatch
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
 }
 
-pub const SHELLS: &[&str] = &["bash"]; // TODO: zsh, fish, etc.
+// None when stdout is not a terminal
+pub fn terminal_width() -> Option<usize> {
+    console::Term::stdout()
+        .size_checked()
+        .map(|(_, cols)| cols as usize)
+}
+
+// None when stdout is not a terminal
+pub fn terminal_height() -> Option<usize> {
+    console::Term::stdout()
+        .size_checked()
+        .map(|(rows, _)| rows as usize)
+}
 
-fn detect_shell() -> Result<String> {
-    for candidate in SHELLS {
+// cuts to at most `width` columns, marking the cut with an ellipsis
+pub fn truncate_to_width(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+
+    let mut out = String::new();
+    let mut used = 0;
+
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w + 1 > width {
+            break;
+        }
+        out.push(c);
+        used += w;
+    }
+
+    out.push('…');
+    out
+}
+
+pub fn human_size(bytes: i64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    let sign = if bytes < 0 { "-" } else { "" };
+    let abs = bytes.unsigned_abs();
+
+    if abs < 1024 {
+        return format!("{sign}{abs} B");
+    }
+
+    let mut size = abs as f64 / 1024.0;
+    let mut unit = 0;
+
+    // compare what will be printed, 1048575 B is 1.0 MiB and not 1024.0 KiB
+    while (size * 10.0).round() / 10.0 >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{sign}{size:.1} {}", UNITS[unit])
+}
+
+pub const PE_TOOLS: &[&str] = &["sudo", "doas", "run0", "pkexec", "systemd-run"];
+
+// arguments needed before anything else for the program to run a command as root
+fn pe_program_args(pe_program: &str) -> &'static [&'static str] {
+    match pe_program {
+        // without --same-dir the command runs from /, breaking relative paths like ./foo.pkg.tar.zst
+        "systemd-run" => &["--pty", "--same-dir", "--uid=0"],
+        _ => &[],
+    }
+}
+
+fn detect_pe_program() -> Result<String> {
+    for candidate in PE_TOOLS {
         if which(candidate) {
             return Ok(candidate.to_string());
         }
     }
 
-    Err(Error::NoShell)
+    Err(Error::NoPETool)
 }
 
 fn which(cmd: &str) -> bool {
@@ -134,20 +357,16 @@ fn as_root_cmd(cmd: &str, args: Vec<String>) -> Result<(Command, String)> {
     let mut command = if is_root() {
         Command::new(cmd)
     } else {
-        Command::new(detect_pe_program()?)
+        let pe_program = detect_pe_program()?;
+        let mut command = Command::new(&pe_program);
+        command.args(pe_program_args(&pe_program));
+        command
     };
 
-    let envs = {
-        let mut vars = HashMap::new();
-
-        for k in ["RUST_BACKTRACE"] {
-            if let Ok(v) = env::var(k) {
-                vars.insert(k, v);
-            }
-        }
-
-        vars
-    };
+    let envs = ["RUST_BACKTRACE", "NAPM_ROOT"]
+        .into_iter()
+        .filter_map(|k| env::var(k).ok().map(|v| (k, v)))
+        .collect::<Vec<_>>();
 
     let safe_arg = |a: &str| {
         if a.chars().all(|c| {
@@ -155,7 +374,8 @@ fn as_root_cmd(cmd: &str, args: Vec<String>) -> Result<(Command, String)> {
         }) {
             a.to_string()
         } else {
-            format!("\"{a}\"")
+            // only used for display, single quotes keep it copy-pastable into a shell
+            format!("'{}'", a.replace('\'', "'\\''"))
         }
     };
 
@@ -165,7 +385,7 @@ fn as_root_cmd(cmd: &str, args: Vec<String>) -> Result<(Command, String)> {
         format!(
             "{} ",
             envs.iter()
-                .map(|(k, v)| format!("{k}={v}"))
+                .map(|(k, v)| format!("{k}={}", safe_arg(v)))
                 .collect::<Vec<_>>()
                 .join(" ")
         )
@@ -178,44 +398,25 @@ fn as_root_cmd(cmd: &str, args: Vec<String>) -> Result<(Command, String)> {
         .join(" ");
 
     if is_root() {
-        command.envs(envs);
-        command.args(args);
-    } else if !envs.is_empty() {
-        match detect_pe_program()?.as_str() {
-            "sudo" => {
-                for (k, v) in envs.iter() {
-                    command.arg(format!("{k}={v}"));
-                }
-
-                command.arg(cmd);
-
-                command.args(args);
-            }
-            "doas" | "pkexec" => {
-                let shell = detect_shell()?;
-
-                if shell == "bash" {
-                    // TODO: match when more shells
-                    command.arg(shell);
-                    command.arg("-c");
-                    command.arg(format!("{envs_str}{args_str}"));
-                } else {
-                    unimplemented!("Unhandled shell: {shell}");
-                }
-            }
-            other_pe_program => unimplemented!("Unhandled PE program: {other_pe_program}"),
-        }
+        command.envs(envs.iter().cloned());
+        command.args(&args);
     } else {
-        command.arg(cmd);
-        command.args(args);
+        command.args(pe_command_args(&detect_pe_program()?, &envs, cmd, &args)?);
     }
 
     let cmd_display = if is_root() {
         format!("{}{} {}", envs_str, safe_arg(cmd), args_str)
     } else {
+        let pe_program = detect_pe_program()?;
+
         format!(
             "{} {}{} {}",
-            detect_pe_program()?,
+            [pe_program.as_str()]
+                .iter()
+                .chain(pe_program_args(&pe_program))
+                .copied()
+                .collect::<Vec<_>>()
+                .join(" "),
             envs_str,
             safe_arg(cmd),
             args_str
@@ -225,12 +426,70 @@ fn as_root_cmd(cmd: &str, args: Vec<String>) -> Result<(Command, String)> {
     Ok((command, cmd_display))
 }
 
+// what the privilege escalation program gets after its own arguments
+fn pe_command_args(
+    pe_program: &str,
+    envs: &[(&str, String)],
+    cmd: &str,
+    args: &[String],
+) -> Result<Vec<String>> {
+    let mut argv = Vec::new();
+
+    if !envs.is_empty() {
+        match pe_program {
+            "sudo" => argv.extend(envs.iter().map(|(k, v)| format!("{k}={v}"))),
+            "run0" | "systemd-run" => {
+                argv.extend(envs.iter().map(|(k, v)| format!("--setenv={k}={v}")))
+            }
+            // neither can pass variables, env sets them without a shell parsing anything
+            "doas" | "pkexec" => {
+                argv.push("env".to_string());
+                argv.extend(envs.iter().map(|(k, v)| format!("{k}={v}")));
+            }
+            _ => return Err(Error::NoPETool),
+        }
+    }
+
+    argv.push(cmd.to_string());
+    argv.extend(args.iter().cloned());
+
+    Ok(argv)
+}
+
 fn napm_as_root_cmd(args: Vec<String>) -> Result<(Command, String)> {
     let cmd: &str = &current_exe();
 
     as_root_cmd(cmd, args)
 }
 
+fn napm_subcommand_args(args: &[&str]) -> Vec<String> {
+    let mut args = args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+
+    if noconfirm() {
+        args.push("--noconfirm".to_string());
+    }
+
+    if offline() {
+        args.push("--offline".to_string());
+    }
+
+    if no_repair() {
+        args.push("--no-repair".to_string());
+    }
+
+    if let Some(root) = root() {
+        args.push(if sysroot() { "--sysroot" } else { "--root" }.to_string());
+        args.push(root.to_string());
+    }
+
+    match crate::log::verbosity() {
+        crate::log::VERBOSITY_QUIET => args.push("--quiet".to_string()),
+        v => args.extend((crate::log::VERBOSITY_NORMAL..v).map(|_| "--verbose".to_string())),
+    }
+
+    args
+}
+
 pub fn require_root() -> Result<()> {
     if is_root() {
         return Ok(());
@@ -260,12 +519,22 @@ pub fn require_root() -> Result<()> {
     Err(cmd.exec().into())
 }
 
-pub fn run_cache_update() -> Result<()> {
-    let (mut cmd, cmd_display) =
-        napm_as_root_cmd(vec!["update".to_string(), "--files".to_string()])?;
+pub fn run_cache_update(reason: &str, kind: CacheKind) -> Result<()> {
+    if offline() {
+        log_warn!("{reason}");
+        return Err(Error::NotCachedOffline("The package cache".to_string()));
+    }
+
+    let (mut cmd, cmd_display) = napm_as_root_cmd(napm_subcommand_args(&[
+        "sync",
+        match kind {
+            CacheKind::DescOnly => "--cache",
+            CacheKind::Full => "--files",
+        },
+    ]))?;
 
     if is_root() {
-        log_warn!("System needs to be updated");
+        log_warn!("{reason}");
 
         let prompt = format!(
             "Do you want to run {ANSI_YELLOW}{}{ANSI_RESET} automatically?",
@@ -278,9 +547,7 @@ pub fn run_cache_update() -> Result<()> {
 
         log_info!("# {}", cmd_display);
     } else {
-        log_warn!(
-            "System needs to be updated and you need {ANSI_YELLOW}root priviledges{ANSI_RESET} for that"
-        );
+        log_warn!("{reason} and you need {ANSI_YELLOW}root priviledges{ANSI_RESET} for that");
 
         let prompt = format!(
             "Do you want to run {ANSI_YELLOW}{}{ANSI_RESET} automatically?",
@@ -306,14 +573,40 @@ pub fn run_cache_update() -> Result<()> {
     }
 }
 
-pub fn require_cache() -> Result<()> {
+pub fn require_cache(kind: CacheKind) -> Result<()> {
     let cache_path = Path::new(NAPM_CACHE_FILE);
 
     if cache_path.exists() {
         return Ok(());
     }
 
-    run_cache_update()
+    run_cache_update("System needs to be updated", kind)
+}
+
+pub fn require_existing_cache() -> Result<()> {
+    if Path::new(NAPM_CACHE_FILE).exists() {
+        Ok(())
+    } else {
+        Err(Error::CacheMissing)
+    }
+}
+
+// unlike run_upgrade, keeps the databases and leaves the confirmation to the caller
+pub fn run_system_upgrade() -> Result<()> {
+    let (mut cmd, cmd_display) = napm_as_root_cmd(napm_subcommand_args(&["upgrade"]))?;
+
+    log_info!("{} {}", if is_root() { "#" } else { "$" }, cmd_display);
+
+    match cmd.spawn()?.wait() {
+        Ok(status) => {
+            if status.success() {
+                Ok(())
+            } else {
+                Err(Error::System)
+            }
+        }
+        Err(err) => Err(Error::InternalIO(err)),
+    }
 }
 
 pub fn run_upgrade(sync_path: &PathBuf) -> Result<()> {
@@ -332,8 +625,8 @@ pub fn run_upgrade(sync_path: &PathBuf) -> Result<()> {
         as_root_cmd("rm", args)?
     };
 
-    let (mut cmd_ud, cmd_ud_display) = napm_as_root_cmd(vec!["update".to_string()])?;
-    let (mut cmd_ug, cmd_ug_display) = napm_as_root_cmd(vec!["upgrade".to_string()])?;
+    let (mut cmd_ud, cmd_ud_display) = napm_as_root_cmd(napm_subcommand_args(&["sync"]))?;
+    let (mut cmd_ug, cmd_ug_display) = napm_as_root_cmd(napm_subcommand_args(&["upgrade"]))?;
 
     if is_root() {
         log_warn!("System needs to be updated and upgraded");
@@ -406,3 +699,138 @@ pub fn run_upgrade(sync_path: &PathBuf) -> Result<()> {
         Err(err) => Err(Error::InternalIO(err)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn human_size_promotes_after_rounding() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1024), "1.0 KiB");
+        assert_eq!(human_size(1048575), "1.0 MiB");
+        assert_eq!(human_size(1048576), "1.0 MiB");
+        assert_eq!(human_size(1024 * 1024 * 1024 - 1), "1.0 GiB");
+    }
+
+    #[test]
+    fn human_size_keeps_the_sign() {
+        assert_eq!(human_size(-1023), "-1023 B");
+        assert_eq!(human_size(-1048575), "-1.0 MiB");
+        assert_eq!(human_size(i64::MIN).chars().next(), Some('-'));
+    }
+
+    #[test]
+    fn parse_selection_accepts_numbers_and_ranges() {
+        assert_eq!(parse_selection("1 3,5-7", 10), Some(vec![1, 3, 5, 6, 7]));
+        assert_eq!(parse_selection("2-3, 1", 3), Some(vec![2, 3, 1]));
+        // repeated entries are kept once, in the order first given
+        assert_eq!(parse_selection("3 1-3", 3), Some(vec![3, 1, 2]));
+        assert_eq!(parse_selection("  ", 3), Some(vec![]));
+    }
+
+    #[test]
+    fn parse_selection_rejects_invalid_input() {
+        for input in ["0", "4", "3-2", "1-4", "a", "1-", "-1", "1.5"] {
+            assert_eq!(parse_selection(input, 3), None, "{input}");
+        }
+    }
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn systemd_run_keeps_the_working_directory() {
+        assert!(pe_program_args("systemd-run").contains(&"--same-dir"));
+        assert!(pe_program_args("sudo").is_empty());
+    }
+
+    #[test]
+    fn pe_command_args_per_program() {
+        let envs = [("NAPM_ROOT", "/mnt".to_string())];
+        let args = strings(&["install", "./foo.pkg.tar.zst"]);
+
+        assert_eq!(
+            pe_command_args("sudo", &envs, "napm", &args).unwrap(),
+            strings(&["NAPM_ROOT=/mnt", "napm", "install", "./foo.pkg.tar.zst"])
+        );
+
+        for program in ["run0", "systemd-run"] {
+            assert_eq!(
+                pe_command_args(program, &envs, "napm", &args).unwrap(),
+                strings(&[
+                    "--setenv=NAPM_ROOT=/mnt",
+                    "napm",
+                    "install",
+                    "./foo.pkg.tar.zst"
+                ])
+            );
+        }
+
+        assert_eq!(
+            pe_command_args("doas", &envs, "napm", &args).unwrap(),
+            strings(&[
+                "env",
+                "NAPM_ROOT=/mnt",
+                "napm",
+                "install",
+                "./foo.pkg.tar.zst"
+            ])
+        );
+    }
+
+    #[test]
+    fn pe_command_args_without_envs() {
+        let args = strings(&["upgrade"]);
+
+        for program in PE_TOOLS {
+            assert_eq!(
+                pe_command_args(program, &[], "napm", &args).unwrap(),
+                strings(&["napm", "upgrade"])
+            );
+        }
+    }
+
+    #[test]
+    fn pe_command_args_pass_arguments_verbatim() {
+        let envs = [("NAPM_ROOT", "/mnt/my root".to_string())];
+        let args = strings(&[
+            "install",
+            "./with space.pkg.tar.zst",
+            "it's",
+            "\"quoted\"",
+            "$(reboot)",
+            "`id`",
+        ]);
+
+        for program in ["doas", "pkexec"] {
+            let argv = pe_command_args(program, &envs, "napm", &args).unwrap();
+
+            assert_eq!(argv[..2], strings(&["env", "NAPM_ROOT=/mnt/my root"])[..]);
+            assert_eq!(argv[2], "napm");
+            assert_eq!(argv[3..], args[..]);
+        }
+    }
+
+    #[test]
+    fn subcommands_keep_no_repair() {
+        set_no_repair(true);
+        let args = napm_subcommand_args(&["sync", "--files"]);
+        set_no_repair(false);
+
+        assert_eq!(args[..2], ["sync", "--files"]);
+        assert!(args.contains(&"--no-repair".to_string()));
+    }
+
+    #[test]
+    fn unknown_pe_program_is_an_error() {
+        let envs = [("NAPM_ROOT", "/mnt".to_string())];
+
+        assert!(matches!(
+            pe_command_args("su", &envs, "napm", &strings(&["upgrade"])),
+            Err(Error::NoPETool)
+        ));
+    }
+}